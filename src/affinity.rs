@@ -0,0 +1,201 @@
+//! Module implementing `--cpus`, which pins the benchmark targets to a set of CPUs (Linux only).
+//!
+//! The benchmark targets are pinned to the given CPUs just before they're launched, while
+//! cargo-criterion itself (including the rayon pool used for the analysis) is restricted to the
+//! other CPUs, so that the analysis of one benchmark doesn't disturb the measurement of the next.
+//! For the most stable measurements, the CPUs should also be isolated from the rest of the system
+//! with the `isolcpus` and `nohz_full` kernel parameters; a warning is printed if they aren't.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeSet;
+use std::process::Command;
+
+/// A set of CPU numbers.
+pub type CpuSet = BTreeSet<usize>;
+
+/// Parse a CPU list in the format used by `taskset -c` and the kernel, eg. `2,3` or `0-3,8`.
+pub fn parse_cpu_list(list: &str) -> Result<CpuSet> {
+    let mut cpus = CpuSet::new();
+    for item in list
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .with_context(|| format!("Invalid CPU number {:?} in CPU list {:?}", cpu, list))
+        };
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(anyhow!(
+                        "Invalid CPU range {:?} in CPU list {:?}",
+                        item,
+                        list
+                    ));
+                }
+                cpus.extend(first..=last);
+            }
+            None => {
+                cpus.insert(parse(item)?);
+            }
+        }
+    }
+    if cpus.is_empty() {
+        return Err(anyhow!("The CPU list {:?} is empty", list));
+    }
+    Ok(cpus)
+}
+
+fn format_cpu_list(cpus: &CpuSet) -> String {
+    let cpus: Vec<String> = cpus.iter().map(ToString::to_string).collect();
+    cpus.join(",")
+}
+
+/// Restrict cargo-criterion's own threads, and the rayon pool used for the analysis, to the CPUs
+/// which the benchmarks won't be pinned to. This must be called before any other threads are
+/// started. Also warns if the benchmark CPUs aren't isolated.
+#[cfg(target_os = "linux")]
+pub fn reserve(bench_cpus: &CpuSet) -> Result<()> {
+    let available = linux::get_affinity().context("Unable to get the CPU affinity")?;
+    let unavailable: CpuSet = bench_cpus.difference(&available).copied().collect();
+    if !unavailable.is_empty() {
+        return Err(anyhow!(
+            "Can't run the benchmarks on CPUs {}; cargo-criterion is only allowed to use CPUs {}",
+            format_cpu_list(&unavailable),
+            format_cpu_list(&available)
+        ));
+    }
+
+    for (setting, file) in &[
+        ("isolcpus", "/sys/devices/system/cpu/isolated"),
+        ("nohz_full", "/sys/devices/system/cpu/nohz_full"),
+    ] {
+        let isolated = std::fs::read_to_string(file)
+            .ok()
+            .and_then(|list| parse_cpu_list(&list).ok())
+            .unwrap_or_default();
+        let missing: CpuSet = bench_cpus.difference(&isolated).copied().collect();
+        if !missing.is_empty() {
+            warn!(
+                "CPUs {} are not in {}; other processes and the kernel may disturb the benchmarks running on them.",
+                format_cpu_list(&missing),
+                setting
+            );
+        }
+    }
+
+    let analysis_cpus: CpuSet = available.difference(bench_cpus).copied().collect();
+    if analysis_cpus.is_empty() {
+        warn!(
+            "cargo-criterion has no CPUs left for its own analysis threads, so they will share CPUs {} with the benchmarks.",
+            format_cpu_list(bench_cpus)
+        );
+        return Ok(());
+    }
+    debug!(
+        "Restricting cargo-criterion to CPUs {}",
+        format_cpu_list(&analysis_cpus)
+    );
+    // Threads inherit the affinity of the thread that starts them, so this covers the threads
+    // started later (including the analysis pool) as well as the main thread.
+    linux::set_affinity(&analysis_cpus).context("Unable to set the CPU affinity")?;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(analysis_cpus.len())
+        .build_global()
+        .context("Unable to configure the analysis thread pool")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reserve(_bench_cpus: &CpuSet) -> Result<()> {
+    Err(anyhow!("--cpus is only supported on Linux"))
+}
+
+/// Pin the process launched by the given command to the given CPUs.
+#[cfg(target_os = "linux")]
+pub fn pin_command(command: &mut Command, cpus: &CpuSet) {
+    use std::os::unix::process::CommandExt;
+
+    // The CPU set is built before forking, since only async-signal-safe functions may be called
+    // between fork and exec.
+    let set = linux::cpu_set(cpus);
+    unsafe {
+        command.pre_exec(move || linux::set_affinity_raw(&set));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_command(_command: &mut Command, _cpus: &CpuSet) {}
+
+/// Pin the calling thread to the given CPUs.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &CpuSet) -> Result<()> {
+    linux::set_affinity(cpus).context("Unable to set the CPU affinity")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &CpuSet) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::CpuSet;
+    use std::io;
+
+    pub fn cpu_set(cpus: &CpuSet) -> libc::cpu_set_t {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            for &cpu in cpus {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            set
+        }
+    }
+
+    /// Set the affinity of the calling thread.
+    pub fn set_affinity_raw(set: &libc::cpu_set_t) -> io::Result<()> {
+        let result =
+            unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn set_affinity(cpus: &CpuSet) -> io::Result<()> {
+        set_affinity_raw(&cpu_set(cpus))
+    }
+
+    /// Returns the CPUs which the calling thread may run on.
+    pub fn get_affinity() -> io::Result<CpuSet> {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((0..libc::CPU_SETSIZE as usize)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        let cpus = |list: &[usize]| list.iter().copied().collect::<CpuSet>();
+        assert_eq!(parse_cpu_list("2,3").unwrap(), cpus(&[2, 3]));
+        assert_eq!(parse_cpu_list("0-2, 8\n").unwrap(), cpus(&[0, 1, 2, 8]));
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+}
@@ -21,6 +21,19 @@ pub struct BenchmarkConfig {
     pub _sample_size: usize,
     pub significance_level: f64,
     pub _warm_up_time: Duration,
+    pub test: ComparisonTest,
+}
+
+/// The hypothesis test used to decide whether a benchmark has changed. In both cases the
+/// distribution of the test statistic is estimated by bootstrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonTest {
+    /// Welch's t-test, which compares the means of the samples. This is what the benchmark runs
+    /// use.
+    TTest,
+    /// The Mann-Whitney U test, which compares the ranks of the samples, so it is less sensitive
+    /// to outliers.
+    MannWhitney,
 }
 
 pub struct MeasuredValues<'a> {
@@ -68,6 +81,7 @@ pub(crate) fn analysis<'a>(
             base_sample_times: old_sample.sample_values.to_vec(),
             base_avg_times,
             base_estimates: old_estimates.clone(),
+            environment_changes: vec![],
         })
     } else {
         None
@@ -190,22 +204,26 @@ pub(crate) fn compare(
     )
 }
 
-// Performs a two sample t-test
+// Performs a two sample t-test (or the test chosen in the config)
 fn t_test(
     avg_times: &Sample<f64>,
     base_avg_times: &Sample<f64>,
     config: &BenchmarkConfig,
 ) -> (f64, Distribution<f64>) {
     let nresamples = config.nresamples;
+    let statistic: fn(&Sample<f64>, &Sample<f64>) -> f64 = match config.test {
+        ComparisonTest::TTest => |a, b| a.t(b),
+        ComparisonTest::MannWhitney => mann_whitney_z,
+    };
 
-    let t_statistic = avg_times.t(base_avg_times);
+    let t_statistic = statistic(avg_times, base_avg_times);
     let t_distribution = elapsed!(
         "Bootstrapping the T distribution",
         crate::stats::univariate::mixed::bootstrap(
             avg_times,
             base_avg_times,
             nresamples,
-            |a, b| (a.t(b),)
+            |a, b| (statistic(a, b),)
         )
     )
     .0;
@@ -224,6 +242,35 @@ fn t_test(
     (t_statistic, t_distribution)
 }
 
+// Returns the Mann-Whitney U statistic of the two samples, standardized so that it is centered on
+// zero when they come from the same distribution. Tied values are given their average rank.
+fn mann_whitney_z(a: &Sample<f64>, b: &Sample<f64>) -> f64 {
+    let mut values: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&x| (x, true))
+        .chain(b.iter().map(|&x| (x, false)))
+        .collect();
+    values.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut rank_sum_a = 0.0;
+    let mut start = 0;
+    while start < values.len() {
+        let end = start
+            + values[start..]
+                .iter()
+                .take_while(|(x, _)| *x == values[start].0)
+                .count();
+        // Ranks start at 1, so the average rank of positions start..end is this:
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum_a += rank * values[start..end].iter().filter(|(_, in_a)| *in_a).count() as f64;
+        start = end;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let u = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    (u - n_a * n_b / 2.0) / (n_a * n_b * (n_a + n_b + 1.0) / 12.0).sqrt()
+}
+
 // Estimates the relative change in the statistics of the population
 fn difference_estimates(
     avg_times: &Sample<f64>,
@@ -257,3 +304,20 @@ fn difference_estimates(
 
     (estimates, distributions)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mann_whitney_z() {
+        let a = [1.0, 2.0, 3.0, 4.0];
+        let b = [5.0, 6.0, 7.0, 8.0];
+        // Every value of a is smaller, so U = 0. Its mean is 8 and its variance is 12.
+        let z = mann_whitney_z(Sample::new(&a), Sample::new(&b));
+        assert!((z - (-8.0 / 12f64.sqrt())).abs() < 1e-9);
+        assert!((mann_whitney_z(Sample::new(&b), Sample::new(&a)) + z).abs() < 1e-9);
+        // Ties get the average rank, so identical samples are centered on zero.
+        assert_eq!(0.0, mann_whitney_z(Sample::new(&a), Sample::new(&a)));
+    }
+}
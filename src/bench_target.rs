@@ -1,17 +1,36 @@
+use crate::config::SelfConfig;
 use crate::connection::{AxisScale, Connection, IncomingMessage, PlotConfiguration};
+use crate::environment::Environment;
 use crate::model::Model;
 use crate::report::{BenchmarkId, Report, ReportContext};
+use crate::rusage::{self, ResourceUsage};
+use crate::timing::PhaseTimer;
+use crate::watchdog::Watchdog;
 use anyhow::{anyhow, Context, Result};
 use std::ffi::OsString;
-use std::net::TcpListener;
-use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+
+/// Events reported by the threads which wait on a running benchmark target.
+enum TargetEvent {
+    /// The target connected to our socket.
+    Connected(io::Result<(TcpStream, SocketAddr)>),
+    /// The target process exited, using the given resources.
+    Exited(io::Result<(ExitStatus, Option<ResourceUsage>)>),
+}
 
 /// Structure representing a compiled benchmark executable.
 #[derive(Debug)]
 pub struct BenchTarget {
     pub name: String,
+    /// The name of the package that the target belongs to.
+    pub package: String,
     pub executable: PathBuf,
+    /// The command to launch the executable with (a program and its arguments), if any.
+    pub runner: Option<Vec<String>>,
 }
 impl BenchTarget {
     /// Launches this benchmark target with the given additional arguments.
@@ -27,27 +46,24 @@ impl BenchTarget {
     /// will block until the benchmark target terminates.
     pub fn execute(
         &self,
-        criterion_home: &Path,
+        self_config: &SelfConfig,
         additional_args: &[OsString],
         library_paths: &[PathBuf],
         report: &dyn Report,
         model: &mut Model,
-        redirect_stdout: bool,
     ) -> Result<()> {
+        let criterion_home = &self_config.criterion_home;
+        let redirect_stdout = self_config.message_format.is_some();
+
         let listener = TcpListener::bind("localhost:0")
             .context("Unable to open socket to connect to Criterion.rs")?;
-        // listener has to be non-blocking while we wait for connections.
-        // Otherwise we'll wait forever if no-one connects to us
-        listener
-            .set_nonblocking(true)
-            .context("Unable to set socket to nonblocking")?;
 
         let addr = listener
             .local_addr()
             .context("Unable to get local address of socket")?;
         let port = addr.port();
 
-        let mut command = Command::new(&self.executable);
+        let mut command = self.command();
         command
             .arg("--bench")
             .args(additional_args)
@@ -64,6 +80,9 @@ impl BenchTarget {
                 Stdio::inherit()
             })
             .stderr(Stdio::inherit());
+        if let Some(cpus) = &self_config.cpus {
+            crate::affinity::pin_command(&mut command, cpus);
+        }
 
         debug!("Running '{:?}'", command);
 
@@ -75,49 +94,103 @@ impl BenchTarget {
             let mut stdout = child.stdout.take().unwrap();
             std::thread::spawn(move || std::io::copy(&mut stdout, &mut std::io::stderr()));
         }
+        let watchdog = Watchdog::start(child.id(), &self_config.timeouts);
 
-        loop {
-            match listener.accept() {
-                Ok((socket, _)) => {
-                    socket
-                        .set_nonblocking(false)
-                        .context("Unable to set socket to blocking")?;
-                    let conn = Connection::new(socket).with_context(|| {
-                        format!("Unable to open connection to bench target {}", self.name)
-                    })?;
-                    return self.communicate(&mut child, conn, report, criterion_home, model);
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // No connection yet, try again in a bit.
-                }
-                Err(e) => {
-                    return Err(e).context("Unable to accept connection to socket");
-                }
-            };
+        // Rather than polling, we block until either the target connects to the socket or it
+        // exits (eg. because it isn't a Criterion.rs benchmark), whichever happens first. Each of
+        // those is waited for on its own thread, which reports back through the channel.
+        let (sender, events) = mpsc::channel();
+        let accept_sender = sender.clone();
+        std::thread::spawn(move || {
+            let _ = accept_sender.send(TargetEvent::Connected(listener.accept()));
+        });
+        let stopper = watchdog.stopper();
+        std::thread::spawn(move || {
+            let exited = rusage::wait(child, || stopper.stop());
+            let _ = sender.send(TargetEvent::Exited(exited));
+        });
 
-            match child.try_wait() {
-                Err(e) => {
-                    return Err(e).context(format!(
+        match events
+            .recv()
+            .expect("Benchmark target threads exited unexpectedly")
+        {
+            TargetEvent::Connected(Ok((socket, _))) => {
+                watchdog.watch_connection(&socket);
+                let conn = Connection::new(socket).with_context(|| {
+                    format!("Unable to open connection to bench target {}", self.name)
+                })?;
+                self.communicate(&events, &watchdog, conn, report, self_config, model)
+            }
+            TargetEvent::Connected(Err(e)) => {
+                Err(e).context("Unable to accept connection to socket")
+            }
+            TargetEvent::Exited(exit_status) => {
+                // Nobody is going to connect now, so connect to the socket ourselves to wake up
+                // the thread waiting for a connection.
+                let _ = TcpStream::connect(addr);
+                let (exit_status, usage) = exit_status.with_context(|| {
+                    format!(
                         "Failed to wait for non-Criterion.rs benchmark target {}",
                         self.name
-                    ));
+                    )
+                })?;
+                self.exited(usage, report, model);
+                if let Some(e) = self.killed(&watchdog) {
+                    Err(e)
+                } else if exit_status.success() {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "Non-Criterion.rs benchmark target {} exited with {}",
+                        self.name,
+                        exit_status
+                    ))
                 }
-                Ok(Some(exit_status)) => {
-                    if exit_status.success() {
-                        return Ok(());
-                    } else {
-                        return Err(anyhow!(
-                            "Non-Criterion.rs benchmark target {} exited with {}",
-                            self.name,
-                            exit_status
-                        ));
-                    }
-                }
-                Ok(None) => (), // Child still running, keep trying.
-            };
+            }
+        }
+    }
+
+    /// Runs this benchmark target in list mode and returns the IDs of the benchmarks that it
+    /// contains. Note that benchmark targets which don't understand the `--list` argument may
+    /// simply run their benchmarks instead, and will generally return an empty list.
+    pub fn list_benchmarks(&self, library_paths: &[PathBuf]) -> Result<Vec<String>> {
+        let mut command = self.command();
+        command
+            .arg("--bench")
+            .arg("--list")
+            .env(dylib_path_envvar(), dylib_search_path(library_paths)?)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit());
+
+        debug!("Running '{:?}'", command);
+
+        let output = command
+            .output()
+            .with_context(|| format!("Unable to launch bench target {}", self.name))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Benchmark target {} exited with {} while listing its benchmarks",
+                self.name,
+                output.status
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.strip_suffix(": bench"))
+            .map(ToOwned::to_owned)
+            .collect())
+    }
 
-            // Wait a bit then poll again.
-            std::thread::yield_now();
+    /// Returns a command which launches the executable, using the runner if there is one.
+    fn command(&self) -> Command {
+        match self.runner.as_deref() {
+            Some([program, args @ ..]) => {
+                let mut command = Command::new(program);
+                command.args(args).arg(&self.executable);
+                command
+            }
+            _ => Command::new(&self.executable),
         }
     }
 
@@ -126,35 +199,44 @@ impl BenchTarget {
     /// model about the benchmarks. This function returns when the benchmark target terminates.
     fn communicate(
         &self,
-        child: &mut Child,
+        events: &Receiver<TargetEvent>,
+        watchdog: &Watchdog,
         mut conn: Connection,
         report: &dyn Report,
-        criterion_home: &std::path::Path,
+        self_config: &SelfConfig,
         model: &mut Model,
     ) -> Result<()> {
+        let environment = model
+            .environment()
+            .with_criterion_rs_version(conn.criterion_rs_version());
         let mut context = ReportContext {
-            output_directory: criterion_home.join("reports"),
+            output_directory: self_config.criterion_home.join("reports"),
             plot_config: PlotConfiguration {
                 summary_scale: AxisScale::Linear,
             },
         };
         let mut any_from_group_executed = false;
         loop {
-            let message_opt = conn.recv().with_context(|| {
-                format!(
-                    "Failed to receive message from Criterion.rs benchmark target {}",
-                    self.name
-                )
-            })?;
+            let message_opt = match conn.recv() {
+                Ok(message_opt) => message_opt,
+                Err(e) => {
+                    return Err(self.killed(watchdog).unwrap_or_else(|| {
+                        e.context(format!(
+                            "Failed to receive message from Criterion.rs benchmark target {}",
+                            self.name
+                        ))
+                    }))
+                }
+            };
 
             if let Some(message) = message_opt {
                 match message {
                     IncomingMessage::BeginningBenchmarkGroup { group } => {
                         any_from_group_executed = false;
-                        model.check_benchmark_group(&self.name, &group);
+                        model.check_benchmark_group(self, &group);
                     }
                     IncomingMessage::FinishedBenchmarkGroup { group } => {
-                        let benchmark_group = model.add_benchmark_group(&self.name, &group);
+                        let benchmark_group = model.add_benchmark_group(self, &group);
                         {
                             let formatter = crate::value_formatter::ValueFormatter::new(&mut conn);
                             report.summarize(&context, &group, benchmark_group, &formatter);
@@ -166,26 +248,54 @@ impl BenchTarget {
                     IncomingMessage::BeginningBenchmark { id } => {
                         any_from_group_executed = true;
                         let mut id = id.into();
-                        model.add_benchmark_id(&self.name, &mut id);
-                        self.run_benchmark(&mut conn, report, model, id, &mut context)?;
+                        model.add_benchmark_id(self, &mut id);
+                        watchdog.start_benchmark();
+                        self.run_benchmark(
+                            &mut conn,
+                            watchdog,
+                            report,
+                            model,
+                            id,
+                            &mut context,
+                            &environment,
+                            self_config,
+                        )?;
+                        watchdog.finish_benchmark();
                     }
                     IncomingMessage::SkippingBenchmark { id } => {
                         let mut id = id.into();
-                        model.add_benchmark_id(&self.name, &mut id);
+                        model.add_benchmark_id(self, &mut id);
                     }
                     other => panic!("Unexpected message {:?}", other),
                 }
+            } else {
+                // The target closed the connection, so it should be exiting.
+                return self.wait_for_exit(events, watchdog, report, model);
             }
+        }
+    }
 
-            match child.try_wait() {
-                Err(e) => {
+    /// Wait for the Criterion.rs benchmark target to exit and check its exit status.
+    fn wait_for_exit(
+        &self,
+        events: &Receiver<TargetEvent>,
+        watchdog: &Watchdog,
+        report: &dyn Report,
+        model: &mut Model,
+    ) -> Result<()> {
+        loop {
+            match events.recv() {
+                Ok(TargetEvent::Exited(Err(e))) => {
                     return Err(e).context(format!(
-                        "Failed to poll Criterion.rs child process {}",
+                        "Failed to wait for Criterion.rs child process {}",
                         self.name
                     ));
                 }
-                Ok(Some(exit_status)) => {
-                    if exit_status.success() {
+                Ok(TargetEvent::Exited(Ok((exit_status, usage)))) => {
+                    self.exited(usage, report, model);
+                    if let Some(e) = self.killed(watchdog) {
+                        return Err(e);
+                    } else if exit_status.success() {
                         return Ok(());
                     } else {
                         return Err(anyhow!(
@@ -195,24 +305,71 @@ impl BenchTarget {
                         ));
                     }
                 }
-                Ok(None) => continue,
-            };
+                Ok(TargetEvent::Connected(_)) => continue,
+                Err(_) => {
+                    return Err(anyhow!(
+                        "Lost track of Criterion.rs child process {}",
+                        self.name
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Called when the target process has exited, to report the resources that it used.
+    fn exited(&self, usage: Option<ResourceUsage>, report: &dyn Report, model: &mut Model) {
+        report.target_complete(self, usage.as_ref());
+        if let Some(usage) = usage {
+            model.resources_used(self, &usage);
+        }
+    }
+
+    /// If the watchdog killed the target, returns an error explaining why.
+    fn killed(&self, watchdog: &Watchdog) -> Option<anyhow::Error> {
+        if watchdog.interrupted() {
+            Some(anyhow!("Benchmark target {} was interrupted", self.name))
+        } else {
+            (watchdog.expired())
+                .map(|timeout| anyhow!("Killed benchmark target {} because {}", self.name, timeout))
         }
     }
 
     /// Helper function for communicating with the benchmark target about a single benchmark.
+    #[allow(clippy::too_many_arguments)]
     fn run_benchmark(
         &self,
         conn: &mut Connection,
+        watchdog: &Watchdog,
         report: &dyn Report,
         model: &mut Model,
         id: BenchmarkId,
         context: &mut ReportContext,
+        environment: &Environment,
+        self_config: &SelfConfig,
     ) -> Result<()> {
         report.benchmark_start(&id, context);
+        let mut timer = PhaseTimer::start();
 
         loop {
-            let message = conn.recv().with_context(|| {
+            let message = conn.recv();
+            if let (Err(_) | Ok(None), true) = (&message, watchdog.interrupted()) {
+                return Err(anyhow!(
+                    "Benchmark target {} was interrupted during benchmark {}",
+                    self.name,
+                    id.as_title()
+                ));
+            }
+            if let (Err(_) | Ok(None), Some(timeout)) = (&message, watchdog.expired()) {
+                report.benchmark_timed_out(&id, context, timeout);
+                model.benchmark_timed_out(&id);
+                return Err(anyhow!(
+                    "Killed benchmark target {} during benchmark {} because {}",
+                    self.name,
+                    id.as_title(),
+                    timeout
+                ));
+            }
+            let message = message.with_context(|| {
                 format!(
                     "Failed to receive message from Criterion.rs benchmark {}",
                     self.name
@@ -231,6 +388,7 @@ impl BenchTarget {
                     estimate_ns,
                     iter_count,
                 } => {
+                    timer.measurement_started();
                     report.measurement_start(&id, context, sample_count, estimate_ns, iter_count);
                 }
                 IncomingMessage::MeasurementComplete {
@@ -240,6 +398,8 @@ impl BenchTarget {
                     sampling_method,
                     benchmark_config,
                 } => {
+                    watchdog.finish_benchmark();
+                    timer.measurement_complete();
                     context.plot_config = plot_config;
                     report.analysis(&id, context);
 
@@ -261,10 +421,20 @@ impl BenchTarget {
 
                     let saved_stats = model.get_last_sample(&id).cloned();
 
-                    let benchmark_config: crate::analysis::BenchmarkConfig =
+                    let mut benchmark_config: crate::analysis::BenchmarkConfig =
                         benchmark_config.into();
+                    if let (true, Some(jitter)) = (
+                        self_config.stability.scale_noise_threshold,
+                        environment.jitter,
+                    ) {
+                        // The jitter is the relative spread of a single sample of the probe, but a
+                        // change is judged on the mean of all of the samples, whose spread shrinks
+                        // with the square root of the sample count. This is a heuristic: it assumes
+                        // that the jitter affects each sample independently, like it does the probe.
+                        benchmark_config.noise_threshold += jitter / (times.len() as f64).sqrt();
+                    }
 
-                    let measured_data = crate::analysis::analysis(
+                    let mut measured_data = crate::analysis::analysis(
                         &benchmark_config,
                         id.throughput.clone(),
                         crate::analysis::MeasuredValues {
@@ -283,17 +453,33 @@ impl BenchTarget {
                         sampling_method,
                     );
 
-                    if let Err(e) = model.benchmark_complete(&id, &measured_data) {
-                        error!(
-                            "Failed to save results for target {} benchmark {}: {}",
-                            self.name,
-                            id.as_title(),
-                            e
-                        );
+                    // Warn the user if the previous measurements were taken on a different
+                    // machine or with a different toolchain.
+                    if let (Some(comparison), Some(base_environment)) = (
+                        measured_data.comparison.as_mut(),
+                        saved_stats.as_ref().and_then(|s| s.environment.as_ref()),
+                    ) {
+                        comparison.environment_changes =
+                            environment.differences_from(base_environment);
                     }
 
                     {
                         let formatter = crate::value_formatter::ValueFormatter::new(conn);
+                        // Save the unit of the values, so that they can be shown without the
+                        // benchmark target later on.
+                        let unit = formatter.raw_unit();
+                        if let Err(e) =
+                            model.benchmark_complete(self, &id, &measured_data, environment, unit)
+                        {
+                            error!(
+                                "Failed to save results for target {} benchmark {}: {}",
+                                self.name,
+                                id.as_title(),
+                                e
+                            );
+                        }
+                        timer.analysis_complete();
+
                         report.measurement_complete(&id, context, &measured_data, &formatter);
 
                         match model.load_history(&id) {
@@ -301,6 +487,7 @@ impl BenchTarget {
                             Err(e) => error!("Failed to load historical data: {:?}", e),
                         }
                     }
+                    model.benchmark_timed(self, &id, timer.finish());
                     return Ok(());
                 }
                 other => panic!("Unexpected message {:?}", other),
@@ -0,0 +1,329 @@
+//! Module implementing results bundles, which are single files containing stored measurements
+//! that can be shared with other developers (`export --bundle`) and loaded into their data
+//! directory (`import`).
+//!
+//! A bundle contains the benchmark records and measurement files exactly as they are stored on
+//! disk, each with its own schema version, so importing a bundle from an older version of
+//! cargo-criterion upgrades the records in the same way as loading them from disk would. Paths
+//! in the bundle are relative to the benchmark's directory.
+
+use crate::config::{ExportOptions, FullConfig, ImportOptions};
+use crate::export::selected_timelines;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::merge::{store_measurement, update_latest_record};
+use crate::model::{
+    is_measurement_file, save_atomically, stored_benchmarks, timeline_directory, BenchmarkRecord,
+};
+use crate::report::{make_filename_safe, BenchmarkId};
+use crate::schema::{self, Migration, Versioned};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde_cbor::Value;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+// Every bundle starts with this, so that we can give a clear error for other files.
+const BUNDLE_FORMAT: &str = "cargo-criterion results bundle";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Bundle {
+    format: String,
+    // The version of cargo-criterion which created the bundle.
+    cargo_criterion_version: String,
+    created: DateTime<Utc>,
+    timelines: Vec<BundleTimeline>,
+}
+impl Versioned for Bundle {
+    const NAME: &'static str = "results bundle";
+    const MIGRATIONS: &'static [Migration] = &[schema::unversioned];
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleTimeline {
+    name: String,
+    benchmarks: Vec<BundleBenchmark>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleBenchmark {
+    // The benchmark record, as stored in benchmark.cbor.
+    record: Value,
+    measurements: Vec<BundleMeasurement>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleMeasurement {
+    file_name: PathBuf,
+    // The measurement, as stored in its measurement file.
+    contents: Value,
+}
+
+/// Write the stored measurements to a results bundle.
+pub fn export(configuration: &FullConfig, options: &ExportOptions, path: &Path) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let criterion_home = &self_config.criterion_home;
+    let _lock =
+        DirectoryLock::acquire(criterion_home, LockKind::Shared, self_config.wait_for_lock)?;
+
+    let mut timelines = vec![];
+    let mut benchmark_count = 0;
+    for (name, data_directory) in selected_timelines(criterion_home, options)? {
+        let mut benchmarks = vec![];
+        for (dir, record) in stored_benchmarks(&data_directory)? {
+            let id = BenchmarkId::from(record.id.clone());
+            if let Some(filter) = &options.filter {
+                if !filter.is_match(id.as_full_id()) {
+                    continue;
+                }
+            }
+            benchmarks.push(export_benchmark(&dir, &record)?);
+        }
+        benchmark_count += benchmarks.len();
+        timelines.push(BundleTimeline { name, benchmarks });
+    }
+
+    let bundle = Bundle {
+        format: BUNDLE_FORMAT.to_owned(),
+        cargo_criterion_version: env!("CARGO_PKG_VERSION").to_owned(),
+        created: Utc::now(),
+        timelines,
+    };
+    save_atomically(path, |writer| schema::to_writer(writer, &bundle))
+        .with_context(|| format!("Failed to write bundle {:?}", path))?;
+
+    eprintln!(
+        "Exported {} benchmarks to {}.",
+        benchmark_count,
+        path.display()
+    );
+    Ok(())
+}
+
+fn export_benchmark(dir: &Path, record: &BenchmarkRecord) -> Result<BundleBenchmark> {
+    let mut measurements = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !is_measurement_file(&entry.file_name()) {
+            continue;
+        }
+        // Measurements are kept as raw CBOR values rather than being loaded, so that they keep
+        // their original schema version.
+        let contents = File::open(entry.path())
+            .map_err(anyhow::Error::from)
+            .and_then(|file| serde_cbor::from_reader(file).map_err(anyhow::Error::from));
+        match contents {
+            Ok(contents) => measurements.push(BundleMeasurement {
+                file_name: PathBuf::from(entry.file_name()),
+                contents,
+            }),
+            Err(e) => warn!(
+                "Skipping unreadable measurement {:?}: {:?}",
+                entry.path(),
+                e
+            ),
+        }
+    }
+    Ok(BundleBenchmark {
+        record: schema::to_value(record)?,
+        measurements,
+    })
+}
+
+/// Entry point for the `import` subcommand.
+pub fn import(configuration: &FullConfig, options: &ImportOptions) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let criterion_home = &self_config.criterion_home;
+
+    let bundle = load_bundle(&options.bundle)?;
+    if options.timeline.is_some() && bundle.timelines.len() > 1 {
+        return Err(anyhow!(
+            "The bundle contains {} timelines, so they can't all be imported into one. Export \
+             them separately with `cargo criterion export --timeline`.",
+            bundle.timelines.len()
+        ));
+    }
+
+    let _lock = DirectoryLock::acquire(
+        criterion_home,
+        LockKind::Exclusive,
+        self_config.wait_for_lock,
+    )?;
+
+    for timeline in bundle.timelines {
+        let name = options.timeline.as_ref().unwrap_or(&timeline.name);
+        let data_directory = timeline_directory(criterion_home, name)?;
+
+        let mut imported = 0;
+        for benchmark in timeline.benchmarks {
+            if import_benchmark(&data_directory, benchmark)? {
+                imported += 1;
+            }
+        }
+        eprintln!(
+            "Imported {} benchmarks into the '{}' timeline.",
+            imported, name
+        );
+    }
+    Ok(())
+}
+
+/// Store the measurements of one benchmark from a bundle in the given timeline directory. Returns
+/// false if the benchmark was skipped.
+fn import_benchmark(data_directory: &Path, benchmark: BundleBenchmark) -> Result<bool> {
+    let record: BenchmarkRecord = schema::from_value(benchmark.record)?;
+    let id = BenchmarkId::from(record.id.clone());
+    let dir = benchmark_directory(data_directory, &id)?;
+
+    let mut latest = None;
+    for measurement in benchmark.measurements {
+        let contents = serde_cbor::to_vec(&measurement.contents)?;
+        let file_name = safe_file_name(&measurement.file_name)?;
+        let stored_name = store_measurement(&contents, file_name, &dir)?;
+        if measurement.file_name == record.latest_record {
+            latest = Some(stored_name);
+        }
+    }
+    match latest {
+        Some(latest) => {
+            update_latest_record(&dir, &record.id, latest)
+                .with_context(|| format!("Failed to import {}", id.as_title()))?;
+            Ok(true)
+        }
+        None => {
+            warn!(
+                "The bundle doesn't contain the latest measurement of {}; skipping it",
+                id.as_title()
+            );
+            Ok(false)
+        }
+    }
+}
+
+fn load_bundle(path: &Path) -> Result<Bundle> {
+    let file = File::open(path).with_context(|| format!("Failed to open bundle {:?}", path))?;
+    let value: Value = serde_cbor::from_reader(file)
+        .with_context(|| format!("{:?} is not a cargo-criterion results bundle", path))?;
+
+    let format_key = Value::Text("format".to_owned());
+    match &value {
+        Value::Map(map) if map.get(&format_key) == Some(&Value::Text(BUNDLE_FORMAT.to_owned())) => {
+        }
+        _ => {
+            return Err(anyhow!(
+                "{:?} is not a cargo-criterion results bundle",
+                path
+            ))
+        }
+    }
+    schema::from_value(value).with_context(|| format!("Failed to read bundle {:?}", path))
+}
+
+/// Create the directory of the given benchmark in the given timeline directory. Bundles may come
+/// from other people, so make sure that the benchmark ID can't point outside of the timeline.
+fn benchmark_directory(data_directory: &Path, id: &BenchmarkId) -> Result<PathBuf> {
+    let parts = [
+        id.namespace(),
+        Some(id.group_id.as_str()),
+        id.function_id.as_deref(),
+        id.value_str.as_deref(),
+    ];
+    if parts
+        .iter()
+        .flatten()
+        .map(|part| make_filename_safe(part))
+        .any(|part| part.is_empty() || part == "." || part == "..")
+    {
+        return Err(anyhow!(
+            "The bundle contains an invalid benchmark ID {:?}",
+            id.as_full_id()
+        ));
+    }
+
+    fs::create_dir_all(data_directory)
+        .with_context(|| format!("Failed to create directory {:?}", data_directory))?;
+    let dir = data_directory.join(id.as_directory_name());
+    // The timeline directory might contain symlinks, so check where the part of the directory
+    // which already exists really is before creating anything. The rest is created inside it.
+    let existing = (dir.ancestors())
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(data_directory);
+    if !existing
+        .canonicalize()?
+        .starts_with(data_directory.canonicalize()?)
+    {
+        return Err(anyhow!(
+            "The directory of benchmark {:?} is outside of {:?}",
+            id.as_full_id(),
+            data_directory
+        ));
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+    Ok(dir)
+}
+
+/// Bundles may come from other people, so make sure that file names can't point outside of the
+/// benchmark's directory.
+fn safe_file_name(file_name: &Path) -> Result<&OsStr> {
+    match file_name.file_name() {
+        Some(name) if Path::new(name) == file_name && is_measurement_file(name) => Ok(name),
+        _ => Err(anyhow!(
+            "The bundle contains an invalid file name {:?}",
+            file_name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::SavedBenchmarkId;
+
+    fn bundle_benchmark(id: BenchmarkId) -> BundleBenchmark {
+        let record = BenchmarkRecord {
+            id: SavedBenchmarkId::from(id),
+            latest_record: PathBuf::from("measurement_1.cbor"),
+        };
+        BundleBenchmark {
+            record: schema::to_value(&record).unwrap(),
+            measurements: vec![BundleMeasurement {
+                file_name: PathBuf::from("measurement_1.cbor"),
+                contents: Value::Null,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_paths_outside_the_timeline() {
+        let root = tempfile::tempdir().unwrap();
+        let data_directory = path!(root.path(), "data", "main");
+
+        let ids = vec![
+            BenchmarkId::new("..".to_owned(), Some("escaped".to_owned()), None, None),
+            BenchmarkId::new("group".to_owned(), Some("..".to_owned()), None, None),
+            BenchmarkId::new("group".to_owned(), None, Some(".".to_owned()), None),
+            BenchmarkId::new("escaped".to_owned(), None, None, None).in_namespace(Some("..")),
+        ];
+        for id in ids {
+            assert!(import_benchmark(&data_directory, bundle_benchmark(id)).is_err());
+        }
+        assert!(!path!(root.path(), "data", "escaped").exists());
+        assert!(!data_directory.join("group").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_import_does_not_follow_symlinks_outside_the_timeline() {
+        let root = tempfile::tempdir().unwrap();
+        let data_directory = path!(root.path(), "data", "main");
+        let outside = root.path().join("outside");
+        fs::create_dir_all(&data_directory).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, data_directory.join("group")).unwrap();
+
+        let id = BenchmarkId::new("group".to_owned(), Some("escaped".to_owned()), None, None);
+        assert!(import_benchmark(&data_directory, bundle_benchmark(id)).is_err());
+        // Nothing was created outside of the timeline before the benchmark was rejected.
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+}
@@ -3,6 +3,7 @@
 //! emits.
 
 use crate::bench_target::BenchTarget;
+use crate::runner::Runners;
 use anyhow::{Context, Result};
 use std::path::PathBuf;
 use std::process::{Command, ExitStatus, Stdio};
@@ -46,6 +47,7 @@ struct Target {
 enum Message {
     #[serde(rename = "compiler-artifact")]
     CompilerArtifact {
+        package_id: String,
         target: Target,
         executable: Option<PathBuf>,
     },
@@ -72,7 +74,11 @@ pub struct CompiledBenchmarks {
 /// list out the benchmarks and their executables and parses that information. This compiles the
 /// benchmarks but doesn't run them. Returns information on the compiled benchmarks that we can use
 /// to run them directly.
-pub fn compile(debug_build: bool, cargo_args: &[std::ffi::OsString]) -> Result<CompiledBenchmarks> {
+pub fn compile(
+    debug_build: bool,
+    cargo_args: &[std::ffi::OsString],
+    runners: &Runners,
+) -> Result<CompiledBenchmarks> {
     let subcommand: &[&'static str] = if debug_build {
         &["test", "--benches"]
     } else {
@@ -101,20 +107,22 @@ pub fn compile(debug_build: bool, cargo_args: &[std::ffi::OsString]) -> Result<C
     for message in stream {
         let message = message.context("Failed to parse message from cargo")?;
         match message {
-            Message::CompilerArtifact { target, executable } => {
-                if target
-                    .kind
-                    .iter()
-                    // Benchmarks and tests have executables. Libraries might, if they expose tests.
-                    .any(|kind| kind == "bench" || kind == "test" || kind == "lib")
-                {
-                    if let Some(executable) = executable {
-                        targets.push(BenchTarget {
-                            name: target.name,
-                            executable,
-                        });
-                    }
-                }
+            Message::CompilerArtifact {
+                package_id,
+                target,
+                executable: Some(executable),
+            } if target
+                .kind
+                .iter()
+                // Benchmarks and tests have executables. Libraries might, if they expose tests.
+                .any(|kind| kind == "bench" || kind == "test" || kind == "lib") =>
+            {
+                targets.push(BenchTarget {
+                    package: package_name(&package_id),
+                    runner: runners.for_target(&target.name).map(ToOwned::to_owned),
+                    name: target.name,
+                    executable,
+                });
             }
             Message::BuildScriptExecuted { linked_paths } => {
                 for path in linked_paths {
@@ -132,7 +140,9 @@ pub fn compile(debug_build: bool, cargo_args: &[std::ffi::OsString]) -> Result<C
         }
     }
 
-    targets.sort_by(|target1, target2| (target1.name).cmp(&target2.name));
+    targets.sort_by(|target1, target2| {
+        (&target1.package, &target1.name).cmp(&(&target2.package, &target2.name))
+    });
 
     let exit_status = cargo
         .wait()
@@ -146,3 +156,46 @@ pub fn compile(debug_build: bool, cargo_args: &[std::ffi::OsString]) -> Result<C
         })
     }
 }
+
+/// Extract the package name from a Cargo package ID. Older versions of Cargo use the format
+/// `name version (source)`, newer versions use a package ID spec like `source#name@version`, or
+/// just `source#version` if the name matches the last component of the source path.
+fn package_name(package_id: &str) -> String {
+    match package_id.split_once('#') {
+        Some((source, fragment)) => match fragment.split_once('@') {
+            Some((name, _)) => name.to_owned(),
+            None => source
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(source)
+                .to_owned(),
+        },
+        None => package_id
+            .split_whitespace()
+            .next()
+            .unwrap_or(package_id)
+            .to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(
+            package_name("my-crate 0.1.0 (path+file:///home/me/my-crate)"),
+            "my-crate"
+        );
+        assert_eq!(
+            package_name("path+file:///home/me/workspace/my-crate#0.1.0"),
+            "my-crate"
+        );
+        assert_eq!(
+            package_name("path+file:///home/me/workspace/crates/foo#my-crate@0.1.0"),
+            "my-crate"
+        );
+    }
+}
@@ -1,10 +1,19 @@
+use crate::affinity::{parse_cpu_list, CpuSet};
+use crate::analysis::ComparisonTest;
+use crate::rename::{self, Renames};
+use crate::runner::{self, Runners};
+use crate::stability::StabilityOptions;
 use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use regex::Regex;
 use std::borrow::ToOwned;
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
 #[derive(Deserialize, Debug, Clone, Copy)]
 pub struct Color {
@@ -76,6 +85,76 @@ struct TomlConfig {
     /// The colors used for the charts. Users may wish to override this to accommodate
     /// colorblindness, or just to make things look prettier.
     pub colors: Colors,
+
+    /// Rules for deleting old measurements.
+    pub retention: RetentionPolicy,
+
+    /// Benchmarks which have been renamed, mapping the old ID to the new one.
+    pub renames: BTreeMap<String, String>,
+    /// Benchmarks stored with `--workspace` which have been renamed, by package.
+    pub package_renames: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Kill benchmark targets which run for longer than this many seconds.
+    pub target_timeout: Option<f64>,
+    /// Kill benchmark targets if a single benchmark runs for longer than this many seconds.
+    pub benchmark_timeout: Option<f64>,
+
+    /// Command to launch the benchmark targets with, eg. `["taskset", "-c", "3"]`.
+    pub runner: Option<Vec<String>>,
+    /// Commands to launch individual benchmark targets with, by target name.
+    pub target_runners: BTreeMap<String, Vec<String>>,
+
+    /// CPUs to pin the benchmark targets to, eg. "2,3".
+    pub cpus: Option<String>,
+
+    /// Refuse to run the benchmarks if the machine looks noisy.
+    pub strict_environment: bool,
+}
+
+/// Rules for deciding which old measurements to delete. By default, everything is kept.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Keep at most this many measurements for each benchmark.
+    pub keep_last: Option<usize>,
+    /// Delete measurements that are older than this many days.
+    pub max_age_days: Option<u64>,
+    /// Always keep measurements from tagged runs, regardless of the other settings.
+    pub keep_tagged: bool,
+}
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_last: None,
+            max_age_days: None,
+            keep_tagged: true,
+        }
+    }
+}
+impl RetentionPolicy {
+    /// Returns true if this policy might delete anything.
+    pub fn is_enabled(&self) -> bool {
+        self.keep_last.is_some() || self.max_age_days.is_some()
+    }
+
+    /// Returns the maximum age of the measurements, if any.
+    pub fn max_age(&self) -> Option<chrono::Duration> {
+        self.max_age_days
+            .map(|days| chrono::Duration::days(days as i64))
+    }
+
+    /// Check that the settings can be applied.
+    fn validate(&self) -> Result<()> {
+        // chrono can't represent durations of more than i64::MAX milliseconds.
+        let max_days = (i64::MAX / (24 * 60 * 60 * 1000)) as u64;
+        match self.max_age_days {
+            Some(days) if days > max_days => Err(anyhow::anyhow!(
+                "max_age_days in the [retention] table of criterion.toml must be at most {}",
+                max_days
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -165,6 +244,12 @@ pub struct SelfConfig {
     pub plotting_backend: PlottingBackend,
     /// Should we compile the benchmarks in debug mode (true) or release mode (false, default)
     pub debug_build: bool,
+    /// The cargo features that were requested for the benchmarks.
+    pub features: Vec<String>,
+    /// Were all of the cargo features enabled?
+    pub all_features: bool,
+    /// Were the default cargo features disabled?
+    pub no_default_features: bool,
     /// Should we print machine-readable output, and if so, in what format?
     pub message_format: Option<MessageFormat>,
     /// The colors to use for charts.
@@ -173,6 +258,175 @@ pub struct SelfConfig {
     pub history_id: Option<String>,
     // An optional description used to describe this run in the history reports.
     pub history_description: Option<String>,
+    /// The directory containing the package being benchmarked.
+    pub package_directory: PathBuf,
+    /// Rules for deleting old measurements.
+    pub retention: RetentionPolicy,
+    /// Should we wait if another cargo-criterion process is using the same CRITERION_HOME?
+    pub wait_for_lock: bool,
+    /// Benchmarks which have been renamed, mapping the old ID to the new one, by namespace.
+    pub renames: Renames,
+    /// The timeline to compare against, instead of the previous measurements.
+    pub baseline: Option<String>,
+    /// Compare against the latest measurements with these tags.
+    pub baseline_tags: BTreeMap<String, String>,
+    /// User-provided labels for this run, which are saved with the measurements.
+    pub tags: BTreeMap<String, String>,
+    /// Should stored data and reports be namespaced by package? (true when benchmarking a
+    /// workspace)
+    pub namespace_by_package: bool,
+    /// How long benchmark targets and benchmarks may run before they are killed.
+    pub timeouts: Timeouts,
+    /// The commands to launch the benchmark targets with.
+    pub runners: Runners,
+    /// The CPUs to pin the benchmark targets to, if any.
+    pub cpus: Option<CpuSet>,
+    /// Should we refuse to run if the machine looks noisy?
+    pub strict_environment: bool,
+    /// Settings for the stability probe.
+    pub stability: StabilityOptions,
+    /// Should we continue the last run, which was interrupted, instead of starting a new one?
+    pub resume: bool,
+}
+
+/// How long benchmark targets may run before they are killed. None means no limit.
+#[derive(Debug, Default, Clone)]
+pub struct Timeouts {
+    /// The limit on the wall-clock time of a whole benchmark target.
+    pub target: Option<Duration>,
+    /// The limit on the time between a benchmark starting and its measurements being received.
+    pub benchmark: Option<Duration>,
+}
+
+/// Options for the `gc` subcommand.
+#[derive(Debug)]
+pub struct GcOptions {
+    /// Only print what would be deleted.
+    pub dry_run: bool,
+    /// Only apply the retention policy; don't look for benchmarks that no longer exist.
+    pub measurements_only: bool,
+}
+
+/// Options for the `doctor` subcommand.
+#[derive(Debug)]
+pub struct DoctorOptions {
+    /// Move unreadable files out of the data directory.
+    pub quarantine: bool,
+}
+
+/// Options for the `rename` subcommand.
+#[derive(Debug)]
+pub struct RenameOptions {
+    /// Rename the benchmarks stored in this package's namespace, rather than those stored without
+    /// a namespace.
+    pub package: Option<String>,
+    /// The ID of the benchmark (or the prefix of the IDs of a group of benchmarks) to rename.
+    pub old: String,
+    /// The new ID.
+    pub new: String,
+}
+
+/// Options for the `merge` subcommand.
+#[derive(Debug)]
+pub struct MergeOptions {
+    /// The CRITERION_HOME directories to merge into ours.
+    pub directories: Vec<PathBuf>,
+}
+
+/// The file formats that the `export` subcommand can write.
+#[derive(Debug)]
+pub enum ExportFormat {
+    /// A results bundle, which can be loaded with the `import` subcommand.
+    Bundle(PathBuf),
+    /// One row per measurement, as comma-separated values.
+    Csv,
+    /// One JSON object per measurement, one per line.
+    JsonLines,
+}
+
+/// Options for the `export` subcommand.
+#[derive(Debug)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Write the table of measurements to this file instead of stdout. Not used for bundles.
+    pub output: Option<PathBuf>,
+    /// Also write the raw samples of every measurement to this file. Not used for bundles.
+    pub samples: Option<PathBuf>,
+    /// The timelines to export. If empty, all timelines are exported.
+    pub timelines: Vec<String>,
+    /// Only export benchmarks whose IDs match this regex.
+    pub filter: Option<Regex>,
+    /// Only export measurements taken at or after this time. Not used for bundles.
+    pub since: Option<DateTime<Utc>>,
+    /// Only export measurements taken at or before this time. Not used for bundles.
+    pub until: Option<DateTime<Utc>>,
+    /// Only export measurements with all of these tags. Not used for bundles.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Options for the `import` subcommand.
+#[derive(Debug)]
+pub struct ImportOptions {
+    /// The results bundle to import.
+    pub bundle: PathBuf,
+    /// Import the bundle into this timeline instead of the timelines it was exported from.
+    pub timeline: Option<String>,
+}
+
+/// Options for the `history` subcommand.
+#[derive(Debug)]
+pub struct HistoryOptions {
+    /// Show the history of the benchmarks whose IDs match this regex.
+    pub filter: Regex,
+    /// Only show this many of the most recent measurements of each benchmark.
+    pub limit: Option<usize>,
+    /// Print JSON instead of a table.
+    pub json: bool,
+    /// The timeline to show the history of.
+    pub timeline: String,
+    /// Only show measurements with all of these tags.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Which outliers to drop from the stored samples before reanalyzing them.
+#[derive(Debug, Clone, Copy)]
+pub enum OutlierPolicy {
+    /// Keep every sample. This is what the benchmark runs do.
+    Keep,
+    /// Drop the samples classified as severe outliers.
+    DropSevere,
+    /// Drop the samples classified as mild or severe outliers.
+    DropAll,
+}
+
+/// Options for the `reanalyze` subcommand.
+#[derive(Debug)]
+pub struct ReanalyzeOptions {
+    /// The timeline to read the stored samples from.
+    pub source: String,
+    /// The timeline to write the new estimates to.
+    pub timeline: String,
+    /// Only reanalyze benchmarks whose IDs match this regex.
+    pub filter: Option<Regex>,
+    pub confidence_level: f64,
+    pub nresamples: usize,
+    pub significance_level: f64,
+    pub noise_threshold: f64,
+    pub test: ComparisonTest,
+    pub outliers: OutlierPolicy,
+}
+
+/// The subcommands which do something other than running the benchmarks.
+#[derive(Debug)]
+pub enum Subcommand {
+    Gc(GcOptions),
+    Doctor(DoctorOptions),
+    Rename(RenameOptions),
+    Merge(MergeOptions),
+    Export(ExportOptions),
+    Import(ImportOptions),
+    History(HistoryOptions),
+    Reanalyze(ReanalyzeOptions),
 }
 
 /// Overall struct that represents all of the configuration data for this run.
@@ -182,8 +436,12 @@ pub struct FullConfig {
     pub self_config: SelfConfig,
     /// The arguments we pass through to cargo bench
     pub cargo_args: Vec<OsString>,
+    /// The benchmark filter (BENCHNAME) we pass through to the benchmark executables, if any
+    pub bench_filter: Option<OsString>,
     /// The additional arguments we pass through to the benchmark executables
     pub additional_args: Vec<OsString>,
+    /// The subcommand to execute instead of running the benchmarks, if any.
+    pub subcommand: Option<Subcommand>,
 }
 
 /// Call `cargo criterion` and parse the output to get the path to the target directory.
@@ -208,7 +466,7 @@ fn get_target_directory_from_metadata() -> Result<PathBuf> {
 pub fn configure() -> Result<FullConfig, anyhow::Error> {
     use clap::{App, AppSettings, Arg};
 
-    let matches = App::new("cargo-criterion")
+    let app = App::new("cargo-criterion")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Execute, analyze and report on benchmarks of a local package")
         .bin_name("cargo criterion")
@@ -370,6 +628,51 @@ pub fn configure() -> Result<FullConfig, anyhow::Error> {
                 .long("--no-fail-fast")
                 .help("Run all benchmarks regardless of failure"),
         )
+        .arg(
+            Arg::with_name("target-timeout")
+                .long("--target-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Kill benchmark targets which run for longer than this. The target is treated as a failed target; see --no-fail-fast."),
+        )
+        .arg(
+            Arg::with_name("benchmark-timeout")
+                .long("--benchmark-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Kill a benchmark target if one of its benchmarks runs for longer than this. The benchmark is reported as timed out, and the target is treated as a failed target; see --no-fail-fast."),
+        )
+        .arg(
+            Arg::with_name("cpus")
+                .long("--cpus")
+                .takes_value(true)
+                .value_name("LIST")
+                .help("Pin the benchmark targets to these CPUs, eg. `--cpus 2,3` or `--cpus 2-5` (Linux only). cargo-criterion's own analysis is restricted to the other CPUs.")
+        )
+        .arg(
+            Arg::with_name("strict-environment")
+                .long("--strict-environment")
+                .help("Refuse to run the benchmarks if the machine looks noisy, eg. because of CPU frequency scaling, turbo boost or a high load average (Linux only).")
+        )
+        .arg(
+            Arg::with_name("no-stability-probe")
+                .long("--no-stability-probe")
+                .help("Don't measure the machine's timing jitter before and after the run.")
+        )
+        .arg(
+            Arg::with_name("max-jitter")
+                .long("--max-jitter")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .conflicts_with("no-stability-probe")
+                .help("Refuse to run the benchmarks if the machine's timing jitter, as measured by the stability probe, is higher than this percentage.")
+        )
+        .arg(
+            Arg::with_name("scale-noise-threshold")
+                .long("--scale-noise-threshold")
+                .conflicts_with("no-stability-probe")
+                .help("Add the machine's timing jitter, as measured by the stability probe, to the noise threshold, so that changes smaller than the jitter aren't reported as regressions or improvements. The jitter is divided by the square root of each benchmark's sample count, since changes are judged on the mean of the samples.")
+        )
         .arg(
             Arg::with_name("debug")
                 .long("--debug")
@@ -429,13 +732,49 @@ See the documentation for details on the data printed by each format.
             Arg::with_name("history_id")
                 .long("--history-id")
                 .takes_value(true)
-                .help("An optional identifier string such as a commit ID that will be shown in the history reports to identify this run.")
+                .help("An optional identifier string such as a commit ID that will be shown in the history reports to identify this run. Defaults to the abbreviated hash of the current git commit, if any.")
         )
         .arg(
             Arg::with_name("history_description")
                 .long("--history-description")
                 .takes_value(true)
-                .help("An optional description string such as a commit message that will be shown in the history reports to describe this run.")
+                .help("An optional description string such as a commit message that will be shown in the history reports to describe this run. Defaults to the subject line of the current git commit, if any.")
+        )
+        .arg(
+            Arg::with_name("baseline")
+                .long("--baseline")
+                .takes_value(true)
+                .value_name("TIMELINE")
+                .help("Compare against the latest measurements in the given timeline (eg. one created with `cargo criterion import --timeline`) instead of the previous run.")
+        )
+        .arg(
+            Arg::with_name("baseline-tag")
+                .long("--baseline-tag")
+                .takes_value(true)
+                .value_name("KEY=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Compare against the latest measurements that have the given tag, eg. `--baseline-tag release=1.4`. May be given more than once. Combine with --baseline to search a different timeline.")
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("--tag")
+                .takes_value(true)
+                .value_name("KEY=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Label this run with a tag, such as `--tag compiler=nightly`, which is saved with the measurements. May be given more than once.")
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("--resume")
+                .conflicts_with("no-run")
+                .help("Continue the last run, which was interrupted, measuring only the benchmarks it didn't complete. The run keeps the history ID, description and tags of the interrupted run unless others are given.")
+        )
+        .arg(
+            Arg::with_name("no-wait-for-lock")
+                .long("--no-wait-for-lock")
+                .help("Fail immediately instead of waiting if another cargo-criterion process is using the same data directory.")
         )
         .arg(
             Arg::with_name("verbose")
@@ -495,7 +834,9 @@ The benchmark filtering argument BENCHNAME and all the arguments following the
 two dashes (`--`) are passed to the benchmark binaries and thus Criterion.rs. 
 If you're passing arguments to both Cargo and the binary, the ones after `--` go 
 to the binary, the ones before go to Cargo. For details about Criterion.rs' arguments see
-the output of `cargo criterion -- --help`.
+the output of `cargo criterion -- --help`. A BENCHNAME which is also the name of
+a subcommand, such as `history`, must be given after `--`, eg.
+`cargo criterion -- history`.
 
 If the `--package` argument is given, then SPEC is a package ID specification
 which indicates which package should be benchmarked. If it is not given, then
@@ -511,8 +852,17 @@ not affect how many jobs are used when running the benchmarks.
 
 Compilation can be customized with the `bench` profile in the manifest.
 ",
-        )
-        .get_matches();
+        );
+
+    // clap checks every positional argument against the names of the subcommands, even after
+    // `--`, so the subcommands are left out if `--` is given. This way, a BENCHNAME which is also
+    // the name of a subcommand can still be used, eg. `cargo criterion -- gc`.
+    let app = if std::env::args_os().any(|arg| arg == "--") {
+        app
+    } else {
+        add_subcommands(app)
+    };
+    let matches = app.get_matches();
 
     // Load the config file.
     let criterion_manifest_file: PathBuf = matches
@@ -647,6 +997,39 @@ Compilation can be customized with the `bench` profile in the manifest.
         PathBuf::from("target/criterion")
     };
 
+    let package_directory = matches
+        .value_of_os("manifest-path")
+        .and_then(|path| Path::new(path).parent())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if let Some(runner) = &toml_config.runner {
+        runner::validate(runner, "runner")?;
+    }
+    for (target, runner) in &toml_config.target_runners {
+        runner::validate(runner, &format!("runner for target {}", target))?;
+    }
+    toml_config.retention.validate()?;
+    let mut renames = Renames::new();
+    rename::check_renames(&toml_config.renames)
+        .context("Invalid [renames] table in criterion.toml")?;
+    renames.insert(None, toml_config.renames);
+    for (package, package_renames) in toml_config.package_renames {
+        rename::check_renames(&package_renames).with_context(|| {
+            format!(
+                "Invalid [package_renames.{}] table in criterion.toml",
+                package
+            )
+        })?;
+        renames.insert(Some(package), package_renames);
+    }
+    let default_runner = match (&toml_config.runner, matches.value_of("target")) {
+        (Some(runner), _) => Some(runner.clone()),
+        (None, Some(triple)) => runner::cargo_target_runner(triple)?,
+        (None, None) => None,
+    };
+
     let self_config = SelfConfig {
         output_format: (matches.value_of("output-format"))
             .or(toml_config.output_format.as_deref())
@@ -663,30 +1046,510 @@ Compilation can be customized with the `bench` profile in the manifest.
             .map(PlottingBackend::from_str)
             .unwrap_or(PlottingBackend::Auto),
         debug_build: matches.is_present("debug"),
+        features: matches
+            .values_of("features")
+            .into_iter()
+            .flatten()
+            .flat_map(|value| value.split([' ', ',']))
+            .filter(|feature| !feature.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+        all_features: matches.is_present("all-features"),
+        no_default_features: matches.is_present("no-default-features"),
         message_format: (matches.value_of("message-format")).map(MessageFormat::from_str),
         colors: toml_config.colors,
         history_id: matches.value_of("history_id").map(|s| s.to_owned()),
         history_description: matches
             .value_of("history_description")
             .map(|s| s.to_owned()),
+        package_directory,
+        retention: toml_config.retention,
+        wait_for_lock: !matches.is_present("no-wait-for-lock"),
+        renames,
+        baseline: matches.value_of("baseline").map(ToOwned::to_owned),
+        baseline_tags: parse_tags(matches.values_of("baseline-tag"))?,
+        tags: parse_tags(matches.values_of("tag"))?,
+        namespace_by_package: matches.is_present("workspace") || matches.is_present("all"),
+        timeouts: Timeouts {
+            target: match matches.value_of("target-timeout") {
+                Some(value) => Some(parse_timeout(value).context("Invalid --target-timeout")?),
+                None => (toml_config.target_timeout.map(seconds_to_duration))
+                    .transpose()
+                    .context("Invalid target_timeout in criterion.toml")?,
+            },
+            benchmark: match matches.value_of("benchmark-timeout") {
+                Some(value) => Some(parse_timeout(value).context("Invalid --benchmark-timeout")?),
+                None => (toml_config.benchmark_timeout.map(seconds_to_duration))
+                    .transpose()
+                    .context("Invalid benchmark_timeout in criterion.toml")?,
+            },
+        },
+        resume: matches.is_present("resume"),
+        runners: Runners {
+            default: default_runner,
+            targets: toml_config.target_runners,
+        },
+        cpus: match matches.value_of("cpus") {
+            Some(list) => Some(parse_cpu_list(list).context("Invalid --cpus")?),
+            None => (toml_config.cpus.as_deref().map(parse_cpu_list))
+                .transpose()
+                .context("Invalid cpus in criterion.toml")?,
+        },
+        strict_environment: matches.is_present("strict-environment")
+            || toml_config.strict_environment,
+        stability: StabilityOptions {
+            probe: !matches.is_present("no-stability-probe"),
+            max_jitter: (matches.value_of("max-jitter").map(parse_percentage))
+                .transpose()
+                .context("Invalid --max-jitter")?,
+            scale_noise_threshold: matches.is_present("scale-noise-threshold"),
+        },
     };
 
     // These are the extra arguments to be passed to the benchmark targets.
+    let bench_filter = matches.value_of_os("BENCHNAME").map(ToOwned::to_owned);
     let mut additional_args: Vec<OsString> = vec![];
-    additional_args.extend(matches.value_of_os("BENCHNAME").map(ToOwned::to_owned));
 
     if let Some(args) = matches.values_of_os("args") {
         additional_args.extend(args.map(ToOwned::to_owned));
     }
 
+    let subcommand = match matches.subcommand() {
+        ("gc", Some(sub_matches)) => Some(Subcommand::Gc(GcOptions {
+            dry_run: sub_matches.is_present("dry-run"),
+            measurements_only: sub_matches.is_present("measurements-only"),
+        })),
+        ("doctor", Some(sub_matches)) => Some(Subcommand::Doctor(DoctorOptions {
+            quarantine: sub_matches.is_present("quarantine"),
+        })),
+        ("rename", Some(sub_matches)) => Some(Subcommand::Rename(RenameOptions {
+            package: sub_matches.value_of("package").map(ToOwned::to_owned),
+            old: sub_matches.value_of("OLD").unwrap().to_owned(),
+            new: sub_matches.value_of("NEW").unwrap().to_owned(),
+        })),
+        ("merge", Some(sub_matches)) => Some(Subcommand::Merge(MergeOptions {
+            directories: sub_matches
+                .values_of_os("DIR")
+                .unwrap()
+                .map(PathBuf::from)
+                .collect(),
+        })),
+        ("export", Some(sub_matches)) => Some(Subcommand::Export(ExportOptions {
+            format: match (
+                sub_matches.value_of_os("bundle"),
+                sub_matches.value_of("format"),
+            ) {
+                (Some(bundle), _) => ExportFormat::Bundle(PathBuf::from(bundle)),
+                (None, Some("csv")) => ExportFormat::Csv,
+                (None, Some("jsonl")) => ExportFormat::JsonLines,
+                (None, _) => unreachable!(),
+            },
+            output: sub_matches.value_of_os("output").map(PathBuf::from),
+            samples: sub_matches.value_of_os("samples").map(PathBuf::from),
+            timelines: sub_matches
+                .values_of("timeline")
+                .map(|values| values.map(ToOwned::to_owned).collect())
+                .unwrap_or_default(),
+            filter: sub_matches
+                .value_of("FILTER")
+                .map(Regex::new)
+                .transpose()
+                .context("Invalid benchmark filter")?,
+            since: sub_matches
+                .value_of("since")
+                .map(|date| parse_date(date, false))
+                .transpose()?,
+            until: sub_matches
+                .value_of("until")
+                .map(|date| parse_date(date, true))
+                .transpose()?,
+            tags: parse_tags(sub_matches.values_of("tag"))?,
+        })),
+        ("import", Some(sub_matches)) => Some(Subcommand::Import(ImportOptions {
+            bundle: PathBuf::from(sub_matches.value_of_os("BUNDLE").unwrap()),
+            timeline: sub_matches.value_of("timeline").map(ToOwned::to_owned),
+        })),
+        ("history", Some(sub_matches)) => Some(Subcommand::History(HistoryOptions {
+            filter: Regex::new(sub_matches.value_of("FILTER").unwrap())
+                .context("Invalid benchmark filter")?,
+            limit: sub_matches
+                .value_of("limit")
+                .map(str::parse)
+                .transpose()
+                .context("--limit must be a positive integer")?,
+            json: sub_matches.is_present("json"),
+            timeline: sub_matches.value_of("timeline").unwrap().to_owned(),
+            tags: parse_tags(sub_matches.values_of("tag"))?,
+        })),
+        ("reanalyze", Some(sub_matches)) => Some(Subcommand::Reanalyze(ReanalyzeOptions {
+            source: sub_matches.value_of("from").unwrap().to_owned(),
+            timeline: sub_matches.value_of("timeline").unwrap().to_owned(),
+            filter: sub_matches
+                .value_of("FILTER")
+                .map(Regex::new)
+                .transpose()
+                .context("Invalid benchmark filter")?,
+            confidence_level: parse_fraction(sub_matches.value_of("confidence-level").unwrap())
+                .context("Invalid --confidence-level")?,
+            nresamples: match sub_matches.value_of("nresamples").unwrap().parse() {
+                Ok(n) if n > 0 => n,
+                _ => anyhow::bail!("--nresamples must be a positive integer"),
+            },
+            significance_level: parse_fraction(sub_matches.value_of("significance-level").unwrap())
+                .context("Invalid --significance-level")?,
+            noise_threshold: match sub_matches.value_of("noise-threshold").unwrap().parse() {
+                Ok(threshold) if threshold >= 0.0 => threshold,
+                _ => anyhow::bail!("--noise-threshold must be a non-negative number"),
+            },
+            test: match sub_matches.value_of("test").unwrap() {
+                "t-test" => ComparisonTest::TTest,
+                "mann-whitney" => ComparisonTest::MannWhitney,
+                _ => unreachable!(),
+            },
+            outliers: match sub_matches.value_of("outliers").unwrap() {
+                "keep" => OutlierPolicy::Keep,
+                "drop-severe" => OutlierPolicy::DropSevere,
+                "drop-all" => OutlierPolicy::DropAll,
+                _ => unreachable!(),
+            },
+        })),
+        _ => None,
+    };
+
     let configuration = FullConfig {
         self_config,
         cargo_args,
+        bench_filter,
         additional_args,
+        subcommand,
     };
     Ok(configuration)
 }
 
+/// Add the subcommands which do something other than running the benchmarks.
+fn add_subcommands<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    use clap::{Arg, SubCommand};
+
+    app
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Delete old measurements and the data of benchmarks that no longer exist")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("--dry-run")
+                        .help("Print what would be deleted without deleting anything"),
+                )
+                .arg(
+                    Arg::with_name("measurements-only")
+                        .long("--measurements-only")
+                        .help("Only apply the retention policy from criterion.toml. Don't compile the benchmarks to look for ones that have been removed."),
+                )
+                .after_help(
+                    "\
+Measurements are deleted according to the [retention] table in criterion.toml:
+
+    [retention]
+    keep_last = 100      # Keep at most this many measurements for each benchmark
+    max_age_days = 365   # Delete measurements older than this
+    keep_tagged = true   # Always keep measurements from tagged runs (the default)
+
+The most recent measurement of each benchmark is never deleted. Unless --measurements-only is
+given, the benchmark targets are also compiled and listed, and the stored data and reports of
+any benchmark that no longer exists in any target are deleted.
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the stored benchmark data for missing or corrupt files and repair it")
+                .arg(
+                    Arg::with_name("quarantine")
+                        .long("--quarantine")
+                        .help("Move unreadable and orphaned files into the quarantine directory in CRITERION_HOME"),
+                )
+                .after_help(
+                    "\
+Every stored benchmark record and measurement is loaded and checked. Benchmarks whose latest
+measurement is missing or unreadable are repointed at the newest measurement that can still be
+read, and damaged history indexes are rebuilt. Unreadable files and files which don't belong to
+any benchmark are reported, and moved aside if --quarantine is given.
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rename")
+                .about("Move the stored history of a benchmark to a new benchmark ID")
+                .arg(
+                    Arg::with_name("package")
+                        .long("--package")
+                        .takes_value(true)
+                        .value_name("PACKAGE")
+                        .help("Rename a benchmark of this package which was stored with --workspace."),
+                )
+                .arg(
+                    Arg::with_name("OLD")
+                        .required(true)
+                        .help("The old ID of the benchmark, eg. 'my_group/my_function'. If this is the prefix of several benchmark IDs, such as a group name, all of them are renamed."),
+                )
+                .arg(
+                    Arg::with_name("NEW")
+                        .required(true)
+                        .help("The new ID of the benchmark"),
+                )
+                .after_help(
+                    "\
+If the new benchmark already has stored data, the two histories are combined. Renames can also be
+listed in criterion.toml, in which case they are applied automatically at the start of each run:
+
+    [renames]
+    \"old_group/old_function\" = \"new_group/new_function\"
+
+    # Benchmarks stored with --workspace are renamed separately for each package.
+    [package_renames.my-package]
+    \"old_group\" = \"new_group\"
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge the data from other cargo-criterion data directories into this one")
+                .arg(
+                    Arg::with_name("DIR")
+                        .required(true)
+                        .multiple(true)
+                        .help("The data directories to merge, eg. the target/criterion directories of several CI shards"),
+                )
+                .after_help(
+                    "\
+The full history of every benchmark is kept, in every timeline. If a benchmark appears in more
+than one directory, its most recent measurement becomes the baseline for the next run, and the
+reports for that measurement are used. Afterwards, the index report is regenerated to cover every
+benchmark.
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export the stored measurements")
+                .arg(
+                    Arg::with_name("bundle")
+                        .long("--bundle")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required_unless("format")
+                        .conflicts_with_all(&["format", "output", "samples", "since", "until", "tag"])
+                        .help("Write a results bundle, which can be loaded with `cargo criterion import`, to this file"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("--format")
+                        .takes_value(true)
+                        .possible_values(&["csv", "jsonl"])
+                        .help("Write a table of the stored measurements in this format, with one row per measurement"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("--output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the table to this file instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("samples")
+                        .long("--samples")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Also write a table of the raw samples of every exported measurement to this file"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("--since")
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .help("Only export measurements taken on or after this date (YYYY-MM-DD or RFC 3339)"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("--until")
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .help("Only export measurements taken on or before this date (YYYY-MM-DD or RFC 3339)"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("--tag")
+                        .takes_value(true)
+                        .value_name("KEY=VALUE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only export measurements from runs with the given tag. May be given more than once."),
+                )
+                .arg(
+                    Arg::with_name("timeline")
+                        .long("--timeline")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only export the given timeline. May be given more than once. Defaults to all timelines."),
+                )
+                .arg(
+                    Arg::with_name("FILTER")
+                        .help("Only export benchmarks whose IDs match this regex, eg. '^my_group/'"),
+                )
+                .after_help(
+                    "\
+Each row of the table describes one measurement: the benchmark ID, when it was taken, its history
+ID, description and tags, every estimate with its confidence interval, the throughput and the change
+from the previous measurement. Values are in the base unit of the measurement, which is
+nanoseconds for wall-clock time. The samples table has one row per sample, with the number of
+iterations and the measured value.
+
+    cargo criterion export --format csv --output history.csv --since 2024-01-01 '^parsing/'
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import a results bundle created by `cargo criterion export --bundle`")
+                .arg(
+                    Arg::with_name("BUNDLE")
+                        .required(true)
+                        .help("The bundle file to import"),
+                )
+                .arg(
+                    Arg::with_name("timeline")
+                        .long("--timeline")
+                        .takes_value(true)
+                        .help("Import the measurements into this timeline instead of the ones they were exported from. Use --baseline with the same name to compare against them."),
+                )
+                .after_help(
+                    "\
+Measurements from the bundle are added to the history of each benchmark. Importing into the
+default timeline ('main') combines the bundle with your own measurements, like `merge` does.
+Importing into a separate timeline, such as `--timeline alice`, keeps them apart so they can be
+used as a baseline:
+
+    cargo criterion import alice.cbor --timeline alice
+    cargo criterion --baseline alice
+",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Print the history of the stored measurements of some benchmarks")
+                .arg(
+                    Arg::with_name("FILTER")
+                        .required(true)
+                        .help("Show the history of the benchmarks whose IDs match this regex, eg. '^my_group/'"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("--limit")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("Only show the N most recent measurements of each benchmark"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("--json")
+                        .help("Print the history as JSON instead of a table"),
+                )
+                .arg(
+                    Arg::with_name("timeline")
+                        .long("--timeline")
+                        .takes_value(true)
+                        .default_value("main")
+                        .help("Show the history stored in this timeline"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("--tag")
+                        .takes_value(true)
+                        .value_name("KEY=VALUE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only show measurements from runs with the given tag. May be given more than once."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reanalyze")
+                .about("Recompute the statistics of the stored measurements with different settings")
+                .arg(
+                    Arg::with_name("timeline")
+                        .long("--timeline")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Write the recomputed measurements to this timeline"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("--from")
+                        .takes_value(true)
+                        .value_name("TIMELINE")
+                        .default_value("main")
+                        .help("Read the stored samples from this timeline"),
+                )
+                .arg(
+                    Arg::with_name("confidence-level")
+                        .long("--confidence-level")
+                        .takes_value(true)
+                        .default_value("0.95")
+                        .help("Confidence level of the estimated confidence intervals"),
+                )
+                .arg(
+                    Arg::with_name("nresamples")
+                        .long("--nresamples")
+                        .takes_value(true)
+                        .default_value("100000")
+                        .help("Number of bootstrap resamples"),
+                )
+                .arg(
+                    Arg::with_name("significance-level")
+                        .long("--significance-level")
+                        .takes_value(true)
+                        .default_value("0.05")
+                        .help("Significance level of the hypothesis test used to detect changes"),
+                )
+                .arg(
+                    Arg::with_name("noise-threshold")
+                        .long("--noise-threshold")
+                        .takes_value(true)
+                        .default_value("0.01")
+                        .help("Relative changes smaller than this are treated as noise"),
+                )
+                .arg(
+                    Arg::with_name("test")
+                        .long("--test")
+                        .takes_value(true)
+                        .possible_values(&["t-test", "mann-whitney"])
+                        .default_value("t-test")
+                        .help("Hypothesis test used to detect changes"),
+                )
+                .arg(
+                    Arg::with_name("outliers")
+                        .long("--outliers")
+                        .takes_value(true)
+                        .possible_values(&["keep", "drop-severe", "drop-all"])
+                        .default_value("keep")
+                        .help("Drop outlying samples before analyzing them"),
+                )
+                .arg(
+                    Arg::with_name("FILTER")
+                        .help("Only reanalyze benchmarks whose IDs match this regex, eg. '^my_group/'"),
+                )
+                .after_help(
+                    "\
+Every stored measurement of each benchmark is analyzed again from its raw samples, and compared
+with the measurement before it. The results are written to a separate timeline, so the stored
+data is left untouched. They can be viewed with `history --timeline` or `export --timeline`:
+
+    cargo criterion reanalyze --timeline strict --significance-level 0.01 --outliers drop-severe
+    cargo criterion history --timeline strict '^parsing/'
+",
+                ),
+        )
+}
+
 /// Load & parse the criterion.toml file (if present).
 fn load_toml_file(toml_path: &Path) -> Result<TomlConfig, anyhow::Error> {
     if !toml_path.exists() {
@@ -704,3 +1567,151 @@ fn load_toml_file(toml_path: &Path) -> Result<TomlConfig, anyhow::Error> {
         .with_context(|| format!("Failed to parse config file {:?}", toml_path))?;
     Ok(config)
 }
+
+/// Parse a date given on the command line, either as YYYY-MM-DD (in UTC) or as an RFC 3339
+/// timestamp. If `end_of_day` is true, a plain date refers to the end of that day rather than
+/// the start.
+fn parse_date(date: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").with_context(|| {
+        format!(
+            "Invalid date '{}'; expected YYYY-MM-DD or an RFC 3339 timestamp",
+            date
+        )
+    })?;
+    let time = if end_of_day {
+        NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999)
+    } else {
+        NaiveTime::from_hms_opt(0, 0, 0)
+    };
+    Ok(DateTime::from_naive_utc_and_offset(
+        day.and_time(time.unwrap()),
+        Utc,
+    ))
+}
+
+/// Parse a timeout given on the command line as a number of seconds.
+fn parse_timeout(value: &str) -> Result<Duration> {
+    match value.parse() {
+        Ok(seconds) => seconds_to_duration(seconds),
+        Err(_) => Err(anyhow::anyhow!("'{}' is not a number of seconds", value)),
+    }
+}
+
+fn seconds_to_duration(seconds: f64) -> Result<Duration> {
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(duration) if seconds > 0.0 => Ok(duration),
+        _ => Err(anyhow::anyhow!(
+            "The timeout must be a positive number of seconds, not {}",
+            seconds
+        )),
+    }
+}
+
+/// Parse a number which must be strictly between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64> {
+    match value.parse() {
+        Ok(fraction) if fraction > 0.0 && fraction < 1.0 => Ok(fraction),
+        _ => Err(anyhow::anyhow!(
+            "'{}' is not a number between 0 and 1",
+            value
+        )),
+    }
+}
+
+/// Parse a positive percentage, returning it as a fraction.
+fn parse_percentage(value: &str) -> Result<f64> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent.is_finite() => Ok(percent / 100.0),
+        _ => Err(anyhow::anyhow!("'{}' is not a positive percentage", value)),
+    }
+}
+
+/// Parse tags given on the command line as KEY=VALUE.
+fn parse_tags(values: Option<clap::Values>) -> Result<BTreeMap<String, String>> {
+    let mut tags = BTreeMap::new();
+    for tag in values.into_iter().flatten() {
+        match tag.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                tags.insert(key.to_owned(), value.to_owned());
+            }
+            _ => return Err(anyhow::anyhow!("Invalid tag '{}'; expected KEY=VALUE", tag)),
+        }
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(Duration::from_millis(1500), parse_timeout("1.5").unwrap());
+        assert_eq!(Duration::from_secs(60), parse_timeout("60").unwrap());
+        for invalid in &["0", "-1", "NaN", "inf", "1e30", "", "ten"] {
+            assert!(
+                parse_timeout(invalid).is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_retention_policy() {
+        let policy = |max_age_days| RetentionPolicy {
+            max_age_days: Some(max_age_days),
+            ..RetentionPolicy::default()
+        };
+        assert!(policy(365).validate().is_ok());
+        assert!(policy(100_000_000_000).max_age().is_some());
+        assert!(policy(u64::MAX).validate().is_err());
+        assert!(policy(i64::MAX as u64).validate().is_err());
+    }
+
+    fn tags(args: &[&str]) -> Result<BTreeMap<String, String>> {
+        use clap::{App, Arg};
+
+        let app = App::new("test").arg(
+            Arg::with_name("tag")
+                .long("--tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        );
+        let mut command_line = vec!["test"];
+        for arg in args {
+            command_line.extend(&["--tag", arg]);
+        }
+        parse_tags(app.get_matches_from(command_line).values_of("tag"))
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert!(tags(&[]).unwrap().is_empty());
+        let parsed = tags(&["compiler=nightly", "flags=-C opt-level=3", "empty="]).unwrap();
+        let expected: BTreeMap<String, String> = vec![
+            ("compiler", "nightly"),
+            ("flags", "-C opt-level=3"),
+            ("empty", ""),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+        assert_eq!(parsed, expected);
+        // The last value of a repeated key wins.
+        assert_eq!(tags(&["a=1", "a=2"]).unwrap()["a"], "2");
+
+        for invalid in &["nightly", "=nightly", "", "="] {
+            let error = tags(&["compiler=nightly", invalid]).unwrap_err();
+            assert!(
+                error.to_string().contains("expected KEY=VALUE"),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+}
@@ -60,7 +60,7 @@ pub struct Connection {
     socket: TcpStream,
     receive_buffer: Vec<u8>,
     send_buffer: Vec<u8>,
-    // criterion_rs_version: [u8; 3],
+    criterion_rs_version: [u8; 3],
     // protocol_version: u16,
     // protocol_format: ProtocolFormat,
 }
@@ -102,12 +102,18 @@ impl Connection {
             socket,
             receive_buffer: vec![],
             send_buffer: vec![],
-            // criterion_rs_version,
+            criterion_rs_version,
             // protocol_version,
             // protocol_format,
         })
     }
 
+    /// The version of Criterion.rs used by the benchmark, as reported in its hello message.
+    pub fn criterion_rs_version(&self) -> String {
+        let [major, minor, patch] = self.criterion_rs_version;
+        format!("{}.{}.{}", major, minor, patch)
+    }
+
     /// Receive a message from the benchmark. If the benchmark has closed the connection, returns
     /// Ok(None).
     pub fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
@@ -291,6 +297,7 @@ impl From<BenchmarkConfig> for crate::analysis::BenchmarkConfig {
                 other.warm_up_time.secs,
                 other.warm_up_time.nanos,
             ),
+            test: crate::analysis::ComparisonTest::TTest,
         }
     }
 }
@@ -0,0 +1,321 @@
+//! Module implementing the `doctor` subcommand, which checks the stored benchmark data for
+//! problems (usually left behind by runs that were killed part-way through) and repairs them.
+
+use crate::config::{DoctorOptions, FullConfig};
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    is_measurement_file, list_timelines, load_benchmark_record, load_measurement,
+    read_stored_history_index, save_benchmark_record, timeline_directory, write_history_index,
+    BenchmarkRecord, HistoryRecord, HISTORY_INDEX, TEMP_SUFFIX,
+};
+use crate::schema::SchemaError;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Counts of what the doctor found, for the final summary.
+#[derive(Default)]
+struct Findings {
+    benchmarks: usize,
+    problems: usize,
+    repaired: usize,
+    // Unreadable files and files which don't belong to any benchmark.
+    bad_files: Vec<PathBuf>,
+}
+impl Findings {
+    fn problem(&mut self, path: &Path, description: &str) {
+        eprintln!("{}: {}", path.display(), description);
+        self.problems += 1;
+    }
+
+    fn repaired(&mut self, description: &str) {
+        eprintln!("    Repaired: {}", description);
+        self.repaired += 1;
+    }
+}
+
+/// Entry point for the `doctor` subcommand.
+pub fn doctor(configuration: &FullConfig, options: &DoctorOptions) -> Result<()> {
+    let criterion_home = &configuration.self_config.criterion_home;
+    let _lock = DirectoryLock::acquire(
+        criterion_home,
+        LockKind::Exclusive,
+        configuration.self_config.wait_for_lock,
+    )?;
+
+    let findings = check_and_repair(criterion_home, options.quarantine)?;
+    eprintln!(
+        "Checked {} benchmarks: found {} problems, repaired {}.",
+        findings.benchmarks, findings.problems, findings.repaired
+    );
+    Ok(())
+}
+
+/// Check and repair the data of every timeline in the given CRITERION_HOME. If `quarantine` is
+/// set, unreadable and orphaned files are moved out of the data directory.
+fn check_and_repair(criterion_home: &Path, quarantine: bool) -> Result<Findings> {
+    let mut findings = Findings::default();
+    for timeline in list_timelines(criterion_home)? {
+        for entry in WalkDir::new(timeline_directory(criterion_home, &timeline)?)
+            .into_iter()
+            .filter_map(::std::result::Result::ok)
+            .filter(|entry| entry.file_type().is_dir())
+        {
+            check_directory(entry.path(), &mut findings)?;
+        }
+    }
+
+    if quarantine {
+        let quarantine_directory = criterion_home.join("quarantine");
+        for path in &findings.bad_files {
+            let relative = path.strip_prefix(criterion_home)?;
+            let destination = quarantine_directory.join(relative);
+            fs::create_dir_all(destination.parent().unwrap())
+                .with_context(|| format!("Failed to create directory {:?}", destination))?;
+            fs::rename(path, &destination)
+                .with_context(|| format!("Failed to move {:?} to {:?}", path, destination))?;
+            eprintln!("Moved {} to {}", path.display(), destination.display());
+            // Clean up directories that only contained orphaned files. This fails harmlessly if
+            // the directory isn't empty.
+            let _ = fs::remove_dir(path.parent().unwrap());
+            findings.repaired += 1;
+        }
+    } else if !findings.bad_files.is_empty() {
+        eprintln!(
+            "{} unreadable or orphaned files were left in place. Use --quarantine to move them out of the data directory.",
+            findings.bad_files.len()
+        );
+    }
+    Ok(findings)
+}
+
+/// Check the files directly inside the given directory.
+fn check_directory(dir: &Path, findings: &mut Findings) -> Result<()> {
+    let mut record_path = None;
+    let mut index_path = None;
+    let mut measurement_paths = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name();
+        if name == "benchmark.cbor" {
+            record_path = Some(entry.path());
+        } else if name == HISTORY_INDEX {
+            index_path = Some(entry.path());
+        } else if is_measurement_file(&name) {
+            measurement_paths.push(entry.path());
+        } else if name.to_string_lossy().ends_with(TEMP_SUFFIX) {
+            findings.problem(
+                &entry.path(),
+                "leftover temporary file from an interrupted run",
+            );
+            findings.bad_files.push(entry.path());
+        }
+    }
+
+    let record_path = match record_path {
+        Some(record_path) => record_path,
+        None => {
+            // Not a benchmark directory, unless there are measurements with no record.
+            for path in measurement_paths.into_iter().chain(index_path) {
+                findings.problem(&path, "orphaned file with no benchmark record");
+                findings.bad_files.push(path);
+            }
+            return Ok(());
+        }
+    };
+    findings.benchmarks += 1;
+
+    // Load everything that can be loaded, and make note of everything that can't.
+    let mut valid = vec![];
+    for path in measurement_paths {
+        match load_measurement(&path) {
+            Ok(stats) => valid.push(HistoryRecord::new(
+                PathBuf::from(path.file_name().unwrap()),
+                &stats,
+            )),
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(e) => {
+                findings.problem(&path, &format!("unreadable measurement ({:#})", e));
+                findings.bad_files.push(path);
+            }
+        }
+    }
+    valid.sort_by_key(|record| record.datetime);
+
+    let record = match load_benchmark_record(&record_path) {
+        Ok(record) => record,
+        Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+        Err(e) => {
+            // Without the record, we don't know which benchmark these measurements belong to.
+            findings.problem(
+                &record_path,
+                &format!("unreadable benchmark record ({:#})", e),
+            );
+            findings.bad_files.push(record_path);
+            for path in valid.iter().map(|record| dir.join(&record.measurement)) {
+                findings.problem(&path, "orphaned file with no benchmark record");
+                findings.bad_files.push(path);
+            }
+            findings.bad_files.extend(index_path);
+            return Ok(());
+        }
+    };
+
+    if !valid
+        .iter()
+        .any(|measurement| measurement.measurement == record.latest_record)
+    {
+        findings.problem(
+            &record_path,
+            &format!(
+                "latest measurement {} is missing or unreadable",
+                record.latest_record.display()
+            ),
+        );
+        match valid.last() {
+            Some(newest) => {
+                let repaired = BenchmarkRecord {
+                    id: record.id,
+                    latest_record: newest.measurement.clone(),
+                };
+                save_benchmark_record(&record_path, &repaired)?;
+                findings.repaired(&format!(
+                    "now pointing at {}",
+                    repaired.latest_record.display()
+                ));
+            }
+            None => {
+                findings.problem(&record_path, "benchmark has no readable measurements");
+                findings.bad_files.push(record_path);
+                findings.bad_files.extend(index_path);
+                return Ok(());
+            }
+        }
+    }
+
+    // The index should list exactly the readable measurements. If it doesn't exist at all, the
+    // data was saved by an older version and the index will be built when it's first needed.
+    if index_path.is_none() {
+        return Ok(());
+    }
+    let expected: Vec<&Path> = valid
+        .iter()
+        .map(|record| record.measurement.as_path())
+        .collect();
+    let problem = match read_stored_history_index(dir)? {
+        None => Some("history index is damaged"),
+        Some(indexed) => {
+            let indexed: Vec<&Path> = (indexed.iter())
+                .map(|record| record.measurement.as_path())
+                .collect();
+            Some("history index is out of date").filter(|_| indexed != expected)
+        }
+    };
+    if let Some(problem) = problem {
+        findings.problem(&dir.join(HISTORY_INDEX), problem);
+        write_history_index(dir, &valid)?;
+        findings.repaired("rebuilt the history index");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::read_history_index;
+    use crate::model::test::{saved_statistics, store_benchmark};
+    use crate::report::BenchmarkId;
+
+    fn store(criterion_home: &Path, timeline: &str, group: &str) -> PathBuf {
+        store_benchmark(
+            &path!(criterion_home, "data", timeline),
+            &BenchmarkId::new(group.to_owned(), None, None, None),
+            &[saved_statistics(1_000, 1.0), saved_statistics(2_000, 2.0)],
+        )
+    }
+
+    fn latest_record(dir: &Path) -> PathBuf {
+        load_benchmark_record(&dir.join("benchmark.cbor"))
+            .unwrap()
+            .latest_record
+    }
+
+    #[test]
+    fn test_healthy_data() {
+        let home = tempfile::tempdir().unwrap();
+        store(home.path(), "main", "a");
+        store(home.path(), "main", "b");
+        let findings = check_and_repair(home.path(), false).unwrap();
+        assert_eq!(findings.benchmarks, 2);
+        assert_eq!(findings.problems, 0);
+        assert!(findings.bad_files.is_empty());
+    }
+
+    #[test]
+    fn test_repoint_at_newest_readable_measurement() {
+        let home = tempfile::tempdir().unwrap();
+        let dir = store(home.path(), "main", "a");
+        // The latest measurement was corrupted by a killed run.
+        fs::write(dir.join("measurement_2000.cbor"), b"garbage").unwrap();
+
+        let findings = check_and_repair(home.path(), false).unwrap();
+        assert_eq!(latest_record(&dir), PathBuf::from("measurement_1000.cbor"));
+        assert_eq!(findings.bad_files, vec![dir.join("measurement_2000.cbor")]);
+        // The history index is rebuilt without the unreadable measurement.
+        assert_eq!(read_history_index(&dir).unwrap().len(), 1);
+        // Repointing and rebuilding the index.
+        assert_eq!(findings.repaired, 2);
+        // The unreadable file is left in place without --quarantine.
+        assert!(dir.join("measurement_2000.cbor").exists());
+    }
+
+    #[test]
+    fn test_quarantine_orphaned_and_unreadable_files() {
+        let home = tempfile::tempdir().unwrap();
+        let dir = store(home.path(), "main", "a");
+        fs::write(dir.join("measurement_3000.cbor"), b"garbage").unwrap();
+        fs::write(dir.join("benchmark.cbor.tmp"), b"partial").unwrap();
+        // Measurements in a directory without a benchmark record belong to no benchmark.
+        let orphan_dir = path!(home.path(), "data", "main", "orphan");
+        fs::create_dir_all(&orphan_dir).unwrap();
+        fs::copy(
+            dir.join("measurement_1000.cbor"),
+            orphan_dir.join("measurement_1000.cbor"),
+        )
+        .unwrap();
+
+        let findings = check_and_repair(home.path(), true).unwrap();
+        assert_eq!(findings.bad_files.len(), 3);
+        let quarantine = path!(home.path(), "quarantine", "data", "main");
+        assert!(path!(&quarantine, "a", "measurement_3000.cbor").is_file());
+        assert!(path!(&quarantine, "a", "benchmark.cbor.tmp").is_file());
+        assert!(path!(&quarantine, "orphan", "measurement_1000.cbor").is_file());
+        assert!(!dir.join("measurement_3000.cbor").exists());
+        assert!(!dir.join("benchmark.cbor.tmp").exists());
+        // The emptied orphan directory is removed.
+        assert!(!orphan_dir.exists());
+        // The readable data is left alone.
+        assert_eq!(latest_record(&dir), PathBuf::from("measurement_2000.cbor"));
+        assert!(dir.join("measurement_1000.cbor").is_file());
+    }
+
+    #[test]
+    fn test_rebuild_damaged_index_in_every_timeline() {
+        let home = tempfile::tempdir().unwrap();
+        store(home.path(), "main", "a");
+        let imported = store(home.path(), "imported", "a");
+        fs::write(imported.join(HISTORY_INDEX), b"garbage").unwrap();
+
+        let findings = check_and_repair(home.path(), false).unwrap();
+        assert_eq!(findings.benchmarks, 2);
+        assert_eq!(findings.problems, 1);
+        assert_eq!(
+            read_stored_history_index(&imported).unwrap().unwrap().len(),
+            2
+        );
+    }
+}
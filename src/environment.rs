@@ -0,0 +1,256 @@
+//! Module that collects information about the machine and toolchain used to run the benchmarks.
+//!
+//! This is stored alongside each measurement so that results taken on different machines or with
+//! different compilers can be told apart when comparing them later.
+
+use crate::config::SelfConfig;
+use crate::noise::NoiseChecks;
+use std::fs;
+use std::process::Command;
+
+/// Description of the environment that a set of measurements was taken in.
+///
+/// Every field is optional because the information may not be available on all platforms, and
+/// because older versions of cargo-criterion didn't record any of it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    pub hostname: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpu_cores: Option<usize>,
+    pub kernel_version: Option<String>,
+    // The first line of `rustc -vV`, eg. "rustc 1.53.0 (53cb7b09b 2021-06-17)"
+    pub rustc_version: Option<String>,
+    // The cargo profile used to build the benchmarks; "bench" or "test"
+    pub profile: Option<String>,
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    pub cargo_criterion_version: Option<String>,
+    // The version of Criterion.rs that the benchmark target reported when it connected to us.
+    pub criterion_rs_version: Option<String>,
+    // The results of the checks for sources of noise made before the run.
+    pub noise_checks: Option<NoiseChecks>,
+    // The timing jitter measured by the stability probe before the run, as a fraction.
+    pub jitter: Option<f64>,
+}
+impl Environment {
+    /// Collect the details of the current machine and the build configuration for this run.
+    pub fn capture(self_config: &SelfConfig) -> Environment {
+        let (cpu_model, cpu_cores) = match fs::read_to_string("/proc/cpuinfo") {
+            Ok(cpuinfo) => parse_cpuinfo(&cpuinfo),
+            Err(_) => (None, None),
+        };
+
+        let profile = if self_config.debug_build {
+            "test"
+        } else {
+            "bench"
+        };
+
+        Environment {
+            hostname: hostname(),
+            cpu_model,
+            cpu_cores: cpu_cores.or_else(|| {
+                std::thread::available_parallelism()
+                    .ok()
+                    .map(|cores| cores.get())
+            }),
+            kernel_version: kernel_version(),
+            rustc_version: rustc_version(),
+            profile: Some(profile.to_owned()),
+            features: self_config.features.clone(),
+            all_features: self_config.all_features,
+            no_default_features: self_config.no_default_features,
+            cargo_criterion_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            criterion_rs_version: None,
+            noise_checks: None,
+            jitter: None,
+        }
+    }
+
+    /// Returns a copy of this environment for a benchmark target which connected to us using the
+    /// given version of Criterion.rs.
+    pub fn with_criterion_rs_version(&self, version: String) -> Environment {
+        Environment {
+            criterion_rs_version: Some(version),
+            ..self.clone()
+        }
+    }
+
+    /// Describe the ways in which this environment is different enough from `base` that a
+    /// comparison between measurements taken in the two is suspect - in other words, whether the
+    /// measurements were taken on different machines, with different toolchains or with different
+    /// versions of cargo-criterion or Criterion.rs. Returns an empty list if the environments are
+    /// comparable or if not enough information is available.
+    pub fn differences_from(&self, base: &Environment) -> Vec<String> {
+        fn compare(
+            differences: &mut Vec<String>,
+            what: &str,
+            new: &Option<String>,
+            old: &Option<String>,
+        ) {
+            if let (Some(new), Some(old)) = (new, old) {
+                if new != old {
+                    differences.push(format!("{} changed from '{}' to '{}'", what, old, new));
+                }
+            }
+        }
+
+        let mut differences = vec![];
+        compare(&mut differences, "Host", &self.hostname, &base.hostname);
+        compare(&mut differences, "CPU", &self.cpu_model, &base.cpu_model);
+        compare(
+            &mut differences,
+            "Compiler",
+            &self.rustc_version,
+            &base.rustc_version,
+        );
+        compare(&mut differences, "Profile", &self.profile, &base.profile);
+        compare(
+            &mut differences,
+            "cargo-criterion version",
+            &self.cargo_criterion_version,
+            &base.cargo_criterion_version,
+        );
+        compare(
+            &mut differences,
+            "Criterion.rs version",
+            &self.criterion_rs_version,
+            &base.criterion_rs_version,
+        );
+        differences
+    }
+
+    /// Short human-readable summary of the machine and toolchain, for use in reports.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        if let Some(hostname) = &self.hostname {
+            parts.push(hostname.clone());
+        }
+        match (&self.cpu_model, self.cpu_cores) {
+            (Some(model), Some(cores)) => parts.push(format!("{} ({} cores)", model, cores)),
+            (Some(model), None) => parts.push(model.clone()),
+            (None, Some(cores)) => parts.push(format!("{} cores", cores)),
+            (None, None) => {}
+        }
+        if let Some(rustc) = &self.rustc_version {
+            parts.push(rustc.clone());
+        }
+        parts.join(", ")
+    }
+}
+
+/// Extract the CPU model name and the number of logical cores from the contents of /proc/cpuinfo.
+fn parse_cpuinfo(cpuinfo: &str) -> (Option<String>, Option<usize>) {
+    let mut model = None;
+    let mut cores = 0;
+    for line in cpuinfo.lines() {
+        let mut split = line.splitn(2, ':');
+        let key = split.next().unwrap_or("").trim();
+        let value = split.next().unwrap_or("").trim();
+        match key {
+            "processor" => cores += 1,
+            // x86 uses "model name", some ARM kernels use "Model" or "Hardware" instead.
+            "model name" | "Model" | "Hardware" if model.is_none() && !value.is_empty() => {
+                model = Some(value.to_owned())
+            }
+            _ => {}
+        }
+    }
+    (model, if cores > 0 { Some(cores) } else { None })
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| command_output(Command::new("hostname")))
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+fn kernel_version() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .or_else(|| {
+            if cfg!(unix) {
+                let mut command = Command::new("uname");
+                command.arg("-r");
+                command_output(command)
+            } else {
+                None
+            }
+        })
+        .map(|version| version.trim().to_owned())
+        .filter(|version| !version.is_empty())
+}
+
+fn rustc_version() -> Option<String> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let mut command = Command::new(rustc);
+    command.arg("-vV");
+    command_output(command).and_then(|output| output.lines().next().map(ToOwned::to_owned))
+}
+
+/// Run the given command and return its stdout, or None if it failed for any reason.
+fn command_output(mut command: Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        debug!("Command {:?} failed with {}", command, output.status);
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpuinfo() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Core(TM) i7-8700K CPU @ 3.70GHz\n\n\
+                       processor\t: 1\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Core(TM) i7-8700K CPU @ 3.70GHz\n";
+        let (model, cores) = parse_cpuinfo(cpuinfo);
+        assert_eq!(
+            Some("Intel(R) Core(TM) i7-8700K CPU @ 3.70GHz".to_owned()),
+            model
+        );
+        assert_eq!(Some(2), cores);
+    }
+
+    #[test]
+    fn test_differences_ignore_unknown_values() {
+        let old = Environment {
+            hostname: Some("ci-runner-1".to_owned()),
+            rustc_version: None,
+            ..Default::default()
+        };
+        let new = Environment {
+            hostname: Some("laptop".to_owned()),
+            rustc_version: Some("rustc 1.53.0".to_owned()),
+            ..Default::default()
+        };
+        let differences = new.differences_from(&old);
+        assert_eq!(1, differences.len());
+        assert!(differences[0].contains("ci-runner-1"));
+    }
+
+    #[test]
+    fn test_differences_include_criterion_versions() {
+        let old = Environment {
+            cargo_criterion_version: Some("1.1.0".to_owned()),
+            criterion_rs_version: Some("0.3.4".to_owned()),
+            ..Default::default()
+        };
+        let new = Environment {
+            cargo_criterion_version: Some("1.1.0".to_owned()),
+            criterion_rs_version: Some("0.3.5".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["Criterion.rs version changed from '0.3.4' to '0.3.5'".to_owned()],
+            new.differences_from(&old)
+        );
+    }
+}
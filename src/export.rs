@@ -0,0 +1,395 @@
+//! Module implementing the `export` subcommand, which writes the stored measurements out as a
+//! results bundle (see the bundle module) or as a flat table (CSV or JSON Lines) for use with
+//! other tools.
+
+use crate::bundle;
+use crate::config::{ExportFormat, ExportOptions, FullConfig};
+use crate::connection::Throughput;
+use crate::estimate::Estimate;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    list_timelines, load_measurement, read_history_index, stored_benchmarks, timeline_directory,
+    ChangeDirection, HistoryRecord,
+};
+use crate::report::BenchmarkId;
+use crate::schema::SchemaError;
+use anyhow::{anyhow, Context, Result};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MEASUREMENT_COLUMNS: &[&str] = &[
+    "timeline",
+    "benchmark_id",
+    "group_id",
+    "function_id",
+    "value_str",
+    "datetime",
+    "history_id",
+    "history_description",
+    "git_commit",
+    "tags",
+    "confidence_level",
+    "mean",
+    "mean_lower",
+    "mean_upper",
+    "median",
+    "median_lower",
+    "median_upper",
+    "median_abs_dev",
+    "median_abs_dev_lower",
+    "median_abs_dev_upper",
+    "slope",
+    "slope_lower",
+    "slope_upper",
+    "std_dev",
+    "std_dev_lower",
+    "std_dev_upper",
+    "throughput_type",
+    "throughput_per_iteration",
+    "change_mean",
+    "change_mean_lower",
+    "change_mean_upper",
+    "change_median",
+    "change_median_lower",
+    "change_median_upper",
+    "change_direction",
+];
+
+const SAMPLE_COLUMNS: &[&str] = &[
+    "timeline",
+    "benchmark_id",
+    "datetime",
+    "sample",
+    "iterations",
+    "value",
+    "avg_value",
+];
+
+/// Entry point for the `export` subcommand.
+pub fn export(configuration: &FullConfig, options: &ExportOptions) -> Result<()> {
+    let format = match &options.format {
+        ExportFormat::Bundle(path) => return bundle::export(configuration, options, path),
+        ExportFormat::Csv => TableFormat::Csv,
+        ExportFormat::JsonLines => TableFormat::JsonLines,
+    };
+
+    let self_config = &configuration.self_config;
+    let criterion_home = &self_config.criterion_home;
+    let _lock =
+        DirectoryLock::acquire(criterion_home, LockKind::Shared, self_config.wait_for_lock)?;
+
+    let mut measurements = Table::create(format, options.output.as_deref(), MEASUREMENT_COLUMNS)?;
+    let mut samples = match &options.samples {
+        Some(path) => Some(Table::create(format, Some(path), SAMPLE_COLUMNS)?),
+        None => None,
+    };
+
+    let mut count = 0;
+    for (timeline, data_directory) in selected_timelines(criterion_home, options)? {
+        for (dir, record) in stored_benchmarks(&data_directory)? {
+            let id = BenchmarkId::from(record.id.clone());
+            if let Some(filter) = &options.filter {
+                if !filter.is_match(id.as_full_id()) {
+                    continue;
+                }
+            }
+
+            for entry in read_history_index(&dir)? {
+                let too_early = matches!(options.since, Some(since) if entry.datetime < since);
+                let too_late = matches!(options.until, Some(until) if entry.datetime > until);
+                if too_early || too_late || !entry.has_tags(&options.tags) {
+                    continue;
+                }
+                measurements.write_row(&measurement_row(&timeline, &id, &entry))?;
+                count += 1;
+
+                if let Some(samples) = &mut samples {
+                    let stats = match load_measurement(&dir.join(&entry.measurement)) {
+                        Ok(stats) => stats,
+                        Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+                        Err(e) => {
+                            warn!(
+                                "Skipping the samples of an unreadable measurement of {}: {:?}",
+                                id.as_title(),
+                                e
+                            );
+                            continue;
+                        }
+                    };
+                    let datetime = stats.datetime.to_rfc3339();
+                    for (i, ((iterations, value), avg_value)) in stats
+                        .iterations
+                        .iter()
+                        .zip(&stats.values)
+                        .zip(&stats.avg_values)
+                        .enumerate()
+                    {
+                        samples.write_row(&[
+                            Cell::text(&timeline),
+                            Cell::text(id.as_full_id()),
+                            Cell::text(&datetime),
+                            Cell::Integer(Some(i as u64)),
+                            Cell::Number(Some(*iterations)),
+                            Cell::Number(Some(*value)),
+                            Cell::Number(Some(*avg_value)),
+                        ])?;
+                    }
+                }
+            }
+        }
+    }
+
+    measurements.finish()?;
+    if let Some(samples) = samples {
+        samples.finish()?;
+    }
+    eprintln!("Exported {} measurements.", count);
+    Ok(())
+}
+
+/// Returns the names and data directories of the timelines selected by the given options.
+pub fn selected_timelines(
+    criterion_home: &Path,
+    options: &ExportOptions,
+) -> Result<Vec<(String, PathBuf)>> {
+    let names = if options.timelines.is_empty() {
+        list_timelines(criterion_home)?
+    } else {
+        options.timelines.clone()
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let data_directory = timeline_directory(criterion_home, &name)?;
+            if !data_directory.is_dir() {
+                return Err(anyhow!("There is no timeline named '{}'", name));
+            }
+            Ok((name, data_directory))
+        })
+        .collect()
+}
+
+fn measurement_row(timeline: &str, id: &BenchmarkId, entry: &HistoryRecord) -> Vec<Cell> {
+    let estimates = &entry.estimates;
+    let mut row = vec![
+        Cell::text(timeline),
+        Cell::text(id.as_full_id()),
+        Cell::text(&id.group_id),
+        Cell::Text(id.function_id.clone()),
+        Cell::Text(id.value_str.clone()),
+        Cell::text(&entry.datetime.to_rfc3339()),
+        Cell::Text(entry.history_id.clone()),
+        Cell::Text(entry.history_description.clone()),
+        Cell::Text(entry.git.as_ref().map(|git| git.commit.clone())),
+        Cell::Json(serde_json::to_value(&entry.tags).unwrap()),
+        Cell::Number(Some(estimates.mean.confidence_interval.confidence_level)),
+    ];
+    push_estimate(&mut row, Some(&estimates.mean));
+    push_estimate(&mut row, Some(&estimates.median));
+    push_estimate(&mut row, Some(&estimates.median_abs_dev));
+    push_estimate(&mut row, estimates.slope.as_ref());
+    push_estimate(&mut row, Some(&estimates.std_dev));
+
+    let (throughput_type, throughput) = match entry.throughput {
+        Some(Throughput::Bytes(n)) => (Some("bytes"), Some(n)),
+        Some(Throughput::BytesDecimal(n)) => (Some("bytes_decimal"), Some(n)),
+        Some(Throughput::Elements(n)) => (Some("elements"), Some(n)),
+        None => (None, None),
+    };
+    row.push(Cell::Text(throughput_type.map(ToOwned::to_owned)));
+    row.push(Cell::Integer(throughput));
+
+    let changes = entry.changes.as_ref();
+    push_estimate(&mut row, changes.map(|changes| &changes.mean));
+    push_estimate(&mut row, changes.map(|changes| &changes.median));
+    let change_direction = entry.change_direction.as_ref().map(ChangeDirection::name);
+    row.push(Cell::Text(change_direction.map(ToOwned::to_owned)));
+    row
+}
+
+/// Add the point estimate and confidence interval bounds of the given estimate to the row.
+fn push_estimate(row: &mut Vec<Cell>, estimate: Option<&Estimate>) {
+    row.push(Cell::Number(estimate.map(|e| e.point_estimate)));
+    row.push(Cell::Number(
+        estimate.map(|e| e.confidence_interval.lower_bound),
+    ));
+    row.push(Cell::Number(
+        estimate.map(|e| e.confidence_interval.upper_bound),
+    ));
+}
+
+/// A single value in a table. None is written as an empty field in CSV and as null in JSON.
+/// Structured values (eg. tags) are nested in JSON and written as JSON text in CSV, so that
+/// any separators inside them can't be misread.
+enum Cell {
+    Text(Option<String>),
+    Number(Option<f64>),
+    Integer(Option<u64>),
+    Json(serde_json::Value),
+}
+impl Cell {
+    fn text(text: &str) -> Cell {
+        Cell::Text(Some(text.to_owned()))
+    }
+
+    fn to_csv(&self) -> String {
+        match self {
+            Cell::Text(Some(text)) => csv_escape(text),
+            Cell::Number(Some(number)) => number.to_string(),
+            Cell::Integer(Some(number)) => number.to_string(),
+            Cell::Json(value) => csv_escape(&value.to_string()),
+            Cell::Text(None) | Cell::Number(None) | Cell::Integer(None) => String::new(),
+        }
+    }
+}
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Text(Some(text)) => serializer.serialize_str(text),
+            Cell::Number(Some(number)) => serializer.serialize_f64(*number),
+            Cell::Integer(Some(number)) => serializer.serialize_u64(*number),
+            Cell::Json(value) => value.serialize(serializer),
+            Cell::Text(None) | Cell::Number(None) | Cell::Integer(None) => {
+                serializer.serialize_none()
+            }
+        }
+    }
+}
+
+/// Quote a CSV field if it contains anything that would otherwise be misread.
+fn csv_escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TableFormat {
+    Csv,
+    JsonLines,
+}
+
+/// Writes rows with a fixed set of columns to a file or to stdout.
+struct Table {
+    format: TableFormat,
+    columns: &'static [&'static str],
+    writer: BufWriter<Box<dyn Write>>,
+}
+impl Table {
+    fn create(
+        format: TableFormat,
+        path: Option<&Path>,
+        columns: &'static [&'static str],
+    ) -> Result<Table> {
+        let writer: Box<dyn Write> = match path {
+            Some(path) => Box::new(
+                File::create(path).with_context(|| format!("Failed to create {:?}", path))?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+        let mut table = Table {
+            format,
+            columns,
+            writer: BufWriter::new(writer),
+        };
+        if let TableFormat::Csv = format {
+            writeln!(table.writer, "{}", columns.join(","))?;
+        }
+        Ok(table)
+    }
+
+    fn write_row(&mut self, row: &[Cell]) -> Result<()> {
+        debug_assert_eq!(row.len(), self.columns.len());
+        match self.format {
+            TableFormat::Csv => {
+                let fields: Vec<String> = row.iter().map(Cell::to_csv).collect();
+                writeln!(self.writer, "{}", fields.join(","))?;
+            }
+            TableFormat::JsonLines => {
+                serde_json::to_writer(
+                    &mut self.writer,
+                    &JsonRow {
+                        columns: self.columns,
+                        row,
+                    },
+                )?;
+                writeln!(self.writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Serializes a row as a JSON object, keeping the columns in order.
+struct JsonRow<'a> {
+    columns: &'a [&'a str],
+    row: &'a [Cell],
+}
+impl Serialize for JsonRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, cell) in self.columns.iter().zip(self.row) {
+            map.serialize_entry(column, cell)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("fib/20"), "fib/20");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_json_row_keeps_column_order() {
+        let row = [Cell::text("b"), Cell::Number(Some(1.5)), Cell::Number(None)];
+        let json = serde_json::to_string(&JsonRow {
+            columns: &["z", "a", "m"],
+            row: &row,
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"z":"b","a":1.5,"m":null}"#);
+    }
+
+    #[test]
+    fn test_tags_cell() {
+        let mut tags = BTreeMap::new();
+        tags.insert("flags".to_owned(), "a=b;c=d".to_owned());
+        tags.insert("say".to_owned(), "\"hi\", twice".to_owned());
+        let cell = Cell::Json(serde_json::to_value(&tags).unwrap());
+
+        let json = serde_json::to_string(&cell).unwrap();
+        assert_eq!(json, r#"{"flags":"a=b;c=d","say":"\"hi\", twice"}"#);
+        let csv = cell.to_csv();
+        assert_eq!(
+            csv,
+            r#""{""flags"":""a=b;c=d"",""say"":""\""hi\"", twice""}""#
+        );
+
+        // Both round-trip to the original tags.
+        let parsed: BTreeMap<String, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, tags);
+        let unquoted = csv[1..csv.len() - 1].replace("\"\"", "\"");
+        assert_eq!(unquoted, json);
+
+        let empty = Cell::Json(serde_json::to_value(BTreeMap::<String, String>::new()).unwrap());
+        assert_eq!(empty.to_csv(), "{}");
+    }
+}
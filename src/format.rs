@@ -20,6 +20,19 @@ pub fn time(ns: f64) -> String {
     }
 }
 
+pub fn bytes(bytes: u64) -> String {
+    let bytes = bytes as f64;
+    if bytes < 1024.0 {
+        format!("{:>6} B", bytes)
+    } else if bytes < 1024f64.powi(2) {
+        format!("{:>6} KiB", short(bytes / 1024.0))
+    } else if bytes < 1024f64.powi(3) {
+        format!("{:>6} MiB", short(bytes / 1024f64.powi(2)))
+    } else {
+        format!("{:>6} GiB", short(bytes / 1024f64.powi(3)))
+    }
+}
+
 pub fn short(n: f64) -> String {
     if n < 10.0 {
         format!("{:.4}", n)
@@ -91,6 +104,13 @@ mod test {
         }
     }
 
+    #[test]
+    fn bytes_units() {
+        assert_eq!(bytes(512), "   512 B");
+        assert_eq!(bytes(1536), "1.5000 KiB");
+        assert_eq!(bytes(12 * 1024 * 1024), "12.000 MiB");
+    }
+
     #[test]
     fn signed_short_max_len() {
         let mut float = -1.0;
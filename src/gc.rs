@@ -0,0 +1,448 @@
+//! Module implementing the `gc` subcommand, which deletes stored data that is no longer needed.
+//!
+//! There are two kinds of garbage. Old measurements are deleted according to the retention
+//! policy configured in criterion.toml, and the data and reports for benchmarks which no longer
+//! exist in any benchmark target are deleted entirely.
+
+use crate::bench_target::BenchTarget;
+use crate::compile;
+use crate::config::{FullConfig, GcOptions, RetentionPolicy};
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    read_history_index, remove_from_history_index, stored_benchmarks, SavedBenchmarkId,
+};
+use crate::report::BenchmarkId;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Delete the measurements in the given benchmark directory which are not allowed by the
+/// retention policy. The measurement referenced by `latest_record` is always kept, since it is
+/// the baseline that the next run will be compared against. Returns the paths of the deleted
+/// measurements (or, if `dry_run` is set, the measurements which would have been deleted).
+pub fn prune_measurements(
+    dir: &Path,
+    latest_record: &Path,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>> {
+    // This mustn't write anything in a dry run, which only holds the shared lock.
+    let mut records = read_history_index(dir)?;
+    records.retain(|record| record.measurement != latest_record);
+    records.sort_by_key(|record| std::cmp::Reverse(record.datetime));
+
+    let now = Utc::now();
+    // The latest measurement counts towards keep_last.
+    let mut kept = 1;
+    let mut deleted = vec![];
+    for record in records {
+        if policy.keep_tagged && record.is_tagged() {
+            continue;
+        }
+        let too_many = policy.keep_last.map(|n| kept >= n).unwrap_or(false);
+        let too_old = policy
+            .max_age()
+            .map(|max_age| now.signed_duration_since(record.datetime) > max_age)
+            .unwrap_or(false);
+        if !(too_many || too_old) {
+            kept += 1;
+            continue;
+        }
+
+        let path = dir.join(&record.measurement);
+        if !dry_run {
+            match fs::remove_file(&path) {
+                // The measurement may have been deleted by hand; just drop it from the index.
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                other => {
+                    other.with_context(|| format!("Failed to delete measurement {:?}", path))?
+                }
+            }
+        }
+        deleted.push(record.measurement);
+    }
+
+    if !dry_run && !deleted.is_empty() {
+        remove_from_history_index(dir, &deleted)?;
+    }
+    Ok(deleted
+        .into_iter()
+        .map(|measurement| dir.join(measurement))
+        .collect())
+}
+
+/// Entry point for the `gc` subcommand.
+pub fn gc(configuration: &FullConfig, options: &GcOptions) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let data_directory = path!(&self_config.criterion_home, "data", "main");
+    let reports_directory = self_config.criterion_home.join("reports");
+    let lock_kind = if options.dry_run {
+        LockKind::Shared
+    } else {
+        LockKind::Exclusive
+    };
+    let _lock = DirectoryLock::acquire(
+        &self_config.criterion_home,
+        lock_kind,
+        self_config.wait_for_lock,
+    )?;
+    let verb = if options.dry_run {
+        "Would delete"
+    } else {
+        "Deleted"
+    };
+
+    let mut deleted_measurements = 0;
+    if self_config.retention.is_enabled() {
+        for (dir, record) in stored_benchmarks(&data_directory)? {
+            let deleted = prune_measurements(
+                &dir,
+                &record.latest_record,
+                &self_config.retention,
+                options.dry_run,
+            )?;
+            for path in &deleted {
+                debug!("{} {:?}", verb, path);
+            }
+            deleted_measurements += deleted.len();
+        }
+    } else {
+        info!("No retention policy is configured; keeping all measurements.");
+    }
+
+    let mut deleted_benchmarks = 0;
+    if !options.measurements_only {
+        let compiled = compile::compile(
+            self_config.debug_build,
+            &configuration.cargo_args,
+            &self_config.runners,
+        )?;
+        let mut live_benchmarks = LiveBenchmarks::default();
+        for target in &compiled.targets {
+            let listed = target.list_benchmarks(&compiled.library_paths)?;
+            if listed.is_empty() {
+                // This is more likely to mean that the target can't list its benchmarks (eg. it
+                // doesn't use Criterion.rs, or an old version) than that they were all removed.
+                info!(
+                    "Benchmark target {} didn't list any benchmarks; keeping its stored benchmarks.",
+                    target.name
+                );
+            }
+            live_benchmarks.add_target(target, listed);
+        }
+
+        for (dir, record) in stored_benchmarks(&data_directory)? {
+            if !live_benchmarks.is_removed(&record.id) {
+                continue;
+            }
+            let id: BenchmarkId = record.id.into();
+            eprintln!("{} removed benchmark {}", verb, id.as_title());
+            if !options.dry_run {
+                let relative = dir.strip_prefix(&data_directory)?;
+                remove_benchmark(&data_directory, &reports_directory, relative)?;
+            }
+            deleted_benchmarks += 1;
+        }
+    }
+
+    eprintln!(
+        "{} {} old measurements and {} removed benchmarks.",
+        verb, deleted_measurements, deleted_benchmarks
+    );
+    Ok(())
+}
+
+/// The benchmarks listed by each benchmark target, for finding the stored benchmarks which have
+/// been removed.
+#[derive(Default)]
+struct LiveBenchmarks {
+    // The full IDs listed by each target, by package and target name.
+    targets: HashMap<(String, String), HashSet<String>>,
+}
+impl LiveBenchmarks {
+    fn add_target(&mut self, target: &BenchTarget, listed: Vec<String>) {
+        self.targets.insert(
+            (target.package.clone(), target.name.clone()),
+            listed.into_iter().collect(),
+        );
+    }
+
+    /// Returns true if the given stored benchmark is no longer listed by the benchmark target it
+    /// was recorded from. Benchmarks which were recorded without their target (by older versions
+    /// of cargo-criterion) are looked for in every target of their package, or every target if
+    /// the package isn't known either. When in doubt, because a candidate target listed nothing or
+    /// none of them was built, the benchmark is kept.
+    fn is_removed(&self, saved_id: &SavedBenchmarkId) -> bool {
+        let id = BenchmarkId::from(saved_id.clone());
+        let package = saved_id.package.as_deref().or_else(|| id.namespace());
+        let candidates: Vec<&HashSet<String>> = (self.targets.iter())
+            .filter(|((target_package, target_name), _)| {
+                (package.is_none() || package == Some(target_package.as_str()))
+                    && (saved_id.target.is_none() || saved_id.target.as_ref() == Some(target_name))
+            })
+            .map(|(_, listed)| listed)
+            .collect();
+        !candidates.is_empty()
+            && (candidates.iter())
+                .all(|listed| !listed.is_empty() && !listed.contains(id.as_full_id()))
+    }
+}
+
+/// Delete the stored data and the reports for the benchmark in the given directory (relative to
+/// the data directory), along with any parent directories which no longer contain benchmarks.
+/// Other benchmarks may be nested inside this one's directory, so those are left alone.
+pub fn remove_benchmark(
+    data_directory: &Path,
+    reports_directory: &Path,
+    relative: &Path,
+) -> Result<()> {
+    let data_dir = data_directory.join(relative);
+    for entry in fs::read_dir(&data_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to delete {:?}", entry.path()))?;
+        }
+    }
+
+    let report_dir = reports_directory.join(relative);
+    if report_dir.is_dir() {
+        for entry in fs::read_dir(&report_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                fs::remove_file(entry.path())?;
+            } else if !data_dir.join(entry.file_name()).exists() {
+                fs::remove_dir_all(entry.path())
+                    .with_context(|| format!("Failed to delete {:?}", entry.path()))?;
+            }
+        }
+    }
+
+    // Walk up the tree, removing directories which don't contain any benchmarks any more.
+    for ancestor in relative.ancestors() {
+        if ancestor.as_os_str().is_empty() || contains_benchmarks(&data_directory.join(ancestor)) {
+            break;
+        }
+        fs::remove_dir_all(data_directory.join(ancestor))?;
+        let report_dir = reports_directory.join(ancestor);
+        if report_dir.is_dir() {
+            fs::remove_dir_all(&report_dir)
+                .with_context(|| format!("Failed to delete {:?}", report_dir))?;
+        }
+    }
+    Ok(())
+}
+
+fn contains_benchmarks(dir: &Path) -> bool {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(::std::result::Result::ok)
+        .any(|entry| entry.file_name() == OsStr::new("benchmark.cbor"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::test::{save_measurement, saved_statistics};
+
+    fn target(package: &str, name: &str) -> BenchTarget {
+        BenchTarget {
+            name: name.to_owned(),
+            package: package.to_owned(),
+            executable: PathBuf::from(name),
+            runner: None,
+        }
+    }
+
+    fn saved_id(
+        full_id: &str,
+        namespace: Option<&str>,
+        target: Option<(&str, &str)>,
+    ) -> SavedBenchmarkId {
+        let mut parts = full_id.splitn(2, '/').map(ToOwned::to_owned);
+        let id = BenchmarkId::new(parts.next().unwrap(), parts.next(), None, None)
+            .in_namespace(namespace);
+        match target {
+            Some((package, name)) => SavedBenchmarkId::from(id).with_target(package, name),
+            None => SavedBenchmarkId::from(id),
+        }
+    }
+
+    fn live_benchmarks() -> LiveBenchmarks {
+        let long_id = format!("group/{}", "x".repeat(100));
+        let mut live = LiveBenchmarks::default();
+        live.add_target(&target("a", "bench"), vec!["fib/10".to_owned(), long_id]);
+        live.add_target(&target("a", "plain"), vec![]);
+        live.add_target(&target("b", "bench"), vec!["sort/100".to_owned()]);
+        live
+    }
+
+    #[test]
+    fn test_removed_from_recorded_target() {
+        let live = live_benchmarks();
+        assert!(!live.is_removed(&saved_id("fib/10", None, Some(("a", "bench")))));
+        assert!(live.is_removed(&saved_id("fib/20", None, Some(("a", "bench")))));
+        // Benchmarks are matched by their full ID, not their (possibly truncated) title.
+        let long_id = format!("group/{}", "x".repeat(100));
+        assert!(!live.is_removed(&saved_id(&long_id, None, Some(("a", "bench")))));
+        // Still listed, but by another package's target.
+        assert!(live.is_removed(&saved_id("sort/100", None, Some(("a", "bench")))));
+    }
+
+    #[test]
+    fn test_kept_when_target_listed_nothing() {
+        let live = live_benchmarks();
+        assert!(!live.is_removed(&saved_id("fib/20", None, Some(("a", "plain")))));
+        // The target wasn't built, eg. because of the cargo arguments.
+        assert!(!live.is_removed(&saved_id("fib/20", None, Some(("c", "bench")))));
+    }
+
+    #[test]
+    fn test_removed_without_recorded_target() {
+        let live = live_benchmarks();
+        // Package b's only target listed something, so its benchmarks can be judged...
+        assert!(!live.is_removed(&saved_id("sort/100", Some("b"), None)));
+        assert!(live.is_removed(&saved_id("fib/10", Some("b"), None)));
+        // ...but package a has a target which listed nothing.
+        assert!(!live.is_removed(&saved_id("sort/100", Some("a"), None)));
+        assert!(!live.is_removed(&saved_id("gone", None, None)));
+    }
+
+    #[test]
+    fn test_remove_benchmark() {
+        let root = tempfile::tempdir().unwrap();
+        let data_directory = root.path().join("data");
+        let reports_directory = root.path().join("reports");
+        for dir in &["group/removed", "group/removed/nested", "group/kept"] {
+            fs::create_dir_all(data_directory.join(dir)).unwrap();
+            fs::write(path!(&data_directory, dir, "benchmark.cbor"), b"").unwrap();
+            fs::create_dir_all(reports_directory.join(dir)).unwrap();
+            fs::write(path!(&reports_directory, dir, "index.html"), b"").unwrap();
+        }
+        fs::create_dir_all(path!(&reports_directory, "group/removed/report")).unwrap();
+
+        remove_benchmark(
+            &data_directory,
+            &reports_directory,
+            Path::new("group/removed"),
+        )
+        .unwrap();
+        // The nested benchmark is left alone.
+        assert!(!path!(&data_directory, "group/removed/benchmark.cbor").exists());
+        assert!(path!(&data_directory, "group/removed/nested/benchmark.cbor").exists());
+        assert!(!path!(&reports_directory, "group/removed/index.html").exists());
+        assert!(!path!(&reports_directory, "group/removed/report").exists());
+        assert!(path!(&reports_directory, "group/removed/nested/index.html").exists());
+
+        remove_benchmark(
+            &data_directory,
+            &reports_directory,
+            Path::new("group/removed/nested"),
+        )
+        .unwrap();
+        assert!(!data_directory.join("group/removed").exists());
+        assert!(!reports_directory.join("group/removed").exists());
+        assert!(path!(&data_directory, "group/kept/benchmark.cbor").exists());
+        assert!(path!(&reports_directory, "group/kept/index.html").exists());
+    }
+
+    /// Save measurements taken the given numbers of days ago, named after their age, eg.
+    /// `measurement_3.cbor`. Returns the name of the newest.
+    fn save_measurements(dir: &Path, ages: &[(i64, Option<&str>)]) -> PathBuf {
+        let now = Utc::now().timestamp();
+        for (age, tag) in ages {
+            let mut stats = saved_statistics(now - age * 24 * 60 * 60, 1.0);
+            if let Some(tag) = tag {
+                stats.tags.insert("tag".to_owned(), (*tag).to_owned());
+            }
+            save_measurement(dir, &format!("measurement_{}.cbor", age), &stats);
+        }
+        PathBuf::from(format!("measurement_{}.cbor", ages[0].0))
+    }
+
+    fn file_names(paths: &[PathBuf]) -> Vec<&str> {
+        let mut names: Vec<&str> = (paths.iter())
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn test_prune_keep_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let latest = save_measurements(dir.path(), &[(1, None), (2, None), (3, None), (4, None)]);
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..RetentionPolicy::default()
+        };
+
+        let deleted = prune_measurements(dir.path(), &latest, &policy, true).unwrap();
+        assert_eq!(
+            file_names(&deleted),
+            vec!["measurement_3.cbor", "measurement_4.cbor"]
+        );
+        assert!(dir.path().join("measurement_4.cbor").exists());
+        assert!(!dir.path().join(crate::model::HISTORY_INDEX).exists());
+
+        let deleted = prune_measurements(dir.path(), &latest, &policy, false).unwrap();
+        assert_eq!(deleted.len(), 2);
+        assert!(dir.path().join("measurement_2.cbor").exists());
+        assert!(!dir.path().join("measurement_3.cbor").exists());
+        assert!(!dir.path().join("measurement_4.cbor").exists());
+        assert_eq!(read_history_index(dir.path()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_prune_max_age_days() {
+        let dir = tempfile::tempdir().unwrap();
+        let latest = save_measurements(dir.path(), &[(1, None), (5, None), (10, None)]);
+        let policy = RetentionPolicy {
+            max_age_days: Some(7),
+            ..RetentionPolicy::default()
+        };
+        let deleted = prune_measurements(dir.path(), &latest, &policy, false).unwrap();
+        assert_eq!(file_names(&deleted), vec!["measurement_10.cbor"]);
+    }
+
+    #[test]
+    fn test_prune_keep_tagged() {
+        let dir = tempfile::tempdir().unwrap();
+        let ages = [(1, None), (10, Some("release")), (20, None)];
+        let latest = save_measurements(dir.path(), &ages);
+        let mut policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let deleted = prune_measurements(dir.path(), &latest, &policy, true).unwrap();
+        assert_eq!(file_names(&deleted), vec!["measurement_20.cbor"]);
+
+        policy.keep_tagged = false;
+        let deleted = prune_measurements(dir.path(), &latest, &policy, true).unwrap();
+        assert_eq!(
+            file_names(&deleted),
+            vec!["measurement_10.cbor", "measurement_20.cbor"]
+        );
+    }
+
+    #[test]
+    fn test_prune_never_deletes_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        save_measurements(dir.path(), &[(1, None), (100, None)]);
+        // The latest record isn't necessarily the newest measurement, eg. after a reanalysis.
+        let latest = PathBuf::from("measurement_100.cbor");
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            max_age_days: Some(0),
+            keep_tagged: false,
+        };
+        let deleted = prune_measurements(dir.path(), &latest, &policy, false).unwrap();
+        assert_eq!(file_names(&deleted), vec!["measurement_1.cbor"]);
+        assert!(dir.path().join(&latest).exists());
+    }
+}
@@ -0,0 +1,236 @@
+//! Module implementing the `history` subcommand, which prints the history of some benchmarks to
+//! the terminal, as an alternative to opening the history reports.
+
+use crate::config::{FullConfig, HistoryOptions, RetentionPolicy};
+use crate::environment::Environment;
+use crate::format;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{timeline_directory, ChangeDirection, HistoryRecord, Model};
+use crate::report::BenchmarkId;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const SPARKLINE_CHARACTERS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Debug, Serialize)]
+struct JsonBenchmark<'a> {
+    id: &'a str,
+    entries: Vec<JsonEntry<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonEntry<'a> {
+    datetime: DateTime<Utc>,
+    history_id: Option<&'a str>,
+    history_description: Option<&'a str>,
+    // The estimates are in the unit of the measured values, eg. "ns" for wall-clock time.
+    unit: Option<&'a str>,
+    estimate: f64,
+    lower_bound: f64,
+    upper_bound: f64,
+    // Relative change of the estimate from the previous entry, eg. 0.05 for 5% slower.
+    change: Option<f64>,
+    change_direction: Option<&'static str>,
+    tags: &'a BTreeMap<String, String>,
+}
+
+/// Entry point for the `history` subcommand.
+pub fn history(configuration: &FullConfig, options: &HistoryOptions) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let _lock = DirectoryLock::acquire(
+        &self_config.criterion_home,
+        LockKind::Shared,
+        self_config.wait_for_lock,
+    )?;
+
+    // Make sure that the timeline name can't refer to anything outside of the data directory.
+    timeline_directory(&self_config.criterion_home, &options.timeline)?;
+    let model = Model::load(
+        self_config.criterion_home.clone(),
+        options.timeline.clone().into(),
+        None,
+        None,
+        Environment::default(),
+        None,
+        RetentionPolicy::default(),
+    )?;
+
+    let mut histories = vec![];
+    for group in model.groups.values() {
+        for id in group.benchmarks.keys() {
+            if !options.filter.is_match(id.as_full_id()) {
+                continue;
+            }
+            let mut history = model.load_history(id)?;
+            history.retain(|record| record.has_tags(&options.tags));
+            if let Some(limit) = options.limit {
+                let skip = history.len().saturating_sub(limit);
+                history.drain(..skip);
+            }
+            histories.push((id, history));
+        }
+    }
+    if histories.is_empty() {
+        return Err(anyhow!(
+            "No stored benchmark has an ID matching '{}'",
+            options.filter
+        ));
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if options.json {
+        let benchmarks: Vec<JsonBenchmark> = histories
+            .iter()
+            .map(|(id, history)| JsonBenchmark {
+                id: id.as_full_id(),
+                entries: json_entries(history),
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut stdout, &benchmarks)?;
+        writeln!(stdout)?;
+    } else {
+        for (id, history) in &histories {
+            print_table(&mut stdout, id, history)?;
+        }
+    }
+    Ok(())
+}
+
+fn json_entries(history: &[HistoryRecord]) -> Vec<JsonEntry<'_>> {
+    history
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let estimate = record.estimates.typical();
+            JsonEntry {
+                datetime: record.datetime,
+                history_id: record.history_id.as_deref(),
+                history_description: record.history_description.as_deref(),
+                unit: record.unit.as_deref(),
+                estimate: estimate.point_estimate,
+                lower_bound: estimate.confidence_interval.lower_bound,
+                upper_bound: estimate.confidence_interval.upper_bound,
+                change: change_from_previous(history, i),
+                change_direction: record.change_direction.as_ref().map(ChangeDirection::name),
+                tags: &record.tags,
+            }
+        })
+        .collect()
+}
+
+fn print_table(out: &mut dyn Write, id: &BenchmarkId, history: &[HistoryRecord]) -> Result<()> {
+    let points: Vec<f64> = history
+        .iter()
+        .map(|record| record.estimates.typical().point_estimate)
+        .collect();
+    writeln!(out, "{}  {}", id.as_title(), sparkline(&points))?;
+    writeln!(
+        out,
+        "  {:<16}  {:<12}  {:<34}  {:<8}  Direction",
+        "Date", "History ID", "Estimate", "Change"
+    )?;
+    for (i, record) in history.iter().enumerate() {
+        let estimate = record.estimates.typical();
+        let interval = format!(
+            "[{} {} {}]",
+            format_value(record, estimate.confidence_interval.lower_bound),
+            format_value(record, estimate.point_estimate),
+            format_value(record, estimate.confidence_interval.upper_bound)
+        );
+        let change = change_from_previous(history, i)
+            .map(|change| format::change(change, true))
+            .unwrap_or_default();
+        let direction = record
+            .change_direction
+            .as_ref()
+            .map(|direction| direction.name().replace('_', " "))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "  {:<16}  {:<12}  {:<34}  {:<8}  {}",
+            record.datetime.format("%Y-%m-%d %H:%M"),
+            record.history_id.as_deref().unwrap_or(""),
+            interval,
+            change,
+            direction
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Format a value of the given measurement in the unit it was recorded in. Measurements without
+/// a unit were saved by older versions, and are assumed to be wall-clock times.
+fn format_value(record: &HistoryRecord, value: f64) -> String {
+    match record.unit.as_deref() {
+        None | Some("ns") => format::time(value),
+        Some(unit) => format!("{} {}", format::short(value), unit),
+    }
+}
+
+/// The relative change of the typical estimate of the given entry from the entry before it.
+fn change_from_previous(history: &[HistoryRecord], i: usize) -> Option<f64> {
+    let previous = history
+        .get(i.checked_sub(1)?)?
+        .estimates
+        .typical()
+        .point_estimate;
+    let current = history[i].estimates.typical().point_estimate;
+    Some((current - previous) / previous)
+}
+
+/// Draw the given values as a line of block characters, scaled so that the smallest value is
+/// the lowest block and the largest is the highest.
+fn sparkline(values: &[f64]) -> String {
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let steps = (SPARKLINE_CHARACTERS.len() - 1) as f64;
+    values
+        .iter()
+        .map(|value| {
+            let level = if max > min {
+                ((value - min) / (max - min) * steps).round() as usize
+            } else {
+                0
+            };
+            SPARKLINE_CHARACTERS[level]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::test::saved_statistics;
+    use crate::model::SavedStatistics;
+
+    #[test]
+    fn test_format_value_in_recorded_unit() {
+        let record = |unit: Option<&str>| {
+            let stats = SavedStatistics {
+                unit: unit.map(ToOwned::to_owned),
+                ..saved_statistics(1_000, 1.0)
+            };
+            HistoryRecord::new("measurement_1000.cbor".into(), &stats)
+        };
+        assert_eq!(format_value(&record(None), 1500.0), format::time(1500.0));
+        assert_eq!(
+            format_value(&record(Some("ns")), 1500.0),
+            format::time(1500.0)
+        );
+        assert_eq!(
+            format_value(&record(Some("cycles")), 1500.0),
+            "1500.0 cycles"
+        );
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[5.0, 5.0]), "▁▁");
+        assert_eq!(sparkline(&[1.0, 8.0, 4.5]), "▁█▅");
+    }
+}
@@ -1,8 +1,8 @@
 use crate::estimate::Estimate;
 use crate::format;
 use crate::model::{
-    Benchmark as BenchmarkModel, BenchmarkGroup as GroupModel, ChangeDirection, Model,
-    SavedStatistics,
+    Benchmark as BenchmarkModel, BenchmarkGroup as GroupModel, ChangeDirection, HistoryRecord,
+    Model,
 };
 use crate::plot::{PlotContext, Plotter, Size};
 use crate::report::{
@@ -11,8 +11,10 @@ use crate::report::{
 };
 use crate::stats::bivariate::regression::Slope;
 use crate::stats::univariate::Sample;
+use crate::timing;
 use crate::value_formatter::ValueFormatter;
 use anyhow::{Context as AnyhowContext, Result};
+use linked_hash_map::LinkedHashMap;
 use linked_hash_set::LinkedHashSet;
 use serde::Serialize;
 use std::cell::RefCell;
@@ -161,6 +163,7 @@ struct Comparison {
     change: ConfidenceInterval,
     thrpt_change: Option<ConfidenceInterval>,
     additional_plots: Vec<Plot>,
+    environment_changes: Vec<String>,
 }
 
 fn if_exists(output_directory: &Path, path: &Path) -> Option<String> {
@@ -171,6 +174,16 @@ fn if_exists(output_directory: &Path, path: &Path) -> Option<String> {
         None
     }
 }
+/// Returns the directory of the given group's reports, relative to the output directory.
+fn group_path(namespace: Option<&str>, group_id: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    if let Some(namespace) = namespace {
+        path.push(make_filename_safe(namespace));
+    }
+    path.push(make_filename_safe(group_id));
+    path
+}
+
 #[derive(Serialize, Debug)]
 struct ReportLink<'a> {
     name: &'a str,
@@ -178,8 +191,12 @@ struct ReportLink<'a> {
 }
 impl<'a> ReportLink<'a> {
     // TODO: Would be nice if I didn't have to keep making these components filename-safe.
-    fn group(output_directory: &Path, group_id: &'a str) -> ReportLink<'a> {
-        let path = PathBuf::from(make_filename_safe(group_id));
+    fn group(
+        output_directory: &Path,
+        namespace: Option<&str>,
+        group_id: &'a str,
+    ) -> ReportLink<'a> {
+        let path = group_path(namespace, group_id);
 
         ReportLink {
             name: group_id,
@@ -187,8 +204,13 @@ impl<'a> ReportLink<'a> {
         }
     }
 
-    fn function(output_directory: &Path, group_id: &str, function_id: &'a str) -> ReportLink<'a> {
-        let mut path = PathBuf::from(make_filename_safe(group_id));
+    fn function(
+        output_directory: &Path,
+        namespace: Option<&str>,
+        group_id: &str,
+        function_id: &'a str,
+    ) -> ReportLink<'a> {
+        let mut path = group_path(namespace, group_id);
         path.push(make_filename_safe(function_id));
 
         ReportLink {
@@ -197,8 +219,13 @@ impl<'a> ReportLink<'a> {
         }
     }
 
-    fn value(output_directory: &Path, group_id: &str, value_str: &'a str) -> ReportLink<'a> {
-        let mut path = PathBuf::from(make_filename_safe(group_id));
+    fn value(
+        output_directory: &Path,
+        namespace: Option<&str>,
+        group_id: &str,
+        value_str: &'a str,
+    ) -> ReportLink<'a> {
+        let mut path = group_path(namespace, group_id);
         path.push(make_filename_safe(value_str));
 
         ReportLink {
@@ -232,12 +259,13 @@ struct BenchmarkGroup<'a> {
     individual_links: Vec<BenchmarkValueGroup<'a>>,
 }
 impl<'a> BenchmarkGroup<'a> {
-    fn new(
-        output_directory: &Path,
-        group_id: &'a str,
-        group: &'a GroupModel,
-    ) -> BenchmarkGroup<'a> {
-        let group_report = ReportLink::group(output_directory, group_id);
+    fn new(output_directory: &Path, group: &'a GroupModel) -> Option<BenchmarkGroup<'a>> {
+        // The group's key in the model may be prefixed with the package name, so take the
+        // group ID and namespace from its benchmarks instead.
+        let first_id = group.benchmarks.keys().next()?;
+        let group_id = first_id.group_id.as_str();
+        let namespace = first_id.namespace();
+        let group_report = ReportLink::group(output_directory, namespace, group_id);
 
         let mut function_ids = LinkedHashSet::new();
         let mut values = LinkedHashSet::new();
@@ -262,33 +290,77 @@ impl<'a> BenchmarkGroup<'a> {
                 .filter_map(|f| individual_links.remove(&(*f, *value)))
                 .collect::<Vec<_>>();
             value_groups.push(BenchmarkValueGroup {
-                value: value.map(|s| ReportLink::value(output_directory, group_id, s)),
+                value: value.map(|s| ReportLink::value(output_directory, namespace, group_id, s)),
                 benchmarks: row,
             });
         }
 
         let function_ids = function_ids
             .into_iter()
-            .map(|os| os.map(|s| ReportLink::function(output_directory, group_id, s)))
+            .map(|os| os.map(|s| ReportLink::function(output_directory, namespace, group_id, s)))
             .collect::<Option<Vec<_>>>();
         let values = values
             .into_iter()
-            .map(|os| os.map(|s| ReportLink::value(output_directory, group_id, s)))
+            .map(|os| os.map(|s| ReportLink::value(output_directory, namespace, group_id, s)))
             .collect::<Option<Vec<_>>>();
 
-        BenchmarkGroup {
+        Some(BenchmarkGroup {
             group_report,
             function_ids,
             values,
             individual_links: value_groups,
-        }
+        })
     }
 }
 
+/// The benchmark groups of one bench target, for the index page.
+#[derive(Serialize, Debug)]
+struct IndexSection<'a> {
+    // eg. "my-crate / my_benchmarks", or None if the target of the groups is not known.
+    title: Option<String>,
+    groups: Vec<BenchmarkGroup<'a>>,
+}
+
 #[derive(Serialize, Debug)]
 struct IndexContext<'a> {
     common_css: &'static str,
-    groups: Vec<BenchmarkGroup<'a>>,
+    environment: String,
+    // Benchmarks which were killed for running too long during this run.
+    timed_out: Vec<ReportLink<'a>>,
+    // The resources used by each benchmark target which ran during this run.
+    targets: Vec<TargetResources>,
+    // The slowest benchmarks and targets of this run.
+    slowest_benchmarks: Vec<BenchmarkTimeSpent<'a>>,
+    slowest_targets: Vec<TargetTimeSpent>,
+    sections: Vec<IndexSection<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct BenchmarkTimeSpent<'a> {
+    benchmark: ReportLink<'a>,
+    total: String,
+    warm_up: String,
+    measurement: String,
+    analysis: String,
+    reporting: String,
+}
+
+#[derive(Serialize, Debug)]
+struct TargetTimeSpent {
+    name: String,
+    total: String,
+}
+
+#[derive(Serialize, Debug)]
+struct TargetResources {
+    name: String,
+    user_time: String,
+    system_time: String,
+    max_rss: String,
+    voluntary_context_switches: u64,
+    involuntary_context_switches: u64,
+    minor_page_faults: u64,
+    major_page_faults: u64,
 }
 
 #[derive(Serialize, Debug)]
@@ -299,6 +371,11 @@ struct HistoryEntry<'a> {
     id: Option<&'a str>,
     datetime: String,
     description: Option<&'a str>,
+    environment: Option<String>,
+    environment_changes: Vec<String>,
+    commit_url: Option<&'a str>,
+    branch: Option<&'a str>,
+    dirty: bool,
 
     has_improved: bool,
     has_regressed: bool,
@@ -463,6 +540,7 @@ impl Report for Html {
             return;
         }
 
+        let namespace = (benchmark_group.benchmarks.keys().next()).and_then(BenchmarkId::namespace);
         let mut function_ids = LinkedHashSet::new();
         let mut value_strs = LinkedHashSet::new();
         for id in benchmark_group.benchmarks.keys() {
@@ -483,7 +561,8 @@ impl Report for Html {
 
             if samples_with_function.len() > 1 {
                 let subgroup_id =
-                    BenchmarkId::new(group_id.to_owned(), Some(function_id.clone()), None, None);
+                    BenchmarkId::new(group_id.to_owned(), Some(function_id.clone()), None, None)
+                        .in_namespace(namespace);
 
                 self.generate_summary(
                     &subgroup_id,
@@ -505,7 +584,8 @@ impl Report for Html {
 
             if samples_with_value.len() > 1 {
                 let subgroup_id =
-                    BenchmarkId::new(group_id.to_owned(), None, Some(value_str.clone()), None);
+                    BenchmarkId::new(group_id.to_owned(), None, Some(value_str.clone()), None)
+                        .in_namespace(namespace);
 
                 self.generate_summary(&subgroup_id, &samples_with_value, context, formatter, false);
             }
@@ -514,7 +594,7 @@ impl Report for Html {
         let all_data: Vec<_> = benchmark_group.benchmarks.iter().collect();
 
         self.generate_summary(
-            &BenchmarkId::new(group_id.to_owned(), None, None, None),
+            &BenchmarkId::new(group_id.to_owned(), None, None, None).in_namespace(namespace),
             &all_data,
             context,
             formatter,
@@ -526,19 +606,77 @@ impl Report for Html {
     fn final_summary(&self, report_context: &ReportContext, model: &Model) {
         let output_directory = &report_context.output_directory;
 
-        let groups = model
-            .groups
-            .iter()
-            .map(|(id, group)| BenchmarkGroup::new(output_directory, id, group))
-            .collect::<Vec<BenchmarkGroup<'_>>>();
+        // Group the benchmark groups by package and bench target, in the order that the
+        // targets were first seen.
+        let mut sections: LinkedHashMap<(Option<&str>, Option<&str>), Vec<BenchmarkGroup<'_>>> =
+            LinkedHashMap::new();
+        for group in model.groups.values() {
+            if let Some(index_group) = BenchmarkGroup::new(output_directory, group) {
+                sections
+                    .entry((group.package.as_deref(), group.target.as_deref()))
+                    .or_default()
+                    .push(index_group);
+            }
+        }
+        let sections = sections
+            .into_iter()
+            .map(|((package, target), groups)| IndexSection {
+                title: match (package, target) {
+                    (Some(package), Some(target)) => Some(format!("{} / {}", package, target)),
+                    (None, Some(target)) => Some(target.to_owned()),
+                    _ => None,
+                },
+                groups,
+            })
+            .collect();
 
         try_else_return!(mkdirp(&output_directory));
 
         let report_path = output_directory.join("index.html");
 
+        let benchmark_times = model.benchmark_times();
         let context = IndexContext {
             common_css: COMMON_CSS,
-            groups,
+            environment: model.environment().summary(),
+            timed_out: (model.timed_out_benchmarks().iter())
+                .map(|id| ReportLink::individual(output_directory, id))
+                .collect(),
+            targets: (model.target_resources().iter())
+                .map(|target| TargetResources {
+                    name: format!("{} / {}", target.package, target.target),
+                    user_time: format::time(target.usage.user_time_seconds * 1e9),
+                    system_time: format::time(target.usage.system_time_seconds * 1e9),
+                    max_rss: format::bytes(target.usage.max_rss_bytes),
+                    voluntary_context_switches: target.usage.voluntary_context_switches,
+                    involuntary_context_switches: target.usage.involuntary_context_switches,
+                    minor_page_faults: target.usage.minor_page_faults,
+                    major_page_faults: target.usage.major_page_faults,
+                })
+                .collect(),
+            slowest_benchmarks: timing::slowest_benchmarks(&benchmark_times)
+                .into_iter()
+                .take(timing::TOP_N)
+                .map(|benchmark| {
+                    let phases = &benchmark.phases;
+                    BenchmarkTimeSpent {
+                        benchmark: ReportLink::individual(output_directory, &benchmark.id),
+                        total: format::time(phases.total_seconds() * 1e9),
+                        warm_up: format::time(phases.warm_up_seconds * 1e9),
+                        measurement: format::time(phases.measurement_seconds * 1e9),
+                        analysis: format::time(phases.analysis_seconds * 1e9),
+                        reporting: format::time(phases.reporting_seconds * 1e9),
+                    }
+                })
+                .collect(),
+            slowest_targets: timing::slowest_targets(&model.target_times())
+                .into_iter()
+                .take(timing::TOP_N)
+                .map(|target| TargetTimeSpent {
+                    name: format!("{} / {}", target.package, target.target),
+                    total: format::time(target.seconds * 1e9),
+                })
+                .collect(),
+            sections,
         };
 
         debug_context(&report_path, &context);
@@ -554,7 +692,7 @@ impl Report for Html {
         &self,
         report_context: &ReportContext,
         id: &BenchmarkId,
-        history: &[SavedStatistics],
+        history: &[HistoryRecord],
         formatter: &ValueFormatter,
     ) {
         let ids: Vec<_> = history
@@ -656,6 +794,18 @@ impl Report for Html {
             .zip(throughput_intervals)
             .enumerate()
             .map(|(i, ((stats, value), throughput))| HistoryEntry {
+                commit_url: stats.git.as_ref().and_then(|git| git.commit_url.as_deref()),
+                branch: stats.git.as_ref().and_then(|git| git.branch.as_deref()),
+                dirty: stats.git.as_ref().map(|git| git.dirty).unwrap_or(false),
+                environment: stats.environment.as_ref().map(|env| env.summary()),
+                environment_changes: match (i.checked_sub(1), &stats.environment) {
+                    (Some(prev), Some(env)) => history[prev]
+                        .environment
+                        .as_ref()
+                        .map(|prev_env| env.differences_from(prev_env))
+                        .unwrap_or_default(),
+                    _ => vec![],
+                },
                 number: i,
                 value,
                 throughput,
@@ -781,6 +931,7 @@ impl Html {
                     Plot::new("Change in median", "change/median.svg"),
                     Plot::new("T-Test", "change/t-test.svg"),
                 ],
+                environment_changes: comp.environment_changes.clone(),
             };
             Some(comp)
         } else {
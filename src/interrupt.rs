@@ -0,0 +1,33 @@
+//! Module implementing graceful handling of Ctrl-C (SIGINT) and SIGTERM during a run.
+//!
+//! Rather than dying immediately, cargo-criterion stops the benchmark target which is running,
+//! skips the remaining targets and generates the summary reports for the benchmarks which
+//! completed. Measurements are saved as each benchmark completes, so they're already on disk. A
+//! second Ctrl-C exits immediately.
+
+use crate::watchdog;
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The exit code used when the run was interrupted (128 + SIGINT, as shells do).
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install the handler for Ctrl-C and SIGTERM.
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again; exiting immediately.");
+            std::process::exit(EXIT_CODE);
+        }
+        eprintln!("Interrupted; stopping the benchmarks. Press Ctrl-C again to exit immediately.");
+        watchdog::interrupt_current_target();
+    })
+    .context("Unable to install the Ctrl-C handler")
+}
+
+/// Returns true if the user has interrupted the run.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,187 @@
+//! Module implementing the advisory lock that prevents several cargo-criterion processes from
+//! modifying the same CRITERION_HOME at once (eg. CI jobs which share a target directory).
+//!
+//! Runs and subcommands which modify the stored data take an exclusive lock; subcommands which
+//! only read it take a shared lock. The lock is released when the `DirectoryLock` is dropped, or
+//! by the operating system if the process dies.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// Holds a lock on a CRITERION_HOME directory until dropped.
+#[derive(Debug)]
+pub struct DirectoryLock {
+    _file: File,
+}
+impl DirectoryLock {
+    /// Lock the given directory, creating it if necessary. If another process holds a
+    /// conflicting lock, this waits for it to be released, or fails if `wait` is false.
+    pub fn acquire(criterion_home: &Path, kind: LockKind, wait: bool) -> Result<DirectoryLock> {
+        fs::create_dir_all(criterion_home)
+            .with_context(|| format!("Failed to create directory {:?}", criterion_home))?;
+        let lock_path = criterion_home.join(".cargo-criterion.lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file {:?}", lock_path))?;
+
+        match lock(&file, kind, false) {
+            Ok(()) => {}
+            Err(e) if is_contended(&e) => {
+                if !wait {
+                    return Err(anyhow::anyhow!(
+                        "Another cargo-criterion process is using {}. Wait for it to finish, \
+                         or set CRITERION_HOME to use a different directory.",
+                        criterion_home.display()
+                    ));
+                }
+                eprintln!(
+                    "Waiting for another cargo-criterion process to finish using {}...",
+                    criterion_home.display()
+                );
+                lock(&file, kind, true)
+                    .with_context(|| format!("Failed to lock {:?}", lock_path))?;
+            }
+            Err(e) => {
+                // Some filesystems (eg. some network filesystems) don't support locking. Carry
+                // on without the lock rather than refusing to run at all.
+                warn!(
+                    "Unable to lock {:?}; concurrent runs may corrupt the stored data: {}",
+                    lock_path, e
+                );
+            }
+        }
+        debug!("Acquired {:?} lock on {:?}", kind, lock_path);
+        Ok(DirectoryLock { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn lock(file: &File, kind: LockKind, block: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut operation = match kind {
+        LockKind::Shared => libc::LOCK_SH,
+        LockKind::Exclusive => libc::LOCK_EX,
+    };
+    if !block {
+        operation |= libc::LOCK_NB;
+    }
+    let result = unsafe { libc::flock(file.as_raw_fd(), operation) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn is_contended(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+#[cfg(windows)]
+fn lock(file: &File, kind: LockKind, block: bool) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+
+    let mut flags = 0;
+    if let LockKind::Exclusive = kind {
+        flags |= LOCKFILE_EXCLUSIVE_LOCK;
+    }
+    if !block {
+        flags |= LOCKFILE_FAIL_IMMEDIATELY;
+    }
+    let result = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(file.as_raw_handle() as _, flags, 0, !0, !0, &mut overlapped)
+    };
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn is_contended(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(winapi::shared::winerror::ERROR_LOCK_VIOLATION as i32)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock(_file: &File, _kind: LockKind, _block: bool) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_contended(_error: &io::Error) -> bool {
+    false
+}
+
+// Other platforms don't lock at all.
+#[cfg(all(test, any(unix, windows)))]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn shared_locks_dont_conflict() {
+        let home = tempfile::tempdir().unwrap();
+        let _first = DirectoryLock::acquire(home.path(), LockKind::Shared, false).unwrap();
+        let _second = DirectoryLock::acquire(home.path(), LockKind::Shared, false).unwrap();
+    }
+
+    #[test]
+    fn shared_lock_blocks_exclusive_lock() {
+        let home = tempfile::tempdir().unwrap();
+        let shared = DirectoryLock::acquire(home.path(), LockKind::Shared, false).unwrap();
+        let error = DirectoryLock::acquire(home.path(), LockKind::Exclusive, false).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Another cargo-criterion process"));
+
+        drop(shared);
+        DirectoryLock::acquire(home.path(), LockKind::Exclusive, false).unwrap();
+    }
+
+    #[test]
+    fn exclusive_lock_blocks_shared_lock() {
+        let home = tempfile::tempdir().unwrap();
+        let _exclusive = DirectoryLock::acquire(home.path(), LockKind::Exclusive, false).unwrap();
+        assert!(DirectoryLock::acquire(home.path(), LockKind::Shared, false).is_err());
+    }
+
+    #[test]
+    fn wait_for_lock() {
+        let home = tempfile::tempdir().unwrap();
+        let shared = DirectoryLock::acquire(home.path(), LockKind::Shared, false).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let path = home.path().to_owned();
+        let waiter = thread::spawn(move || {
+            let lock = DirectoryLock::acquire(&path, LockKind::Exclusive, true);
+            sender.send(lock.is_ok()).unwrap();
+        });
+
+        // The waiter can't get the lock while we hold ours...
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        // ...but gets it as soon as we release it.
+        drop(shared);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(30)), Ok(true));
+        waiter.join().unwrap();
+    }
+}
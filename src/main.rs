@@ -23,22 +23,43 @@ mod macros_private;
 #[macro_use]
 mod plot;
 
+mod affinity;
 mod analysis;
 mod bench_target;
+mod bundle;
 mod compile;
 mod config;
 mod connection;
+mod doctor;
+mod environment;
 mod estimate;
+mod export;
 mod format;
+mod gc;
+mod git;
+mod history;
 mod html;
+mod interrupt;
 mod kde;
+mod lock;
+mod merge;
 mod message_formats;
 mod model;
+mod noise;
+mod reanalyze;
+mod rename;
 mod report;
+mod resume;
+mod runner;
+mod rusage;
+mod schema;
+mod stability;
 mod stats;
+mod timing;
 mod value_formatter;
+mod watchdog;
 
-use crate::config::{OutputFormat, PlottingBackend, SelfConfig, TextColor};
+use crate::config::{OutputFormat, PlottingBackend, SelfConfig, Subcommand, TextColor};
 use crate::connection::{AxisScale, PlotConfiguration};
 use crate::plot::Plotter;
 use crate::report::{Report, ReportContext};
@@ -80,19 +101,90 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let configuration = config::configure()?;
     let self_config = &configuration.self_config;
 
+    if let Some(subcommand) = &configuration.subcommand {
+        match subcommand {
+            Subcommand::Gc(options) => gc::gc(&configuration, options)?,
+            Subcommand::Doctor(options) => doctor::doctor(&configuration, options)?,
+            Subcommand::Rename(options) => rename::rename(&configuration, options)?,
+            Subcommand::Export(options) => export::export(&configuration, options)?,
+            Subcommand::Import(options) => bundle::import(&configuration, options)?,
+            Subcommand::History(options) => history::history(&configuration, options)?,
+            Subcommand::Reanalyze(options) => reanalyze::reanalyze(&configuration, options)?,
+            Subcommand::Merge(options) => {
+                let html_report = get_plotter(self_config)?.map(crate::html::Html::new);
+                merge::merge(&configuration, options, html_report.as_ref())?
+            }
+        }
+        return Ok(());
+    }
+
+    // Keep our own threads off the CPUs that the benchmarks will be pinned to. This has to happen
+    // before any other threads are started.
+    if let (true, Some(cpus)) = (self_config.do_run, &self_config.cpus) {
+        affinity::reserve(cpus)?;
+    }
+
+    // Check the machine for sources of noise. This is done before building the benchmarks, since
+    // the build itself raises the load average, and so that --strict-environment doesn't have to
+    // wait for the build. Swapping is checked just before the benchmarks run instead.
+    let mut noise_checks = if self_config.do_run {
+        noise::check(self_config.cpus.as_ref())
+    } else {
+        None
+    };
+    let noise_warnings = (noise_checks.as_ref())
+        .map(noise::NoiseChecks::warnings)
+        .unwrap_or_default();
+    warn_about_noise(&noise_warnings, self_config.strict_environment)?;
+
     // Launch cargo to compile the crate and produce a list of the benchmark targets to run.
     let compile::CompiledBenchmarks {
         targets,
         library_paths,
-    } = compile::compile(self_config.debug_build, &configuration.cargo_args)?;
+    } = compile::compile(
+        self_config.debug_build,
+        &configuration.cargo_args,
+        &self_config.runners,
+    )?;
+
+    // Make sure no other cargo-criterion process modifies the stored data while we're using it.
+    let lock_kind = if self_config.do_run {
+        lock::LockKind::Exclusive
+    } else {
+        lock::LockKind::Shared
+    };
+    let _lock = lock::DirectoryLock::acquire(
+        &self_config.criterion_home,
+        lock_kind,
+        self_config.wait_for_lock,
+    )?;
+    if self_config.do_run {
+        rename::apply_configured_renames(&self_config.criterion_home, &self_config.renames)?;
+    }
 
-    // Load the saved measurements from the last run.
-    let mut run_model = model::Model::load(
-        self_config.criterion_home.clone(),
-        "main".into(),
-        self_config.history_id.clone(),
-        self_config.history_description.clone(),
-    );
+    // When resuming, load the record of the interrupted run. Unless the user says otherwise, the
+    // run keeps its history ID, description and tags.
+    let resumed = if self_config.do_run && self_config.resume {
+        Some(resume::RunRecord::load_unfinished(
+            &self_config.criterion_home,
+        )?)
+    } else {
+        None
+    };
+
+    // Ask git which commit we're benchmarking. Unless the user says otherwise, this is used to
+    // identify the run in the history reports.
+    let git = git::GitMetadata::detect(&self_config.package_directory);
+    let history_id = (self_config.history_id.clone())
+        .or_else(|| resumed.as_ref().and_then(|run| run.history_id.clone()))
+        .or_else(|| git.as_ref().map(|git| git.short_commit().to_owned()));
+    let history_description = (self_config.history_description.clone())
+        .or_else(|| (resumed.as_ref()).and_then(|run| run.history_description.clone()))
+        .or_else(|| git.as_ref().and_then(|git| git.subject.clone()));
+    let tags = match &resumed {
+        Some(run) if self_config.tags.is_empty() => run.tags.clone(),
+        _ => self_config.tags.clone(),
+    };
 
     // Set up the reports. These receive notifications as the benchmarks proceed and generate output for the user.
     let cli_report = configure_cli_output(self_config);
@@ -115,31 +207,145 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     let reports = crate::report::Reports::new(reports);
 
+    // Check whether the machine is swapping just before running the benchmarks.
+    if let Some(noise_checks) = &mut noise_checks {
+        noise_checks.sample_swapping();
+        let swap_warning: Vec<String> = noise_checks.swap_warning().into_iter().collect();
+        warn_about_noise(&swap_warning, self_config.strict_environment)?;
+    }
+
+    // Measure the machine's timing jitter just before running the benchmarks.
+    let jitter_before = if self_config.do_run && self_config.stability.probe {
+        let jitter = stability::probe(self_config.cpus.as_ref())?;
+        reports.jitter_measured(jitter, None);
+        match self_config.stability.max_jitter {
+            Some(max_jitter) if jitter > max_jitter => {
+                return Err(anyhow::anyhow!(
+                    "Refusing to run the benchmarks because the timing jitter is higher than --max-jitter ({})",
+                    stability::format_jitter(max_jitter)
+                )
+                .into());
+            }
+            _ => Some(jitter),
+        }
+    } else {
+        None
+    };
+
+    let mut environment = environment::Environment::capture(self_config);
+    environment.noise_checks = noise_checks;
+    environment.jitter = jitter_before;
+
+    // Load the saved measurements from the last run.
+    let mut run_model = model::Model::load(
+        self_config.criterion_home.clone(),
+        "main".into(),
+        history_id.clone(),
+        history_description.clone(),
+        environment,
+        git,
+        self_config.retention.clone(),
+    )?;
+    run_model.set_tags(tags.clone());
+    run_model.set_namespace_by_package(self_config.namespace_by_package);
+    if self_config.baseline.is_some() || !self_config.baseline_tags.is_empty() {
+        let timeline = self_config.baseline.as_deref().unwrap_or("main");
+        run_model.load_baseline(
+            &self_config.criterion_home,
+            timeline,
+            &self_config.baseline_tags,
+        )?;
+    }
+
     if self_config.do_run {
+        interrupt::install_handler()?;
+
+        let resuming = resumed.is_some();
+        let mut run = match resumed {
+            Some(mut run) => {
+                run.history_id = history_id;
+                run.history_description = history_description;
+                run.tags = tags;
+                run
+            }
+            None => resume::RunRecord::new(history_id, history_description, tags),
+        };
+        run.jitter_before = jitter_before;
+        run_model.start_run(run)?;
+
         // Execute each benchmark target, updating the model as we go.
+        let mut result = Ok(());
         for bench in targets {
-            info!("Executing {} - {:?}", bench.name, bench.executable);
-            let err = bench.execute(
-                &self_config.criterion_home,
-                &configuration.additional_args,
+            if interrupt::interrupted() {
+                break;
+            }
+            let err = resume::target_args(
+                run_model.run_record().filter(|_| resuming),
+                &bench,
                 &library_paths,
-                &reports,
-                &mut run_model,
-                self_config.message_format.is_some(),
-            );
+                configuration.bench_filter.as_deref(),
+                &configuration.additional_args,
+            )
+            .and_then(|args| match args {
+                Some(args) => {
+                    info!("Executing {} - {:?}", bench.name, bench.executable);
+                    let started = std::time::Instant::now();
+                    let result =
+                        bench.execute(self_config, &args, &library_paths, &reports, &mut run_model);
+                    run_model.target_timed(&bench, started.elapsed());
+                    result
+                }
+                None => {
+                    eprintln!(
+                        "Skipping benchmark target {}; the interrupted run completed it.",
+                        bench.name
+                    );
+                    Ok(())
+                }
+            });
 
             if let Err(err) = err {
-                if self_config.do_fail_fast {
-                    return Err(err.into());
+                if interrupt::interrupted() {
+                    break;
+                } else if self_config.do_fail_fast {
+                    // Still generate the summary report, so that it covers the benchmarks which
+                    // did complete.
+                    result = Err(err);
+                    break;
                 } else {
                     error!(
                         "Failed to execute benchmark target {}:\n{}",
                         bench.name, err
                     );
                 }
+            } else {
+                run_model.target_complete(&bench);
             }
         }
 
+        // Measure the jitter again, to see whether the machine stayed stable during the run.
+        if let (Some(jitter_before), false) = (jitter_before, interrupt::interrupted()) {
+            match stability::probe(self_config.cpus.as_ref()) {
+                Ok(jitter) => {
+                    reports.jitter_measured(jitter_before, Some(jitter));
+                    run_model.set_jitter_after(jitter);
+                    if let Some(max_jitter) = self_config.stability.max_jitter {
+                        if jitter > max_jitter {
+                            warn!(
+                                "The timing jitter rose above --max-jitter ({}) during the run, so the results may be unreliable.",
+                                stability::format_jitter(max_jitter)
+                            );
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to measure the timing jitter: {:#}", e),
+            }
+        }
+
+        if !interrupt::interrupted() && result.is_ok() {
+            run_model.finish_run();
+        }
+
         // Generate the overall summary report using all of the records in the model.
         let final_context = ReportContext {
             output_directory: self_config.criterion_home.join("reports"),
@@ -149,11 +355,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
 
         reports.final_summary(&final_context, &run_model);
+        if interrupt::interrupted() {
+            reports.interrupted(&final_context);
+            eprintln!("The run was interrupted; the reports only cover the completed benchmarks.");
+            std::process::exit(interrupt::EXIT_CODE);
+        }
+        result?;
     }
     Ok(())
 }
 
 /// Configure and return a Report object that prints benchmark information to the command-line.
+/// Print the problems found by the noise checks, if any. With --strict-environment, refuse to run
+/// the benchmarks if there are any.
+fn warn_about_noise(warnings: &[String], strict_environment: bool) -> Result<(), Error> {
+    if warnings.is_empty() {
+        return Ok(());
+    }
+    eprintln!("Warning: this machine may produce noisy measurements:");
+    for warning in warnings {
+        eprintln!("  - {}", warning);
+    }
+    if strict_environment {
+        return Err(anyhow::anyhow!(
+            "Refusing to run the benchmarks because of the problems above (--strict-environment or strict_environment in criterion.toml)"
+        ));
+    }
+    Ok(())
+}
+
 fn configure_cli_output(self_config: &crate::config::SelfConfig) -> crate::report::CliReport {
     let stderr_isatty = atty::is(atty::Stream::Stderr);
     let mut enable_text_overwrite = stderr_isatty && !debug_enabled();
@@ -0,0 +1,392 @@
+//! Module implementing the `merge` subcommand, which combines the data directories produced by
+//! several machines or CI shards into one.
+
+use crate::config::{FullConfig, MergeOptions, RetentionPolicy};
+use crate::connection::{AxisScale, PlotConfiguration};
+use crate::environment::Environment;
+use crate::html::Html;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    is_measurement_file, list_timelines, load_benchmark_record, load_measurement,
+    rebuild_history_index, save_atomically, save_benchmark_record, stored_benchmarks,
+    timeline_directory, BenchmarkRecord, Model, SavedBenchmarkId,
+};
+use crate::report::{BenchmarkId, Report, ReportContext};
+use crate::schema::SchemaError;
+use anyhow::{anyhow, Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Entry point for the `merge` subcommand.
+pub fn merge(
+    configuration: &FullConfig,
+    options: &MergeOptions,
+    html: Option<&Html>,
+) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let criterion_home = &self_config.criterion_home;
+    let _lock = DirectoryLock::acquire(
+        criterion_home,
+        LockKind::Exclusive,
+        self_config.wait_for_lock,
+    )?;
+
+    for source in &options.directories {
+        let source_data = source.join("data");
+        if !source_data.is_dir() {
+            return Err(anyhow!(
+                "{} doesn't contain any cargo-criterion data (expected to find {})",
+                source.display(),
+                source_data.display()
+            ));
+        }
+    }
+
+    let mut merged = 0;
+    for source in &options.directories {
+        if is_same_directory(source, criterion_home) {
+            warn!("Skipping {}; it is the output directory", source.display());
+            continue;
+        }
+        let _source_lock =
+            DirectoryLock::acquire(source, LockKind::Shared, self_config.wait_for_lock)?;
+        merged += merge_directory(criterion_home, source)?;
+    }
+
+    if let Some(html) = html {
+        let model = Model::load(
+            criterion_home.clone(),
+            "main".into(),
+            None,
+            None,
+            Environment::default(),
+            None,
+            RetentionPolicy::default(),
+        )?;
+        let context = ReportContext {
+            output_directory: criterion_home.join("reports"),
+            plot_config: PlotConfiguration {
+                summary_scale: AxisScale::Linear,
+            },
+        };
+        html.final_summary(&context, &model);
+    }
+
+    eprintln!(
+        "Merged {} benchmarks from {} directories into {}.",
+        merged,
+        options.directories.len(),
+        criterion_home.display()
+    );
+    Ok(())
+}
+
+/// Merge every timeline of the given CRITERION_HOME into ours. Returns the number of benchmarks
+/// merged.
+fn merge_directory(criterion_home: &Path, source: &Path) -> Result<usize> {
+    let mut merged = 0;
+    for timeline in list_timelines(source)? {
+        let source_data = timeline_directory(source, &timeline)?;
+        let dest_data = timeline_directory(criterion_home, &timeline)?;
+        for (dir, record) in stored_benchmarks(&source_data)? {
+            let id = BenchmarkId::from(record.id.clone());
+            let source_won = match merge_benchmark(&dest_data, &dir, &record) {
+                Ok(source_won) => source_won,
+                Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+                Err(e) => {
+                    error!(
+                        "Failed to merge benchmark {} of timeline '{}' from {}; it will be skipped: {:?}",
+                        id.as_title(),
+                        timeline,
+                        source.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            // Only the main timeline has reports.
+            if source_won && timeline == "main" {
+                let relative = dir.strip_prefix(&source_data)?;
+                copy_reports(
+                    &path!(source, "reports", relative),
+                    &path!(criterion_home, "reports", relative),
+                    &dir,
+                )?;
+            }
+            merged += 1;
+        }
+    }
+
+    // Fill in anything else we don't have yet, such as the summary reports of groups.
+    copy_missing_reports(&source.join("reports"), &criterion_home.join("reports"))?;
+    Ok(merged)
+}
+
+/// Copy the measurements of the given benchmark into the given timeline directory and combine
+/// them with any measurements that are already there. Returns true if the source's latest
+/// measurement is newer than ours, in which case it becomes the latest measurement.
+fn merge_benchmark(
+    dest_data: &Path,
+    source_dir: &Path,
+    source_record: &BenchmarkRecord,
+) -> Result<bool> {
+    let id = BenchmarkId::from(source_record.id.clone());
+    let dest_dir = dest_data.join(id.as_directory_name());
+    fs::create_dir_all(&dest_dir)
+        .with_context(|| format!("Failed to create directory {:?}", dest_dir))?;
+
+    let mut source_latest = None;
+    for entry in fs::read_dir(source_dir)? {
+        let entry = entry?;
+        if !is_measurement_file(&entry.file_name()) {
+            continue;
+        }
+        let contents =
+            fs::read(entry.path()).with_context(|| format!("Failed to read {:?}", entry.path()))?;
+        let dest_name = store_measurement(&contents, &entry.file_name(), &dest_dir)?;
+        if entry.file_name() == source_record.latest_record.as_os_str() {
+            source_latest = Some(dest_name);
+        }
+    }
+    let source_latest = source_latest.ok_or_else(|| {
+        anyhow!(
+            "The latest measurement {:?} is missing",
+            source_dir.join(&source_record.latest_record)
+        )
+    })?;
+    update_latest_record(&dest_dir, &source_record.id, source_latest)
+}
+
+/// After new measurements have been stored in the given benchmark directory, make `latest` the
+/// latest measurement if it is newer than the current one (or if there is no current one), and
+/// rebuild the history index. Returns true if `latest` became the latest measurement.
+pub fn update_latest_record(
+    dest_dir: &Path,
+    id: &SavedBenchmarkId,
+    latest: PathBuf,
+) -> Result<bool> {
+    let source_datetime = load_measurement(&dest_dir.join(&latest))?.datetime;
+
+    let dest_record_path = dest_dir.join("benchmark.cbor");
+    let source_won = if dest_record_path.is_file() {
+        let dest_record = load_benchmark_record(&dest_record_path)?;
+        match load_measurement(&dest_dir.join(&dest_record.latest_record)) {
+            Ok(dest_latest) => source_datetime > dest_latest.datetime,
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(_) => true,
+        }
+    } else {
+        true
+    };
+
+    if source_won {
+        let record = BenchmarkRecord {
+            id: id.clone(),
+            latest_record: latest,
+        };
+        save_benchmark_record(&dest_record_path, &record)?;
+    }
+    rebuild_history_index(dest_dir)?;
+    Ok(source_won)
+}
+
+/// Save the contents of a measurement file into the given directory under the given name, and
+/// return the name of the new file. If a different measurement with the same name is already
+/// there (measurement files are named after the time they were taken, so shards can easily
+/// collide), the new file is given a different name.
+pub fn store_measurement(source_contents: &[u8], name: &OsStr, dest_dir: &Path) -> Result<PathBuf> {
+    let name = name.to_string_lossy().into_owned();
+    let stem = name.trim_end_matches(".cbor");
+
+    for counter in 0.. {
+        let dest_name = if counter == 0 {
+            name.clone()
+        } else {
+            format!("{}_{}.cbor", stem, counter)
+        };
+        let dest = dest_dir.join(&dest_name);
+        if !dest.exists() {
+            save_atomically(&dest, |writer| {
+                writer.write_all(source_contents).map_err(Into::into)
+            })
+            .with_context(|| format!("Failed to write {:?}", dest))?;
+            return Ok(PathBuf::from(dest_name));
+        }
+        if fs::read(&dest)? == source_contents {
+            // Already merged.
+            return Ok(PathBuf::from(dest_name));
+        }
+    }
+    unreachable!()
+}
+
+/// Copy the reports of a single benchmark, replacing ours. Subdirectories which hold the reports
+/// of other benchmarks nested inside this one are left alone.
+fn copy_reports(source: &Path, dest: &Path, data_dir: &Path) -> Result<()> {
+    if !source.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && data_dir.join(entry.file_name()).is_dir() {
+            continue;
+        }
+        copy_tree(&entry.path(), &dest.join(entry.file_name()), true)?;
+    }
+    Ok(())
+}
+
+/// Copy any report files that we don't have yet. The top-level index is skipped, since it is
+/// regenerated after merging.
+fn copy_missing_reports(source: &Path, dest: &Path) -> Result<()> {
+    if !source.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        if entry.file_name() != "index.html" {
+            copy_tree(&entry.path(), &dest.join(entry.file_name()), false)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_tree(source: &Path, dest: &Path, overwrite: bool) -> Result<()> {
+    for entry in WalkDir::new(source)
+        .into_iter()
+        .filter_map(::std::result::Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let target = dest.join(entry.path().strip_prefix(source)?);
+        if target.exists() && !overwrite {
+            continue;
+        }
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::copy(entry.path(), &target)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", entry.path(), target))?;
+    }
+    Ok(())
+}
+
+fn is_same_directory(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::read_history_index;
+    use crate::model::test::{save_measurement, saved_statistics, store_benchmark};
+
+    fn measurements(dir: &Path) -> Vec<(String, f64)> {
+        (read_history_index(dir).unwrap().into_iter())
+            .map(|record| {
+                let stats = load_measurement(&dir.join(&record.measurement)).unwrap();
+                (
+                    record.measurement.to_string_lossy().into_owned(),
+                    stats.estimates.mean.point_estimate,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_overlapping_histories() {
+        let ours = tempfile::tempdir().unwrap();
+        let theirs = tempfile::tempdir().unwrap();
+        let id = BenchmarkId::new("fib".to_owned(), None, None, None);
+        // Both sides share the first measurement. The second was taken by different machines in
+        // the same second, so the names collide.
+        let dir = store_benchmark(
+            &path!(ours.path(), "data", "main"),
+            &id,
+            &[saved_statistics(1_000, 1.0), saved_statistics(2_000, 2.0)],
+        );
+        store_benchmark(
+            &path!(theirs.path(), "data", "main"),
+            &id,
+            &[
+                saved_statistics(1_000, 1.0),
+                saved_statistics(2_000, 3.0),
+                saved_statistics(3_000, 4.0),
+            ],
+        );
+
+        assert_eq!(merge_directory(ours.path(), theirs.path()).unwrap(), 1);
+        let expected = vec![
+            ("measurement_1000.cbor".to_owned(), 1.0),
+            ("measurement_2000.cbor".to_owned(), 2.0),
+            ("measurement_2000_1.cbor".to_owned(), 3.0),
+            ("measurement_3000.cbor".to_owned(), 4.0),
+        ];
+        assert_eq!(measurements(&dir), expected);
+        let record = load_benchmark_record(&dir.join("benchmark.cbor")).unwrap();
+        assert_eq!(record.latest_record, PathBuf::from("measurement_3000.cbor"));
+
+        // Merging again changes nothing.
+        merge_directory(ours.path(), theirs.path()).unwrap();
+        assert_eq!(measurements(&dir), expected);
+    }
+
+    #[test]
+    fn test_merge_keeps_newer_latest_record() {
+        let ours = tempfile::tempdir().unwrap();
+        let theirs = tempfile::tempdir().unwrap();
+        let id = BenchmarkId::new("fib".to_owned(), None, None, None);
+        let dir = store_benchmark(
+            &path!(ours.path(), "data", "main"),
+            &id,
+            &[saved_statistics(3_000, 3.0)],
+        );
+        let their_dir = store_benchmark(
+            &path!(theirs.path(), "data", "main"),
+            &id,
+            &[saved_statistics(1_000, 1.0)],
+        );
+        save_measurement(
+            &their_dir,
+            "measurement_2000.cbor",
+            &saved_statistics(2_000, 2.0),
+        );
+
+        merge_directory(ours.path(), theirs.path()).unwrap();
+        let record = load_benchmark_record(&dir.join("benchmark.cbor")).unwrap();
+        assert_eq!(record.latest_record, PathBuf::from("measurement_3000.cbor"));
+        assert_eq!(measurements(&dir).len(), 3);
+    }
+
+    #[test]
+    fn test_merge_every_timeline() {
+        let ours = tempfile::tempdir().unwrap();
+        let theirs = tempfile::tempdir().unwrap();
+        let id = BenchmarkId::new("fib".to_owned(), None, None, None);
+        store_benchmark(
+            &path!(theirs.path(), "data", "main"),
+            &id,
+            &[saved_statistics(1_000, 1.0)],
+        );
+        store_benchmark(
+            &path!(theirs.path(), "data", "nightly"),
+            &id,
+            &[saved_statistics(2_000, 2.0)],
+        );
+
+        assert_eq!(merge_directory(ours.path(), theirs.path()).unwrap(), 2);
+        assert_eq!(
+            list_timelines(ours.path()).unwrap(),
+            vec!["main", "nightly"]
+        );
+        let dir = path!(ours.path(), "data", "nightly", id.as_directory_name());
+        assert_eq!(
+            measurements(&dir),
+            vec![("measurement_2000.cbor".to_owned(), 2.0)]
+        );
+    }
+}
@@ -1,9 +1,13 @@
+use crate::bench_target::BenchTarget;
 use crate::connection::Throughput as ThroughputEnum;
-use crate::model::BenchmarkGroup;
+use crate::model::{BenchmarkGroup, Model};
 use crate::report::{
     compare_to_threshold, BenchmarkId, ComparisonResult, MeasurementData, Report, ReportContext,
 };
+use crate::rusage::ResourceUsage;
+use crate::timing::{self, PhaseTimes};
 use crate::value_formatter::ValueFormatter;
+use crate::watchdog::Timeout;
 use anyhow::Result;
 use serde_derive::Serialize;
 use serde_json::json;
@@ -48,6 +52,7 @@ struct ChangeDetails {
     median: ConfidenceInterval,
 
     change: ChangeType,
+    environment_changes: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -86,6 +91,71 @@ impl Message for BenchmarkGroupComplete {
     }
 }
 
+#[derive(Serialize)]
+struct BenchmarkTimedOut {
+    id: String,
+    // Which timeout expired; "target" or "benchmark".
+    timeout: &'static str,
+    timeout_seconds: f64,
+}
+impl Message for BenchmarkTimedOut {
+    fn reason() -> &'static str {
+        "benchmark-timed-out"
+    }
+}
+
+#[derive(Serialize)]
+struct TargetComplete<'a> {
+    package: &'a str,
+    target: &'a str,
+    // None if the platform doesn't report the resources used by processes.
+    resource_usage: Option<&'a ResourceUsage>,
+}
+impl Message for TargetComplete<'_> {
+    fn reason() -> &'static str {
+        "target-complete"
+    }
+}
+
+#[derive(Serialize)]
+struct BenchmarkTimeSpent<'a> {
+    id: &'a str,
+    package: &'a str,
+    target: &'a str,
+    total_seconds: f64,
+    #[serde(flatten)]
+    phases: &'a PhaseTimes,
+}
+
+#[derive(Serialize)]
+struct TargetTimeSpent<'a> {
+    package: &'a str,
+    target: &'a str,
+    total_seconds: f64,
+}
+
+// The time spent on each benchmark and target, slowest first.
+#[derive(Serialize)]
+struct TimeSpent<'a> {
+    benchmarks: Vec<BenchmarkTimeSpent<'a>>,
+    targets: Vec<TargetTimeSpent<'a>>,
+}
+impl Message for TimeSpent<'_> {
+    fn reason() -> &'static str {
+        "time-spent"
+    }
+}
+
+#[derive(Serialize)]
+struct RunInterrupted {
+    report_directory: String,
+}
+impl Message for RunInterrupted {
+    fn reason() -> &'static str {
+        "run-interrupted"
+    }
+}
+
 pub struct JsonMessageReport;
 impl JsonMessageReport {
     fn send_message<M: Message>(&self, message: M) {
@@ -177,6 +247,7 @@ impl Report for JsonMessageReport {
                     mean: ConfidenceInterval::from_percent(&comparison.relative_estimates.mean),
                     median: ConfidenceInterval::from_percent(&comparison.relative_estimates.median),
                     change,
+                    environment_changes: comparison.environment_changes.clone(),
                 }
             }),
         };
@@ -184,6 +255,54 @@ impl Report for JsonMessageReport {
         self.send_message(message);
     }
 
+    fn benchmark_timed_out(&self, id: &BenchmarkId, _context: &ReportContext, timeout: Timeout) {
+        self.send_message(BenchmarkTimedOut {
+            id: id.as_title().to_owned(),
+            timeout: match timeout {
+                Timeout::Target(_) => "target",
+                Timeout::Benchmark(_) => "benchmark",
+            },
+            timeout_seconds: timeout.duration().as_secs_f64(),
+        });
+    }
+
+    fn target_complete(&self, target: &BenchTarget, usage: Option<&ResourceUsage>) {
+        self.send_message(TargetComplete {
+            package: &target.package,
+            target: &target.name,
+            resource_usage: usage,
+        });
+    }
+
+    fn final_summary(&self, _context: &ReportContext, model: &Model) {
+        self.send_message(TimeSpent {
+            benchmarks: timing::slowest_benchmarks(&model.benchmark_times())
+                .into_iter()
+                .map(|benchmark| BenchmarkTimeSpent {
+                    id: benchmark.id.as_title(),
+                    package: &benchmark.package,
+                    target: &benchmark.target,
+                    total_seconds: benchmark.phases.total_seconds(),
+                    phases: &benchmark.phases,
+                })
+                .collect(),
+            targets: timing::slowest_targets(&model.target_times())
+                .into_iter()
+                .map(|target| TargetTimeSpent {
+                    package: &target.package,
+                    target: &target.target,
+                    total_seconds: target.seconds,
+                })
+                .collect(),
+        });
+    }
+
+    fn interrupted(&self, context: &ReportContext) {
+        self.send_message(RunInterrupted {
+            report_directory: context.output_directory.display().to_string(),
+        });
+    }
+
     fn summarize(
         &self,
         context: &ReportContext,
@@ -191,6 +310,7 @@ impl Report for JsonMessageReport {
         benchmark_group: &BenchmarkGroup,
         _formatter: &ValueFormatter,
     ) {
+        let namespace = (benchmark_group.benchmarks.keys().next()).and_then(BenchmarkId::namespace);
         let message = BenchmarkGroupComplete {
             group_name: group_id.to_owned(),
             benchmarks: benchmark_group
@@ -200,7 +320,9 @@ impl Report for JsonMessageReport {
                 .collect(),
             report_directory: path!(
                 &context.output_directory,
-                BenchmarkId::new(group_id.to_owned(), None, None, None).as_directory_name()
+                BenchmarkId::new(group_id.to_owned(), None, None, None)
+                    .in_namespace(namespace)
+                    .as_directory_name()
             )
             .display()
             .to_string(),
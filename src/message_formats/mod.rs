@@ -62,6 +62,43 @@ impl Report for MessageReport {
         }
     }
 
+    fn benchmark_timed_out(
+        &self,
+        id: &crate::report::BenchmarkId,
+        context: &crate::report::ReportContext,
+        timeout: crate::watchdog::Timeout,
+    ) {
+        match self {
+            Self::Json(report) => report.benchmark_timed_out(id, context, timeout),
+            Self::OpenMetrics(report) => report.benchmark_timed_out(id, context, timeout),
+        }
+    }
+
+    fn target_complete(
+        &self,
+        target: &crate::bench_target::BenchTarget,
+        usage: Option<&crate::rusage::ResourceUsage>,
+    ) {
+        match self {
+            Self::Json(report) => report.target_complete(target, usage),
+            Self::OpenMetrics(report) => report.target_complete(target, usage),
+        }
+    }
+
+    fn final_summary(&self, context: &crate::report::ReportContext, model: &crate::model::Model) {
+        match self {
+            Self::Json(report) => report.final_summary(context, model),
+            Self::OpenMetrics(report) => report.final_summary(context, model),
+        }
+    }
+
+    fn interrupted(&self, context: &crate::report::ReportContext) {
+        match self {
+            Self::Json(report) => report.interrupted(context),
+            Self::OpenMetrics(report) => report.interrupted(context),
+        }
+    }
+
     fn summarize(
         &self,
         context: &crate::report::ReportContext,
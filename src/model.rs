@@ -1,13 +1,22 @@
+use crate::bench_target::BenchTarget;
+use crate::config::RetentionPolicy;
 use crate::connection::Throughput;
+use crate::environment::Environment;
 use crate::estimate::{ChangeEstimates, Estimates};
+use crate::git::GitMetadata;
 use crate::report::{BenchmarkId, ComparisonData, MeasurementData};
-use anyhow::{Context, Result};
+use crate::resume::RunRecord;
+use crate::rusage::ResourceUsage;
+use crate::schema::{self, Migration, SchemaError, Versioned};
+use crate::timing::{BenchmarkTime, PhaseTimes, TargetTime};
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
 use linked_hash_map::LinkedHashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
 use walkdir::WalkDir;
 
 #[derive(Debug)]
@@ -15,6 +24,9 @@ pub struct Benchmark {
     pub latest_stats: SavedStatistics,
     pub previous_stats: Option<SavedStatistics>,
     pub target: Option<String>,
+    pub package: Option<String>,
+    // Has this benchmark been seen during this run?
+    seen: bool,
 }
 impl Benchmark {
     fn new(stats: SavedStatistics) -> Self {
@@ -22,6 +34,8 @@ impl Benchmark {
             latest_stats: stats,
             previous_stats: None,
             target: None,
+            package: None,
+            seen: false,
         }
     }
 
@@ -35,16 +49,29 @@ impl Benchmark {
 pub struct BenchmarkGroup {
     pub benchmarks: LinkedHashMap<BenchmarkId, Benchmark>,
     pub target: Option<String>,
+    pub package: Option<String>,
+    // Has this group been seen during this run?
+    seen: bool,
 }
 impl Default for BenchmarkGroup {
     fn default() -> Self {
         BenchmarkGroup {
             benchmarks: LinkedHashMap::new(),
             target: None,
+            package: None,
+            seen: false,
         }
     }
 }
 
+/// The resources used by a benchmark target during this run.
+#[derive(Debug)]
+pub struct TargetResources {
+    pub package: String,
+    pub target: String,
+    pub usage: ResourceUsage,
+}
+
 /// The Model struct stores everything that we keep in-memory about the benchmarks and their
 /// performance. It's loaded from disk at the beginning of a run and updated as benchmarks
 /// are executed.
@@ -53,31 +80,66 @@ pub struct Model {
     // Path to output directory
     data_directory: PathBuf,
     // Track all of the unique benchmark titles and directories we've seen, so we can uniquify them.
-    all_titles: HashSet<String>,
+    // Titles only need to be unique within each package's namespace.
+    all_titles: HashMap<Option<String>, HashSet<String>>,
     all_directories: HashSet<PathBuf>,
-    // All of the known benchmark groups, stored in execution order (where possible).
+    // All of the known benchmark groups, stored in execution order (where possible). The keys
+    // are the group IDs, prefixed with the package name if benchmarks are namespaced by package.
     pub groups: LinkedHashMap<String, BenchmarkGroup>,
+    // Should the data and reports of each package be stored in separate directories?
+    namespace_by_package: bool,
+    // Benchmarks which were killed for running too long during this run.
+    timed_out: Vec<BenchmarkId>,
+    // The record of this run's progress, which is kept up to date so that the run can be resumed
+    // if it's interrupted. It also holds the resources used by and the time spent on each
+    // benchmark and target, so that they cover the whole run when it's resumed.
+    criterion_home: PathBuf,
+    run: Option<RunRecord>,
 
     history_id: Option<String>,
     history_description: Option<String>,
+    // The machine and toolchain used for this run.
+    environment: Environment,
+    // The git commit being benchmarked, if the package is in a git repository.
+    git: Option<GitMetadata>,
+    // User-provided labels for this run.
+    tags: BTreeMap<String, String>,
+    // Rules for deleting old measurements after new ones are saved.
+    retention: RetentionPolicy,
+    // If set, benchmarks are compared against the latest measurements in this timeline instead
+    // of their own previous measurements.
+    baseline: Option<HashMap<BenchmarkId, SavedStatistics>>,
 }
 impl Model {
     /// Load the model from disk. The output directory is scanned for benchmark files. Any files
     /// found are loaded into the model so that we can include them in the reports even if this
-    /// run doesn't execute that particular benchmark.
+    /// run doesn't execute that particular benchmark. Fails if any of the files were saved by a
+    /// newer version of cargo-criterion, since continuing would overwrite them.
     pub fn load(
         criterion_home: PathBuf,
         timeline: PathBuf,
         history_id: Option<String>,
         history_description: Option<String>,
-    ) -> Model {
+        environment: Environment,
+        git: Option<GitMetadata>,
+        retention: RetentionPolicy,
+    ) -> Result<Model> {
         let mut model = Model {
-            data_directory: path!(criterion_home, "data", timeline),
-            all_titles: HashSet::new(),
+            data_directory: path!(&criterion_home, "data", timeline),
+            all_titles: HashMap::new(),
             all_directories: HashSet::new(),
             groups: LinkedHashMap::new(),
+            namespace_by_package: false,
+            timed_out: vec![],
+            criterion_home,
+            run: None,
             history_id,
             history_description,
+            environment,
+            git,
+            tags: BTreeMap::new(),
+            retention,
+            baseline: None,
         };
 
         for entry in WalkDir::new(&model.data_directory)
@@ -87,57 +149,87 @@ impl Model {
             .filter(|entry| entry.file_name() == OsStr::new("benchmark.cbor"))
         {
             if let Err(e) = model.load_stored_benchmark(entry.path()) {
-                error!("Encountered error while loading stored data: {}", e)
+                if SchemaError::is_too_new(&e) {
+                    return Err(e);
+                }
+                error!(
+                    "Encountered error while loading stored data; it will be ignored: {:?}",
+                    e
+                )
             }
         }
 
-        model
+        Ok(model)
     }
 
     fn load_stored_benchmark(&mut self, benchmark_path: &Path) -> Result<()> {
         if !benchmark_path.is_file() {
             return Ok(());
         }
-        let mut benchmark_file = File::open(benchmark_path)
-            .with_context(|| format!("Failed to open benchmark file {:?}", benchmark_path))?;
-        let benchmark_record: BenchmarkRecord = serde_cbor::from_reader(&mut benchmark_file)
-            .with_context(|| format!("Failed to read benchmark file {:?}", benchmark_path))?;
+        let benchmark_record = load_benchmark_record(benchmark_path)?;
 
-        let measurement_path = benchmark_path.with_file_name(benchmark_record.latest_record);
+        let measurement_path = benchmark_path.with_file_name(&benchmark_record.latest_record);
         if !measurement_path.is_file() {
+            warn!(
+                "The latest measurement of benchmark {} ({:?}) is missing. Run `cargo criterion doctor` to repair the stored data.",
+                BenchmarkId::from(benchmark_record.id).as_title(),
+                measurement_path
+            );
             return Ok(());
         }
-        let mut measurement_file = File::open(&measurement_path)
-            .with_context(|| format!("Failed to open measurement file {:?}", measurement_path))?;
-        let saved_stats: SavedStatistics = serde_cbor::from_reader(&mut measurement_file)
-            .with_context(|| format!("Failed to read measurement file {:?}", measurement_path))?;
+        let saved_stats = load_measurement(&measurement_path)?;
 
-        self.groups
-            .entry(benchmark_record.id.group_id.clone())
-            .or_insert_with(Default::default)
-            .benchmarks
-            .insert(benchmark_record.id.into(), Benchmark::new(saved_stats));
+        let package = benchmark_record.id.package.clone();
+        let target = benchmark_record.id.target.clone();
+        let id = BenchmarkId::from(benchmark_record.id);
+        let group = self.groups.entry(group_key(&id)).or_default();
+        if group.target.is_none() {
+            group.target = target.clone();
+            group.package = package.clone();
+        }
+        let mut benchmark = Benchmark::new(saved_stats);
+        benchmark.target = target;
+        benchmark.package = package;
+        group.benchmarks.insert(id, benchmark);
         Ok(())
     }
 
-    pub fn add_benchmark_id(&mut self, target: &str, id: &mut BenchmarkId) {
+    /// Store the data and reports of each package in separate directories. This is used when
+    /// benchmarking a whole workspace, where packages may well use the same benchmark IDs.
+    pub fn set_namespace_by_package(&mut self, namespace_by_package: bool) {
+        self.namespace_by_package = namespace_by_package;
+    }
+
+    fn namespace<'a>(&self, target: &'a BenchTarget) -> Option<&'a str> {
+        if self.namespace_by_package {
+            Some(&target.package)
+        } else {
+            None
+        }
+    }
+
+    pub fn add_benchmark_id(&mut self, target: &BenchTarget, id: &mut BenchmarkId) {
+        *id = id.clone().in_namespace(self.namespace(target));
         id.ensure_directory_name_unique(&self.all_directories);
         self.all_directories
             .insert(id.as_directory_name().to_owned());
 
-        id.ensure_title_unique(&self.all_titles);
-        self.all_titles.insert(id.as_title().to_owned());
+        let titles = self
+            .all_titles
+            .entry(id.namespace().map(ToOwned::to_owned))
+            .or_default();
+        id.ensure_title_unique(titles);
+        titles.insert(id.as_title().to_owned());
 
-        let group = self
-            .groups
-            .entry(id.group_id.clone())
-            .or_insert_with(Default::default);
+        let group = self.groups.entry(group_key(id)).or_default();
 
         if let Some(mut benchmark) = group.benchmarks.remove(id) {
-            if let Some(target) = &benchmark.target {
-                warn!("Benchmark ID {} encountered multiple times. Benchmark IDs must be unique. First seen in the benchmark target '{}'", id.as_title(), target);
+            if benchmark.seen {
+                warn!("Benchmark ID {} encountered multiple times. Benchmark IDs must be unique. First seen in the benchmark target '{}'", id.as_title(), benchmark.target.as_deref().unwrap_or_default());
             } else {
-                benchmark.target = Some(target.to_owned());
+                benchmark.target = Some(target.name.clone());
+                benchmark.package = Some(target.package.clone());
+                benchmark.seen = true;
             }
 
             // Remove and re-insert to move the benchmark to the end of its list.
@@ -147,8 +239,11 @@ impl Model {
 
     pub fn benchmark_complete(
         &mut self,
+        target: &BenchTarget,
         id: &BenchmarkId,
         analysis_results: &MeasurementData,
+        environment: &Environment,
+        unit: Option<String>,
     ) -> Result<()> {
         let dir = path!(&self.data_directory, id.as_directory_name());
 
@@ -176,123 +271,582 @@ impl Model {
                 .map(get_change_direction),
             history_id: self.history_id.clone(),
             history_description: self.history_description.clone(),
+            environment: Some(environment.clone()),
+            git: self.git.clone(),
+            tags: self.tags.clone(),
+            unit,
         };
 
         let measurement_path = dir.join(&measurement_name);
-        let mut measurement_file = File::create(&measurement_path)
-            .with_context(|| format!("Failed to create measurement file {:?}", measurement_path))?;
-        serde_cbor::to_writer(&mut measurement_file, &saved_stats).with_context(|| {
-            format!("Failed to save measurements to file {:?}", measurement_path)
-        })?;
+        save_atomically(&measurement_path, |writer| {
+            schema::to_writer(writer, &saved_stats)
+        })
+        .with_context(|| format!("Failed to save measurements to file {:?}", measurement_path))?;
 
         let record = BenchmarkRecord {
-            id: id.into(),
+            id: SavedBenchmarkId::from(id).with_target(&target.package, &target.name),
             latest_record: PathBuf::from(&measurement_name),
         };
 
         let benchmark_path = dir.join("benchmark.cbor");
-        let mut benchmark_file = File::create(&benchmark_path)
-            .with_context(|| format!("Failed to create benchmark file {:?}", benchmark_path))?;
-        serde_cbor::to_writer(&mut benchmark_file, &record)
-            .with_context(|| format!("Failed to save benchmark file {:?}", benchmark_path))?;
+        save_benchmark_record(&benchmark_path, &record)?;
+
+        append_to_history_index(
+            &dir,
+            &HistoryRecord::new(measurement_name.into(), &saved_stats),
+        )
+        .with_context(|| format!("Failed to update the history index in {:?}", dir))?;
+
+        if self.retention.is_enabled() {
+            crate::gc::prune_measurements(&dir, &record.latest_record, &self.retention, false)
+                .with_context(|| format!("Failed to delete old measurements in {:?}", dir))?;
+        }
 
         let benchmark_entry = self
             .groups
-            .get_mut(&id.group_id)
+            .get_mut(&group_key(id))
             .unwrap()
             .benchmarks
             .entry(id.clone());
 
         match benchmark_entry {
             vacant @ linked_hash_map::Entry::Vacant(_) => {
-                vacant.or_insert(Benchmark::new(saved_stats));
+                let benchmark = vacant.or_insert(Benchmark::new(saved_stats));
+                benchmark.target = Some(target.name.clone());
+                benchmark.package = Some(target.package.clone());
+                benchmark.seen = true;
             }
             linked_hash_map::Entry::Occupied(mut occupied) => {
                 occupied.get_mut().add_stats(saved_stats)
             }
         };
+        self.update_run(|run| run.benchmark_complete(target, id));
+        Ok(())
+    }
+
+    /// The environment that this run is executing in.
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Label the measurements saved by this run with the given tags.
+    pub fn set_tags(&mut self, tags: BTreeMap<String, String>) {
+        self.tags = tags;
+    }
+
+    /// Load the latest measurements of the given timeline (eg. one created by importing a
+    /// colleague's results) and compare against them instead of the previous measurements. If
+    /// any tags are given, the latest measurements with all of those tags are used instead.
+    pub fn load_baseline(
+        &mut self,
+        criterion_home: &Path,
+        timeline: &str,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let data_directory = timeline_directory(criterion_home, timeline)?;
+        if !data_directory.is_dir() {
+            return Err(anyhow!(
+                "There is no baseline named '{}' (expected to find {:?})",
+                timeline,
+                data_directory
+            ));
+        }
+
+        let mut baseline = HashMap::new();
+        for (dir, record) in stored_benchmarks(&data_directory)? {
+            let measurement = if tags.is_empty() {
+                Some(record.latest_record)
+            } else {
+                read_history_index(&dir)?
+                    .into_iter()
+                    .filter(|entry| entry.has_tags(tags))
+                    .max_by_key(|entry| entry.datetime)
+                    .map(|entry| entry.measurement)
+            };
+            let measurement = match measurement {
+                Some(measurement) => measurement,
+                None => continue,
+            };
+            match load_measurement(&dir.join(measurement)) {
+                Ok(stats) => {
+                    baseline.insert(BenchmarkId::from(record.id), stats);
+                }
+                Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+                Err(e) => error!(
+                    "Encountered error while loading the baseline; it will be ignored: {:?}",
+                    e
+                ),
+            }
+        }
+        if baseline.is_empty() {
+            warn!(
+                "No stored measurements in the '{}' timeline match the baseline; nothing will be compared",
+                timeline
+            );
+        }
+        self.baseline = Some(baseline);
         Ok(())
     }
 
+    /// The measurements that the next measurement of the given benchmark should be compared
+    /// against.
     pub fn get_last_sample(&self, id: &BenchmarkId) -> Option<&SavedStatistics> {
+        if let Some(baseline) = &self.baseline {
+            return baseline.get(id);
+        }
         self.groups
-            .get(&id.group_id)
+            .get(&group_key(id))
             .and_then(|g| g.benchmarks.get(id))
             .map(|b| &b.latest_stats)
     }
 
-    pub fn check_benchmark_group(&self, current_target: &str, group: &str) {
-        if let Some(benchmark_group) = self.groups.get(group) {
-            if let Some(target) = &benchmark_group.target {
-                if target != current_target {
+    /// Record that the given benchmark was killed for running too long.
+    pub fn benchmark_timed_out(&mut self, id: &BenchmarkId) {
+        self.timed_out.push(id.clone());
+    }
+
+    pub fn timed_out_benchmarks(&self) -> &[BenchmarkId] {
+        &self.timed_out
+    }
+
+    pub fn resources_used(&mut self, target: &BenchTarget, usage: &ResourceUsage) {
+        self.update_run(|run| run.resources_used(target, usage));
+    }
+
+    /// The resources used by each benchmark target which ran during this run.
+    pub fn target_resources(&self) -> Vec<TargetResources> {
+        (self.run.iter())
+            .flat_map(RunRecord::resource_usage)
+            .map(|(package, target, usage)| TargetResources {
+                package: package.to_owned(),
+                target: target.to_owned(),
+                usage: usage.clone(),
+            })
+            .collect()
+    }
+
+    pub fn benchmark_timed(&mut self, target: &BenchTarget, id: &BenchmarkId, phases: PhaseTimes) {
+        self.update_run(|run| run.benchmark_timed(target, id, phases));
+    }
+
+    pub fn target_timed(&mut self, target: &BenchTarget, duration: std::time::Duration) {
+        self.update_run(|run| run.target_timed(target, duration.as_secs_f64()));
+    }
+
+    /// The wall-clock time spent on each benchmark which completed during this run.
+    pub fn benchmark_times(&self) -> Vec<BenchmarkTime> {
+        // Packages may well use the same benchmark IDs when they are namespaced.
+        let ids: HashMap<(Option<&str>, &str), &BenchmarkId> = (self.groups.values())
+            .flat_map(|group| group.benchmarks.keys())
+            .map(|id| ((id.namespace(), id.as_full_id()), id))
+            .collect();
+        (self.run.iter())
+            .flat_map(RunRecord::benchmark_times)
+            .filter_map(|(package, target, id, phases)| {
+                let namespace = if self.namespace_by_package {
+                    Some(package)
+                } else {
+                    None
+                };
+                Some(BenchmarkTime {
+                    id: (*ids.get(&(namespace, id))?).clone(),
+                    package: package.to_owned(),
+                    target: target.to_owned(),
+                    phases: phases.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The wall-clock time spent on each benchmark target which ran during this run.
+    pub fn target_times(&self) -> Vec<TargetTime> {
+        (self.run.iter())
+            .flat_map(RunRecord::target_seconds)
+            .map(|(package, target, seconds)| TargetTime {
+                package: package.to_owned(),
+                target: target.to_owned(),
+                seconds,
+            })
+            .collect()
+    }
+
+    /// Start keeping a record of this run's progress, so that it can be resumed with `--resume`
+    /// if it's interrupted.
+    pub fn start_run(&mut self, run: RunRecord) -> Result<()> {
+        run.save(&self.criterion_home)?;
+        self.run = Some(run);
+        Ok(())
+    }
+
+    pub fn run_record(&self) -> Option<&RunRecord> {
+        self.run.as_ref()
+    }
+
+    pub fn target_complete(&mut self, target: &BenchTarget) {
+        self.update_run(|run| run.target_complete(target));
+    }
+
+    /// Record the timing jitter measured by the stability probe after the benchmarks.
+    pub fn set_jitter_after(&mut self, jitter: f64) {
+        self.update_run(|run| run.jitter_after = Some(jitter));
+    }
+
+    /// Mark this run as finished, so that it can't be resumed.
+    pub fn finish_run(&mut self) {
+        self.update_run(|run| run.finished = true);
+    }
+
+    fn update_run<F: FnOnce(&mut RunRecord)>(&mut self, update: F) {
+        if let Some(run) = &mut self.run {
+            update(run);
+            if let Err(e) = run.save(&self.criterion_home) {
+                error!("{:#}", e);
+            }
+        }
+    }
+
+    pub fn check_benchmark_group(&self, current_target: &BenchTarget, group: &str) {
+        let key = namespaced_group_key(self.namespace(current_target), group);
+        if let Some(benchmark_group) = self.groups.get(&key) {
+            if let (true, Some(target)) = (benchmark_group.seen, &benchmark_group.target) {
+                if target != &current_target.name {
                     warn!("Benchmark group {} encountered again. Benchmark group IDs must be unique. First seen in the benchmark target '{}'", group, target);
                 }
             }
         }
     }
 
-    pub fn add_benchmark_group(&mut self, target: &str, group_name: &str) -> &BenchmarkGroup {
+    pub fn add_benchmark_group(
+        &mut self,
+        target: &BenchTarget,
+        group_name: &str,
+    ) -> &BenchmarkGroup {
+        let key = namespaced_group_key(self.namespace(target), group_name);
         // Remove and reinsert so that the group will be at the end of the map.
-        let mut group = self.groups.remove(group_name).unwrap_or_default();
-        group.target = Some(target.to_owned());
-        self.groups.insert(group_name.to_owned(), group);
-        self.groups.get(group_name).unwrap()
+        let mut group = self.groups.remove(&key).unwrap_or_default();
+        group.target = Some(target.name.clone());
+        group.package = Some(target.package.clone());
+        group.seen = true;
+        self.groups.insert(key.clone(), group);
+        self.groups.get(&key).unwrap()
     }
 
-    pub fn load_history(&self, id: &BenchmarkId) -> Result<Vec<SavedStatistics>> {
-        let dir = path!(&self.data_directory, id.as_directory_name());
+    /// Load the summaries of all of the stored measurements of the given benchmark, oldest first.
+    pub fn load_history(&self, id: &BenchmarkId) -> Result<Vec<HistoryRecord>> {
+        read_history_index(&path!(&self.data_directory, id.as_directory_name()))
+    }
+}
 
-        fn load_from(measurement_path: &Path) -> Result<SavedStatistics> {
-            let mut measurement_file = File::open(measurement_path).with_context(|| {
-                format!("Failed to open measurement file {:?}", measurement_path)
-            })?;
-            serde_cbor::from_reader(&mut measurement_file)
-                .with_context(|| format!("Failed to read measurement file {:?}", measurement_path))
+/// Returns the key of the given benchmark's group in `Model::groups`.
+fn group_key(id: &BenchmarkId) -> String {
+    namespaced_group_key(id.namespace(), &id.group_id)
+}
+
+fn namespaced_group_key(namespace: Option<&str>, group_id: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}/{}", namespace, group_id),
+        None => group_id.to_owned(),
+    }
+}
+
+/// Returns the data directory of the named timeline. The default timeline is "main"; others can
+/// be created by importing results bundles.
+pub fn timeline_directory(criterion_home: &Path, timeline: &str) -> Result<PathBuf> {
+    let mut components = Path::new(timeline).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(path!(criterion_home, "data", timeline)),
+        _ => Err(anyhow!("'{}' is not a valid timeline name", timeline)),
+    }
+}
+
+/// Returns the names of the timelines stored in the given CRITERION_HOME.
+pub fn list_timelines(criterion_home: &Path) -> Result<Vec<String>> {
+    let data_directory = criterion_home.join("data");
+    if !data_directory.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut timelines = vec![];
+    for entry in fs::read_dir(&data_directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            timelines.push(entry.file_name().to_string_lossy().into_owned());
         }
+    }
+    timelines.sort();
+    Ok(timelines)
+}
 
-        let mut stats = Vec::new();
-        for entry in WalkDir::new(dir)
-            .max_depth(1)
-            .into_iter()
-            // Ignore errors.
-            .filter_map(::std::result::Result::ok)
-        {
-            let name_str = entry.file_name().to_string_lossy();
-            if name_str.starts_with("measurement_") && name_str.ends_with(".cbor") {
-                match load_from(entry.path()) {
-                    Ok(saved_stats) => stats.push(saved_stats),
-                    Err(e) => error!(
-                        "Unexpected error loading benchmark history from file {}: {:?}",
-                        entry.path().display(),
-                        e
-                    ),
-                }
+/// Load the benchmark record stored in the given `benchmark.cbor` file.
+pub fn load_benchmark_record(benchmark_path: &Path) -> Result<BenchmarkRecord> {
+    let mut benchmark_file = File::open(benchmark_path)
+        .with_context(|| format!("Failed to open benchmark file {:?}", benchmark_path))?;
+    schema::from_reader(&mut benchmark_file)
+        .with_context(|| format!("Failed to read benchmark file {:?}", benchmark_path))
+}
+
+/// Save the given benchmark record to the given `benchmark.cbor` file.
+pub fn save_benchmark_record(benchmark_path: &Path, record: &BenchmarkRecord) -> Result<()> {
+    save_atomically(benchmark_path, |writer| schema::to_writer(writer, record))
+        .with_context(|| format!("Failed to save benchmark file {:?}", benchmark_path))
+}
+
+/// Write a file by writing to a temporary file in the same directory and then renaming it into
+/// place, so that a run which is killed part-way through never leaves a truncated file behind.
+pub fn save_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(TEMP_SUFFIX);
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let temp_file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create file {:?}", temp_path))?;
+        let mut writer = BufWriter::new(temp_file);
+        write(&mut writer)?;
+        let temp_file = writer.into_inner().map_err(|e| e.into_error())?;
+        temp_file.sync_all()?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to rename {:?} to {:?}", temp_path, path))
+    })();
+    if result.is_err() {
+        // Don't leave the partial file lying around.
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Load the statistics stored in the given measurement file.
+pub fn load_measurement(measurement_path: &Path) -> Result<SavedStatistics> {
+    let mut measurement_file = File::open(measurement_path)
+        .with_context(|| format!("Failed to open measurement file {:?}", measurement_path))?;
+    schema::from_reader(&mut measurement_file)
+        .with_context(|| format!("Failed to read measurement file {:?}", measurement_path))
+}
+
+/// Find and load all of the benchmark records stored under the given data directory. Returns the
+/// directory of each benchmark along with its record. Records which can't be read are skipped,
+/// unless they were saved by a newer version of cargo-criterion.
+pub fn stored_benchmarks(data_directory: &Path) -> Result<Vec<(PathBuf, BenchmarkRecord)>> {
+    let mut benchmarks = vec![];
+    for entry in WalkDir::new(data_directory)
+        .into_iter()
+        // Ignore errors.
+        .filter_map(::std::result::Result::ok)
+        .filter(|entry| entry.file_name() == OsStr::new("benchmark.cbor"))
+    {
+        match load_benchmark_record(entry.path()) {
+            Ok(record) => benchmarks.push((entry.path().parent().unwrap().to_owned(), record)),
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(e) => error!(
+                "Encountered error while loading stored data; it will be ignored: {:?}",
+                e
+            ),
+        }
+    }
+    Ok(benchmarks)
+}
+
+/// Load the history index of the benchmark stored in the given directory. If the index doesn't
+/// exist (eg. because the data was saved by an older version of cargo-criterion) or is damaged,
+/// it is rebuilt from the measurement files.
+pub fn load_history_index(dir: &Path) -> Result<Vec<HistoryRecord>> {
+    match read_stored_history_index(dir)? {
+        Some(records) => Ok(records),
+        None => rebuild_history_index(dir),
+    }
+}
+
+/// Like `load_history_index`, but never writes to the benchmark's directory: a missing or damaged
+/// index is rebuilt in memory only. This is for commands which only read the stored data.
+pub fn read_history_index(dir: &Path) -> Result<Vec<HistoryRecord>> {
+    match read_stored_history_index(dir)? {
+        Some(records) => Ok(records),
+        None => history_from_measurements(dir),
+    }
+}
+
+/// Rebuild the history index of the benchmark stored in the given directory by loading every
+/// measurement file, and return the new index.
+pub fn rebuild_history_index(dir: &Path) -> Result<Vec<HistoryRecord>> {
+    let records = history_from_measurements(dir)?;
+    write_history_index(dir, &records)?;
+    Ok(records)
+}
+
+/// Read the history index stored in the given directory. Returns None if there is no index or it
+/// is damaged.
+pub fn read_stored_history_index(dir: &Path) -> Result<Option<Vec<HistoryRecord>>> {
+    let index_path = dir.join(HISTORY_INDEX);
+    if !index_path.is_file() {
+        return Ok(None);
+    }
+
+    let index_file = File::open(&index_path)
+        .with_context(|| format!("Failed to open history index {:?}", index_path))?;
+    let mut records: Vec<HistoryRecord> = vec![];
+    for value in serde_cbor::Deserializer::from_reader(BufReader::new(index_file))
+        .into_iter::<serde_cbor::Value>()
+    {
+        match value.map_err(Into::into).and_then(schema::from_value) {
+            Ok(record) => records.push(record),
+            Err(e) if SchemaError::is_too_new(&e) => {
+                return Err(e.context(format!("Failed to read history index {:?}", index_path)))
+            }
+            Err(e) => {
+                warn!(
+                    "History index {:?} is damaged ({}); reading the history from the stored measurements instead.",
+                    index_path, e
+                );
+                return Ok(None);
             }
         }
+    }
+    records.sort_by_key(|record| record.datetime);
+    Ok(Some(records))
+}
+
+/// Load the summaries of every measurement file in the given directory, oldest first.
+fn history_from_measurements(dir: &Path) -> Result<Vec<HistoryRecord>> {
+    let mut records = vec![];
+    for entry in WalkDir::new(dir)
+        .max_depth(1)
+        .into_iter()
+        // Ignore errors.
+        .filter_map(::std::result::Result::ok)
+        .filter(|entry| is_measurement_file(entry.file_name()))
+    {
+        match load_measurement(entry.path()) {
+            Ok(saved_stats) => records.push(HistoryRecord::new(
+                PathBuf::from(entry.file_name()),
+                &saved_stats,
+            )),
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(e) => error!(
+                "Unexpected error loading benchmark history from file {}: {:?}",
+                entry.path().display(),
+                e
+            ),
+        }
+    }
+    records.sort_by_key(|record| record.datetime);
+    Ok(records)
+}
+
+/// Replace the history index of the benchmark stored in the given directory.
+pub fn write_history_index(dir: &Path, records: &[HistoryRecord]) -> Result<()> {
+    let index_path = dir.join(HISTORY_INDEX);
+    save_atomically(&index_path, |writer| {
+        for record in records {
+            schema::to_writer(&mut *writer, record)?;
+        }
+        Ok(())
+    })
+    .with_context(|| format!("Failed to write history index {:?}", index_path))
+}
 
-        stats.sort_unstable_by_key(|st| st.datetime);
+/// Remove the given measurements (by file name) from the history index of the benchmark stored
+/// in the given directory.
+pub fn remove_from_history_index(dir: &Path, measurements: &[PathBuf]) -> Result<()> {
+    let mut records = load_history_index(dir)?;
+    records.retain(|record| !measurements.contains(&record.measurement));
+    write_history_index(dir, &records)
+}
 
-        Ok(stats)
+fn append_to_history_index(dir: &Path, record: &HistoryRecord) -> Result<()> {
+    let index_path = dir.join(HISTORY_INDEX);
+    if !index_path.is_file() {
+        // The new measurement has already been saved, so it will be picked up by the rebuild.
+        return rebuild_history_index(dir).map(|_| ());
     }
+
+    let index_file = OpenOptions::new()
+        .append(true)
+        .open(&index_path)
+        .with_context(|| format!("Failed to open history index {:?}", index_path))?;
+    schema::to_writer(index_file, record)
+        .with_context(|| format!("Failed to write history index {:?}", index_path))
+}
+
+/// Returns true if the given file name looks like one of our measurement files.
+pub fn is_measurement_file(file_name: &OsStr) -> bool {
+    let name_str = file_name.to_string_lossy();
+    name_str.starts_with("measurement_") && name_str.ends_with(".cbor")
 }
 
 // These structs are saved to disk and may be read by future versions of cargo-criterion, so
-// backwards compatibility is important.
+// backwards compatibility is important. Once a version has been released, any change to their
+// layout, including new optional fields, must bump the schema version by adding a migration to
+// the struct's Versioned impl (see the schema module).
 
-#[derive(Debug, Deserialize, Serialize)]
+// The name of the file in each benchmark directory which holds the history index. This is a
+// sequence of CBOR-encoded HistoryRecords, one per measurement, which is appended to as new
+// measurements are saved.
+pub const HISTORY_INDEX: &str = "history.cbor";
+
+// Files are written under a name with this suffix and then renamed into place.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SavedBenchmarkId {
     group_id: String,
     function_id: Option<String>,
     value_str: Option<String>,
     throughput: Option<Throughput>,
+    // The package and bench target that the benchmark was run from. Older versions of
+    // cargo-criterion did not record these.
+    #[serde(default)]
+    pub package: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    // The package that the benchmark's directories are nested under, if any.
+    #[serde(default)]
+    namespace: Option<String>,
+}
+impl SavedBenchmarkId {
+    /// Returns a copy of this ID with the given full ID (eg. "group/function/value"). The new ID
+    /// is split into parts so that it has the same shape as this one where possible. For
+    /// example, if this ID has a group and a value but no function, so will the result.
+    pub fn renamed(&self, new_id: &str) -> SavedBenchmarkId {
+        let mut parts = new_id.splitn(3, '/').map(ToOwned::to_owned);
+        let group_id = parts.next().unwrap_or_default();
+        let rest: Vec<String> = parts.collect();
+        let (function_id, value_str) = match (&self.function_id, &self.value_str, rest.len()) {
+            (_, _, 0) => (None, None),
+            (Some(_), None, _) => (Some(rest.join("/")), None),
+            (None, Some(_), _) => (None, Some(rest.join("/"))),
+            (_, _, 1) => (Some(rest[0].clone()), None),
+            (_, _, _) => (Some(rest[0].clone()), Some(rest[1].clone())),
+        };
+        SavedBenchmarkId {
+            group_id,
+            function_id,
+            value_str,
+            throughput: self.throughput.clone(),
+            package: self.package.clone(),
+            target: self.target.clone(),
+            namespace: self.namespace.clone(),
+        }
+    }
+
+    /// Returns a copy of this ID which records the package and bench target it was run from.
+    pub fn with_target(mut self, package: &str, target: &str) -> SavedBenchmarkId {
+        self.package = Some(package.to_owned());
+        self.target = Some(target.to_owned());
+        self
+    }
 }
 impl From<BenchmarkId> for SavedBenchmarkId {
     fn from(other: BenchmarkId) -> Self {
         SavedBenchmarkId {
+            namespace: other.namespace().map(ToOwned::to_owned),
             group_id: other.group_id,
             function_id: other.function_id,
             value_str: other.value_str,
             throughput: other.throughput,
+            package: None,
+            target: None,
         }
     }
 }
@@ -309,13 +863,19 @@ impl From<SavedBenchmarkId> for BenchmarkId {
             other.value_str,
             other.throughput,
         )
+        .in_namespace(other.namespace.as_deref())
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct BenchmarkRecord {
-    id: SavedBenchmarkId,
-    latest_record: PathBuf,
+pub struct BenchmarkRecord {
+    pub id: SavedBenchmarkId,
+    // The file name of the most recent measurement, relative to the benchmark's directory.
+    pub latest_record: PathBuf,
+}
+impl Versioned for BenchmarkRecord {
+    const NAME: &'static str = "benchmark record";
+    const MIGRATIONS: &'static [Migration] = &[schema::unversioned];
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -325,8 +885,19 @@ pub enum ChangeDirection {
     Improved,
     Regressed,
 }
+impl ChangeDirection {
+    /// A short name for the change direction, for use in exported data.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeDirection::NoChange => "no_change",
+            ChangeDirection::NotSignificant => "not_significant",
+            ChangeDirection::Improved => "improved",
+            ChangeDirection::Regressed => "regressed",
+        }
+    }
+}
 
-fn get_change_direction(comp: &ComparisonData) -> ChangeDirection {
+pub fn get_change_direction(comp: &ComparisonData) -> ChangeDirection {
     if comp.p_value < comp.significance_threshold {
         return ChangeDirection::NoChange;
     }
@@ -371,4 +942,444 @@ pub struct SavedStatistics {
     // An optional user-provided description. This might be a version control commit message or
     // something custom.
     pub history_description: Option<String>,
+
+    // The machine and toolchain that these measurements were taken with. Older versions of
+    // cargo-criterion did not record this.
+    pub environment: Option<Environment>,
+    // The git commit that these measurements were taken from, if known.
+    pub git: Option<GitMetadata>,
+    // User-provided labels for the run, such as `compiler=nightly`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    // The unit of the measured values, as reported by the benchmark target (eg. "ns"). Older
+    // versions of cargo-criterion did not record this; they almost always measured wall-clock
+    // time in nanoseconds.
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+impl Versioned for SavedStatistics {
+    const NAME: &'static str = "measurement";
+    const MIGRATIONS: &'static [Migration] = &[schema::unversioned];
+}
+
+/// Summary of a single saved measurement. These are stored in the history index so that the
+/// history reports can be generated without loading the raw samples of every measurement.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryRecord {
+    // The file name of the full measurement, relative to the benchmark's directory.
+    pub measurement: PathBuf,
+    pub datetime: DateTime<Utc>,
+    pub estimates: Estimates,
+    pub throughput: Option<Throughput>,
+    pub changes: Option<ChangeEstimates>,
+    pub change_direction: Option<ChangeDirection>,
+    pub history_id: Option<String>,
+    pub history_description: Option<String>,
+    pub environment: Option<Environment>,
+    pub git: Option<GitMetadata>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+impl Versioned for HistoryRecord {
+    const NAME: &'static str = "history index entry";
+    const MIGRATIONS: &'static [Migration] = &[schema::unversioned];
+}
+impl HistoryRecord {
+    pub fn new(measurement: PathBuf, stats: &SavedStatistics) -> HistoryRecord {
+        HistoryRecord {
+            measurement,
+            datetime: stats.datetime,
+            estimates: stats.estimates.clone(),
+            throughput: stats.throughput.clone(),
+            changes: stats.changes.clone(),
+            change_direction: stats.change_direction.clone(),
+            history_id: stats.history_id.clone(),
+            history_description: stats.history_description.clone(),
+            environment: stats.environment.clone(),
+            git: stats.git.clone(),
+            tags: stats.tags.clone(),
+            unit: stats.unit.clone(),
+        }
+    }
+
+    /// Returns true if this measurement was explicitly labeled by the user, with tags or a history
+    /// ID, as opposed to having only the history ID that is derived automatically from the git
+    /// commit.
+    pub fn is_tagged(&self) -> bool {
+        if !self.tags.is_empty() {
+            return true;
+        }
+        match (&self.history_id, &self.git) {
+            (Some(id), Some(git)) => id != git.short_commit(),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Returns true if this measurement has all of the given tags.
+    pub fn has_tags(&self, tags: &BTreeMap<String, String>) -> bool {
+        tags.iter()
+            .all(|(key, value)| self.tags.get(key) == Some(value))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::estimate::{ConfidenceInterval, Estimate};
+    use chrono::TimeZone;
+    use std::io::Write;
+
+    /// An estimate with the given point estimate and no uncertainty.
+    pub fn estimate(point_estimate: f64) -> Estimate {
+        Estimate {
+            confidence_interval: ConfidenceInterval {
+                confidence_level: 0.95,
+                lower_bound: point_estimate,
+                upper_bound: point_estimate,
+            },
+            point_estimate,
+            standard_error: 0.0,
+        }
+    }
+
+    /// A measurement taken at the given Unix time, whose estimates are all `point_estimate`.
+    pub fn saved_statistics(timestamp: i64, point_estimate: f64) -> SavedStatistics {
+        SavedStatistics {
+            datetime: Utc.timestamp_opt(timestamp, 0).unwrap(),
+            iterations: vec![1.0, 2.0, 3.0],
+            values: vec![point_estimate, 2.0 * point_estimate, 3.0 * point_estimate],
+            avg_values: vec![point_estimate; 3],
+            estimates: Estimates {
+                mean: estimate(point_estimate),
+                median: estimate(point_estimate),
+                median_abs_dev: estimate(0.0),
+                slope: Some(estimate(point_estimate)),
+                std_dev: estimate(0.0),
+            },
+            throughput: None,
+            changes: None,
+            change_direction: None,
+            history_id: None,
+            history_description: None,
+            environment: None,
+            git: None,
+            tags: BTreeMap::new(),
+            unit: None,
+        }
+    }
+
+    /// Save the given measurement in the given benchmark directory, without updating the index.
+    pub fn save_measurement(dir: &Path, file_name: &str, stats: &SavedStatistics) {
+        save_atomically(&dir.join(file_name), |writer| {
+            schema::to_writer(writer, stats)
+        })
+        .unwrap();
+    }
+
+    /// Store a benchmark with the given measurements (named after their timestamps, eg.
+    /// `measurement_1000.cbor`) in the given timeline directory, the last of which is the latest.
+    /// Returns the benchmark's directory.
+    pub fn store_benchmark(
+        data_directory: &Path,
+        id: &BenchmarkId,
+        measurements: &[SavedStatistics],
+    ) -> PathBuf {
+        let dir = data_directory.join(id.as_directory_name());
+        fs::create_dir_all(&dir).unwrap();
+        let mut records = vec![];
+        for stats in measurements {
+            let file_name = format!("measurement_{}.cbor", stats.datetime.timestamp());
+            save_measurement(&dir, &file_name, stats);
+            records.push(HistoryRecord::new(file_name.into(), stats));
+        }
+        write_history_index(&dir, &records).unwrap();
+        let record = BenchmarkRecord {
+            id: SavedBenchmarkId::from(id),
+            latest_record: records.last().unwrap().measurement.clone(),
+        };
+        save_benchmark_record(&dir.join("benchmark.cbor"), &record).unwrap();
+        dir
+    }
+
+    fn measurements(records: &[HistoryRecord]) -> Vec<&str> {
+        (records.iter())
+            .map(|record| record.measurement.to_str().unwrap())
+            .collect()
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> BTreeMap<String, String> {
+        (tags.iter())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn tagged_statistics(timestamp: i64, tag: &[(&str, &str)]) -> SavedStatistics {
+        SavedStatistics {
+            tags: tags(tag),
+            ..saved_statistics(timestamp, timestamp as f64)
+        }
+    }
+
+    #[test]
+    fn test_has_tags() {
+        let stats = tagged_statistics(1_000, &[("compiler", "nightly"), ("opt", "3")]);
+        let record = HistoryRecord::new("measurement_1000.cbor".into(), &stats);
+        assert!(record.has_tags(&tags(&[])));
+        assert!(record.has_tags(&tags(&[("compiler", "nightly")])));
+        assert!(record.has_tags(&tags(&[("compiler", "nightly"), ("opt", "3")])));
+        assert!(!record.has_tags(&tags(&[("compiler", "stable")])));
+        assert!(!record.has_tags(&tags(&[("compiler", "nightly"), ("lto", "fat")])));
+        assert!(!record.has_tags(&tags(&[("Compiler", "nightly")])));
+
+        let untagged =
+            HistoryRecord::new("measurement_1000.cbor".into(), &saved_statistics(1, 1.0));
+        assert!(untagged.has_tags(&tags(&[])));
+        assert!(!untagged.has_tags(&tags(&[("compiler", "nightly")])));
+    }
+
+    #[test]
+    fn test_load_tagged_baseline() {
+        let home = tempfile::tempdir().unwrap();
+        let id = BenchmarkId::new("fib".to_owned(), None, None, None);
+        store_benchmark(
+            &path!(home.path(), "data", "main"),
+            &id,
+            &[
+                tagged_statistics(1_000, &[("release", "1.3")]),
+                tagged_statistics(2_000, &[("release", "1.4"), ("compiler", "nightly")]),
+                tagged_statistics(3_000, &[("release", "1.4")]),
+                tagged_statistics(4_000, &[]),
+            ],
+        );
+        let baseline = |filter: &[(&str, &str)]| {
+            let mut model = Model::load(
+                home.path().to_owned(),
+                "main".into(),
+                None,
+                None,
+                Environment::default(),
+                None,
+                RetentionPolicy::default(),
+            )
+            .unwrap();
+            model
+                .load_baseline(home.path(), "main", &tags(filter))
+                .unwrap();
+            model
+                .get_last_sample(&id)
+                .map(|stats| stats.datetime.timestamp())
+        };
+
+        assert_eq!(baseline(&[]), Some(4_000));
+        assert_eq!(baseline(&[("release", "1.3")]), Some(1_000));
+        assert_eq!(baseline(&[("release", "1.4")]), Some(3_000));
+        assert_eq!(
+            baseline(&[("release", "1.4"), ("compiler", "nightly")]),
+            Some(2_000)
+        );
+        assert_eq!(baseline(&[("release", "1.5")]), None);
+    }
+
+    #[test]
+    fn test_benchmark_times_by_package() {
+        let home = tempfile::tempdir().unwrap();
+        let data_directory = path!(home.path(), "data", "main");
+        let id = |package| {
+            BenchmarkId::new("fib".to_owned(), None, None, None).in_namespace(Some(package))
+        };
+        store_benchmark(&data_directory, &id("a"), &[saved_statistics(1_000, 1.0)]);
+        store_benchmark(&data_directory, &id("b"), &[saved_statistics(1_000, 1.0)]);
+
+        let mut model = Model::load(
+            home.path().to_owned(),
+            "main".into(),
+            None,
+            None,
+            Environment::default(),
+            None,
+            RetentionPolicy::default(),
+        )
+        .unwrap();
+        model.set_namespace_by_package(true);
+        model
+            .start_run(RunRecord::new(None, None, BTreeMap::new()))
+            .unwrap();
+        for (package, seconds) in &[("a", 1.0), ("b", 2.0)] {
+            let target = BenchTarget {
+                name: "bench".to_owned(),
+                package: package.to_string(),
+                executable: PathBuf::new(),
+                runner: None,
+            };
+            let phases = PhaseTimes {
+                measurement_seconds: *seconds,
+                ..PhaseTimes::default()
+            };
+            model.benchmark_timed(&target, &id(package), phases);
+        }
+
+        let mut times: Vec<(String, Option<String>, f64)> = (model.benchmark_times().into_iter())
+            .map(|time| {
+                (
+                    time.package,
+                    time.id.namespace().map(ToOwned::to_owned),
+                    time.phases.measurement_seconds,
+                )
+            })
+            .collect();
+        times.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            times,
+            vec![
+                ("a".to_owned(), Some("a".to_owned()), 1.0),
+                ("b".to_owned(), Some("b".to_owned()), 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.cbor");
+        let temp_path = dir.path().join("file.cbor.tmp");
+        save_atomically(&path, |writer| {
+            writer.write_all(b"first").map_err(Into::into)
+        })
+        .unwrap();
+        save_atomically(&path, |writer| {
+            writer.write_all(b"second").map_err(Into::into)
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!temp_path.exists());
+
+        // A failed write leaves the old file in place and cleans up after itself.
+        let result = save_atomically(&path, |writer| {
+            writer.write_all(b"partial")?;
+            Err(anyhow!("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!temp_path.exists());
+    }
+
+    #[test]
+    fn test_load_history_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let older = saved_statistics(1_000, 1.0);
+        let newer = saved_statistics(2_000, 2.0);
+        save_measurement(dir.path(), "measurement_2.cbor", &newer);
+        save_measurement(dir.path(), "measurement_1.cbor", &older);
+        // The index isn't necessarily in order, but it's loaded oldest first.
+        write_history_index(
+            dir.path(),
+            &[
+                HistoryRecord::new("measurement_2.cbor".into(), &newer),
+                HistoryRecord::new("measurement_1.cbor".into(), &older),
+            ],
+        )
+        .unwrap();
+
+        let records = load_history_index(dir.path()).unwrap();
+        assert_eq!(
+            measurements(&records),
+            vec!["measurement_1.cbor", "measurement_2.cbor"]
+        );
+        assert_eq!(records[1].estimates.mean.point_estimate, 2.0);
+    }
+
+    #[test]
+    fn test_append_to_history_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = saved_statistics(1_000, 1.0);
+        save_measurement(dir.path(), "measurement_1.cbor", &first);
+        append_to_history_index(
+            dir.path(),
+            &HistoryRecord::new("measurement_1.cbor".into(), &first),
+        )
+        .unwrap();
+        assert!(dir.path().join(HISTORY_INDEX).is_file());
+
+        // Appending to an existing index doesn't look at the measurement files.
+        let second = saved_statistics(2_000, 2.0);
+        append_to_history_index(
+            dir.path(),
+            &HistoryRecord::new("measurement_2.cbor".into(), &second),
+        )
+        .unwrap();
+        let records = load_history_index(dir.path()).unwrap();
+        assert_eq!(
+            measurements(&records),
+            vec!["measurement_1.cbor", "measurement_2.cbor"]
+        );
+    }
+
+    #[test]
+    fn test_rebuild_missing_history_index() {
+        let dir = tempfile::tempdir().unwrap();
+        save_measurement(
+            dir.path(),
+            "measurement_1.cbor",
+            &saved_statistics(1_000, 1.0),
+        );
+        save_measurement(
+            dir.path(),
+            "measurement_2.cbor",
+            &saved_statistics(2_000, 2.0),
+        );
+        fs::write(dir.path().join("benchmark.cbor"), b"not a measurement").unwrap();
+        let index_path = dir.path().join(HISTORY_INDEX);
+
+        // Reading the history doesn't write the index...
+        let records = read_history_index(dir.path()).unwrap();
+        assert_eq!(
+            measurements(&records),
+            vec!["measurement_1.cbor", "measurement_2.cbor"]
+        );
+        assert!(!index_path.exists());
+
+        // ...but loading it does.
+        let records = load_history_index(dir.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(index_path.is_file());
+        assert_eq!(
+            read_stored_history_index(dir.path())
+                .unwrap()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_recover_damaged_history_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = saved_statistics(1_000, 1.0);
+        save_measurement(dir.path(), "measurement_1.cbor", &stats);
+        let index_path = dir.path().join(HISTORY_INDEX);
+        let mut damaged = serde_cbor::to_vec(
+            &schema::to_value(&HistoryRecord::new("measurement_1.cbor".into(), &stats)).unwrap(),
+        )
+        .unwrap();
+        damaged.truncate(damaged.len() / 2);
+        fs::write(&index_path, &damaged).unwrap();
+
+        assert!(read_stored_history_index(dir.path()).unwrap().is_none());
+        let records = read_history_index(dir.path()).unwrap();
+        assert_eq!(measurements(&records), vec!["measurement_1.cbor"]);
+        assert_eq!(fs::read(&index_path).unwrap(), damaged);
+
+        let records = load_history_index(dir.path()).unwrap();
+        assert_eq!(measurements(&records), vec!["measurement_1.cbor"]);
+        assert_eq!(
+            read_stored_history_index(dir.path())
+                .unwrap()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
 }
@@ -0,0 +1,234 @@
+//! Module which checks the machine for common sources of measurement noise before a run (Linux
+//! only): CPU frequency scaling, turbo boost, other processes competing for the CPU, swapping and
+//! virtualization.
+//!
+//! The results are printed as warnings and saved with the environment of each measurement, so
+//! that a noisy run can be recognized later. With `--strict-environment`, cargo-criterion refuses
+//! to run if any of the checks fail.
+//!
+//! Swapping is detected by comparing the swap counters over a short window just before the
+//! benchmarks run, separately from the other checks. Building the benchmarks can swap for reasons
+//! of its own, so the build mustn't fall inside the window.
+
+use crate::affinity::{parse_cpu_list, CpuSet};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+/// How long to watch the swap counters for before running the benchmarks.
+const SWAP_WINDOW: Duration = Duration::from_millis(500);
+
+/// The results of the noise checks. Every field is optional because the information may not be
+/// available on all machines.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseChecks {
+    /// The CPU frequency governors of the CPUs the benchmarks run on, other than `performance`.
+    pub governors: Vec<String>,
+    pub turbo_enabled: Option<bool>,
+    /// The 1-minute load average.
+    pub load_average: Option<f64>,
+    /// The number of pages swapped in or out during the short window before the benchmarks ran.
+    pub pages_swapped: Option<u64>,
+    /// The hypervisor or virtual machine vendor, if running in a virtual machine.
+    pub virtual_machine: Option<String>,
+    /// The number of CPUs, used to judge the load average.
+    pub cpus: Option<usize>,
+}
+impl NoiseChecks {
+    /// Describe the problems found by the checks. Returns an empty list if there are none.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if !self.governors.is_empty() {
+            warnings.push(format!(
+                "The CPU frequency governor is {} rather than 'performance', so the clock speed may vary.",
+                self.governors.join(", ")
+            ));
+        }
+        if self.turbo_enabled == Some(true) {
+            warnings.push(
+                "Turbo boost is enabled, so the clock speed depends on temperature and load."
+                    .to_owned(),
+            );
+        }
+        if let Some(load) = self.load_average {
+            let limit = load_limit(self.cpus.unwrap_or(1));
+            if load > limit {
+                warnings.push(format!(
+                    "The load average is {:.2}; other processes are competing for the CPU.",
+                    load
+                ));
+            }
+        }
+        warnings.extend(self.swap_warning());
+        if let Some(vm) = &self.virtual_machine {
+            warnings.push(format!(
+                "Running in a virtual machine ({}); other guests may disturb the measurements.",
+                vm
+            ));
+        }
+        warnings
+    }
+
+    /// Describe the swapping found by `sample_swapping`, if any.
+    pub fn swap_warning(&self) -> Option<String> {
+        let pages = self.pages_swapped.filter(|&pages| pages > 0)?;
+        Some(format!(
+            "The system is swapping ({} pages in the {} ms before the benchmarks started).",
+            pages,
+            SWAP_WINDOW.as_millis()
+        ))
+    }
+
+    /// Measure how much the system swaps over a short window. This should be done just before
+    /// the benchmarks run.
+    pub fn sample_swapping(&mut self) {
+        let before = read_pages_swapped();
+        if before.is_some() {
+            thread::sleep(SWAP_WINDOW);
+        }
+        self.pages_swapped = match (before, read_pages_swapped()) {
+            (Some(before), Some(after)) => Some(after.saturating_sub(before)),
+            _ => None,
+        };
+    }
+}
+
+/// The load average above which we warn: one busy process on small machines, or a quarter of
+/// the CPUs on larger ones.
+fn load_limit(cpus: usize) -> f64 {
+    (cpus as f64 / 4.0).max(1.0)
+}
+
+/// Run the noise checks for benchmarks running on the given CPUs (or on any CPU, if None), other
+/// than the check for swapping; see `NoiseChecks::sample_swapping`. Returns None on platforms
+/// where the checks aren't supported.
+#[cfg(target_os = "linux")]
+pub fn check(bench_cpus: Option<&CpuSet>) -> Option<NoiseChecks> {
+    let cpus: Vec<usize> = match bench_cpus {
+        Some(cpus) => cpus.iter().copied().collect(),
+        None => (0..)
+            .take_while(|cpu| fs::metadata(format!("/sys/devices/system/cpu/cpu{}", cpu)).is_ok())
+            .collect(),
+    };
+    let mut governors: Vec<String> = cpus
+        .iter()
+        .filter_map(|cpu| {
+            read_trimmed(&format!(
+                "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor",
+                cpu
+            ))
+        })
+        .filter(|governor| governor != "performance")
+        .map(|governor| format!("'{}'", governor))
+        .collect();
+    governors.sort();
+    governors.dedup();
+
+    let turbo_enabled = match read_trimmed("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        Some(no_turbo) => Some(no_turbo == "0"),
+        None => read_trimmed("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1"),
+    };
+
+    let load_average = read_trimmed("/proc/loadavg").and_then(|loadavg| parse_loadavg(&loadavg));
+
+    let virtual_machine = match fs::read_to_string("/proc/cpuinfo") {
+        Ok(cpuinfo) if has_hypervisor_flag(&cpuinfo) => Some(
+            read_trimmed("/sys/class/dmi/id/sys_vendor")
+                .or_else(|| read_trimmed("/sys/hypervisor/type"))
+                .unwrap_or_else(|| "unknown hypervisor".to_owned()),
+        ),
+        _ => None,
+    };
+
+    // This counts the CPUs of the whole machine, whereas available_parallelism only counts the
+    // ones that we're allowed to use, which `--cpus` restricts.
+    let online_cpus = read_trimmed("/sys/devices/system/cpu/online")
+        .and_then(|list| parse_cpu_list(&list).ok())
+        .map(|cpus| cpus.len());
+
+    Some(NoiseChecks {
+        governors,
+        turbo_enabled,
+        load_average,
+        pages_swapped: None,
+        virtual_machine,
+        cpus: online_cpus,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check(_bench_cpus: Option<&CpuSet>) -> Option<NoiseChecks> {
+    None
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim().to_owned())
+}
+
+fn read_pages_swapped() -> Option<u64> {
+    fs::read_to_string("/proc/vmstat")
+        .ok()
+        .and_then(|vmstat| parse_pages_swapped(&vmstat))
+}
+
+/// Returns the 1-minute load average from the contents of /proc/loadavg.
+fn parse_loadavg(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Returns the total number of pages swapped in and out from the contents of /proc/vmstat.
+fn parse_pages_swapped(vmstat: &str) -> Option<u64> {
+    let mut total = None;
+    for line in vmstat.lines() {
+        let mut split = line.split_whitespace();
+        if let (Some("pswpin"), Some(pages)) | (Some("pswpout"), Some(pages)) =
+            (split.next(), split.next())
+        {
+            *total.get_or_insert(0) += pages.parse::<u64>().ok()?;
+        }
+    }
+    total
+}
+
+fn has_hypervisor_flag(cpuinfo: &str) -> bool {
+    cpuinfo
+        .lines()
+        .filter(|line| line.starts_with("flags"))
+        .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        assert_eq!(parse_loadavg("0.34 0.58 0.51 2/72 9519\n"), Some(0.34));
+        assert_eq!(
+            parse_pages_swapped("pgpgout 12\npswpin 3\npswpout 4\nsomething 5\n"),
+            Some(7)
+        );
+        assert_eq!(parse_pages_swapped("pgpgout 12\n"), None);
+        assert!(has_hypervisor_flag(
+            "flags\t\t: fpu vme hypervisor lahf_lm\n"
+        ));
+        assert!(!has_hypervisor_flag("flags\t\t: fpu vme\n"));
+    }
+
+    #[test]
+    fn test_swap_warning() {
+        let checks = |pages_swapped| NoiseChecks {
+            pages_swapped,
+            ..NoiseChecks::default()
+        };
+        assert_eq!(checks(None).swap_warning(), None);
+        assert_eq!(checks(Some(0)).swap_warning(), None);
+        assert!(checks(Some(0)).warnings().is_empty());
+        let swapping = checks(Some(12));
+        assert!(swapping.swap_warning().unwrap().contains("12 pages"));
+        assert_eq!(swapping.warnings(), vec![swapping.swap_warning().unwrap()]);
+    }
+}
@@ -0,0 +1,391 @@
+//! Module implementing the `reanalyze` subcommand, which recomputes the statistics of stored
+//! measurements from their raw samples with different settings. The results are written to a
+//! separate timeline so that different settings can be compared without re-running the
+//! benchmarks.
+
+use crate::analysis::{analysis, BenchmarkConfig, MeasuredValues};
+use crate::config::{FullConfig, OutlierPolicy, ReanalyzeOptions};
+use crate::connection::SamplingMethod;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    get_change_direction, is_measurement_file, load_history_index, load_measurement,
+    save_atomically, save_benchmark_record, stored_benchmarks, timeline_directory,
+    write_history_index, BenchmarkRecord, HistoryRecord, SavedStatistics, HISTORY_INDEX,
+};
+use crate::report::BenchmarkId;
+use crate::schema::{self, SchemaError};
+use crate::stats::univariate::outliers::tukey;
+use crate::stats::univariate::Sample;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Entry point for the `reanalyze` subcommand.
+pub fn reanalyze(configuration: &FullConfig, options: &ReanalyzeOptions) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let criterion_home = &self_config.criterion_home;
+    if options.source == options.timeline {
+        return Err(anyhow!(
+            "The reanalyzed measurements must be written to a different timeline than '{}'",
+            options.source
+        ));
+    }
+    let source_directory = timeline_directory(criterion_home, &options.source)?;
+    let dest_directory = timeline_directory(criterion_home, &options.timeline)?;
+    if !source_directory.is_dir() {
+        return Err(anyhow!("There is no timeline named '{}'", options.source));
+    }
+
+    let _lock = DirectoryLock::acquire(
+        criterion_home,
+        LockKind::Exclusive,
+        self_config.wait_for_lock,
+    )?;
+
+    let config = BenchmarkConfig {
+        confidence_level: options.confidence_level,
+        _measurement_time: Duration::default(),
+        noise_threshold: options.noise_threshold,
+        nresamples: options.nresamples,
+        _sample_size: 0,
+        significance_level: options.significance_level,
+        _warm_up_time: Duration::default(),
+        test: options.test,
+    };
+
+    let mut reanalyzed = 0;
+    for (dir, record) in stored_benchmarks(&source_directory)? {
+        let id = BenchmarkId::from(record.id.clone());
+        if let Some(filter) = &options.filter {
+            if !filter.is_match(id.as_full_id()) {
+                continue;
+            }
+        }
+
+        eprintln!("Reanalyzing {}", id.as_title());
+        let dest_dir = dest_directory.join(id.as_directory_name());
+        match reanalyze_benchmark(&dir, &record, &dest_dir, &config, options.outliers) {
+            Ok(()) => reanalyzed += 1,
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(e) => error!(
+                "Failed to reanalyze benchmark {}; it will be skipped: {:?}",
+                id.as_title(),
+                e
+            ),
+        }
+    }
+
+    eprintln!(
+        "Reanalyzed {} benchmarks into the '{}' timeline.",
+        reanalyzed, options.timeline
+    );
+    Ok(())
+}
+
+/// Reanalyze every stored measurement of one benchmark, in the order they were taken, and
+/// replace whatever the destination directory held for that benchmark.
+fn reanalyze_benchmark(
+    source_dir: &Path,
+    record: &BenchmarkRecord,
+    dest_dir: &Path,
+    config: &BenchmarkConfig,
+    outliers: OutlierPolicy,
+) -> Result<()> {
+    let mut history = load_history_index(source_dir)?;
+    history.sort_by_key(|entry| entry.datetime);
+
+    fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Failed to create directory {:?}", dest_dir))?;
+    for entry in fs::read_dir(dest_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if entry.file_type()?.is_file()
+            && (is_measurement_file(&name) || name == "benchmark.cbor" || name == HISTORY_INDEX)
+        {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to delete {:?}", entry.path()))?;
+        }
+    }
+
+    let mut previous: Option<SavedStatistics> = None;
+    let mut index = vec![];
+    for entry in history {
+        let path = source_dir.join(&entry.measurement);
+        let stats = match load_measurement(&path) {
+            Ok(stats) => stats,
+            Err(e) if SchemaError::is_too_new(&e) => return Err(e),
+            Err(e) => {
+                warn!("Skipping unreadable measurement {:?}: {:?}", path, e);
+                continue;
+            }
+        };
+        let stats = match drop_outliers(stats, outliers) {
+            Some(stats) => stats,
+            None => {
+                warn!(
+                    "Skipping measurement {:?}; too few samples are left after dropping outliers",
+                    path
+                );
+                continue;
+            }
+        };
+
+        let stats = reanalyze_measurement(stats, previous.as_ref(), config);
+        let dest_path = dest_dir.join(&entry.measurement);
+        save_atomically(&dest_path, |writer| schema::to_writer(writer, &stats))
+            .with_context(|| format!("Failed to save measurements to file {:?}", dest_path))?;
+        index.push(HistoryRecord::new(entry.measurement, &stats));
+        previous = Some(stats);
+    }
+
+    // Normally the latest measurement stays the latest, unless it was skipped above.
+    let latest_record = if index
+        .iter()
+        .any(|entry| entry.measurement == record.latest_record)
+    {
+        record.latest_record.clone()
+    } else {
+        match index.last() {
+            Some(entry) => entry.measurement.clone(),
+            None => {
+                return Err(anyhow!(
+                    "None of the stored measurements could be reanalyzed"
+                ))
+            }
+        }
+    };
+    save_benchmark_record(
+        &dest_dir.join("benchmark.cbor"),
+        &BenchmarkRecord {
+            id: record.id.clone(),
+            latest_record,
+        },
+    )?;
+    write_history_index(dest_dir, &index)
+}
+
+/// Recompute the estimates of the given measurement, and its changes relative to the previous
+/// measurement (if any).
+fn reanalyze_measurement(
+    stats: SavedStatistics,
+    previous: Option<&SavedStatistics>,
+    config: &BenchmarkConfig,
+) -> SavedStatistics {
+    // Only linear sampling produces a slope estimate.
+    let sampling_method = if stats.estimates.slope.is_some() {
+        SamplingMethod::Linear
+    } else {
+        SamplingMethod::Flat
+    };
+
+    let measured_data = analysis(
+        config,
+        stats.throughput.clone(),
+        MeasuredValues {
+            iteration_count: &stats.iterations,
+            sample_values: &stats.values,
+            avg_values: &stats.avg_values,
+        },
+        previous.map(|previous| {
+            let measured_values = MeasuredValues {
+                iteration_count: &previous.iterations,
+                sample_values: &previous.values,
+                avg_values: &previous.avg_values,
+            };
+            (measured_values, &previous.estimates)
+        }),
+        sampling_method,
+    );
+
+    let estimates = measured_data.absolute_estimates.clone();
+    let changes = (measured_data.comparison.as_ref()).map(|c| c.relative_estimates.clone());
+    let change_direction = (measured_data.comparison.as_ref()).map(get_change_direction);
+    SavedStatistics {
+        estimates,
+        changes,
+        change_direction,
+        ..stats
+    }
+}
+
+/// Remove the samples that the outlier policy says to drop. Returns None if too few samples
+/// would be left to analyze.
+fn drop_outliers(mut stats: SavedStatistics, policy: OutlierPolicy) -> Option<SavedStatistics> {
+    if stats.avg_values.len() < 2 {
+        return None;
+    }
+    let keep: Vec<bool> = tukey::classify(Sample::new(&stats.avg_values))
+        .iter()
+        .map(|(_, label)| match policy {
+            OutlierPolicy::Keep => true,
+            OutlierPolicy::DropSevere => !label.is_severe(),
+            OutlierPolicy::DropAll => !label.is_outlier(),
+        })
+        .collect();
+
+    let filter = |values: &[f64]| -> Vec<f64> {
+        values
+            .iter()
+            .zip(&keep)
+            .filter(|(_, keep)| **keep)
+            .map(|(value, _)| *value)
+            .collect()
+    };
+    stats.iterations = filter(&stats.iterations);
+    stats.values = filter(&stats.values);
+    stats.avg_values = filter(&stats.avg_values);
+
+    if stats.avg_values.len() < 2 {
+        None
+    } else {
+        Some(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::ComparisonTest;
+    use crate::estimate::Estimates;
+    use crate::model::test::{saved_statistics, store_benchmark};
+    use crate::model::{load_benchmark_record, read_history_index};
+    use approx::assert_relative_eq;
+    use std::path::PathBuf;
+
+    /// Twelve samples around `10 + offset`, the last two of which are a mild and a severe outlier.
+    fn noisy_statistics(timestamp: i64, offset: f64) -> SavedStatistics {
+        let mut avg_values: Vec<f64> = (0..10).map(|i| 10.0 + offset + i as f64 / 10.0).collect();
+        avg_values.extend(&[12.0 + offset, 30.0 + offset]);
+        let iterations: Vec<f64> = (1..=12).map(f64::from).collect();
+        let values = (iterations.iter().zip(&avg_values))
+            .map(|(iterations, avg_value)| iterations * avg_value)
+            .collect();
+        // The stored estimates are deliberately wrong, so that recomputing them shows.
+        let base = saved_statistics(timestamp, 0.0);
+        SavedStatistics {
+            iterations,
+            values,
+            avg_values,
+            estimates: Estimates {
+                slope: None,
+                ..base.estimates
+            },
+            ..base
+        }
+    }
+
+    #[test]
+    fn test_drop_outliers() {
+        let kept = |policy| {
+            let stats = drop_outliers(noisy_statistics(1_000, 0.0), policy).unwrap();
+            // The samples stay paired with their iteration counts.
+            for ((iterations, value), avg_value) in stats
+                .iterations
+                .iter()
+                .zip(&stats.values)
+                .zip(&stats.avg_values)
+            {
+                assert_relative_eq!(iterations * avg_value, *value);
+            }
+            stats.avg_values
+        };
+
+        assert_eq!(kept(OutlierPolicy::Keep).len(), 12);
+        let without_severe = kept(OutlierPolicy::DropSevere);
+        assert_eq!(without_severe.len(), 11);
+        assert!(without_severe.contains(&12.0));
+        assert!(!without_severe.contains(&30.0));
+        let without_any = kept(OutlierPolicy::DropAll);
+        assert_eq!(without_any.len(), 10);
+        assert!(!without_any.contains(&12.0));
+
+        let mut too_few = saved_statistics(1_000, 1.0);
+        too_few.iterations.truncate(1);
+        too_few.values.truncate(1);
+        too_few.avg_values.truncate(1);
+        assert!(drop_outliers(too_few, OutlierPolicy::Keep).is_none());
+    }
+
+    #[test]
+    fn test_reanalyze_benchmark() {
+        let home = tempfile::tempdir().unwrap();
+        let id = BenchmarkId::new("fib".to_owned(), None, None, None);
+        let source_dir = store_benchmark(
+            &path!(home.path(), "data", "main"),
+            &id,
+            &[noisy_statistics(1_000, 0.0), noisy_statistics(2_000, 5.0)],
+        );
+        let record = load_benchmark_record(&source_dir.join("benchmark.cbor")).unwrap();
+        // Whatever an earlier reanalysis left behind is replaced.
+        let dest_dir = store_benchmark(
+            &path!(home.path(), "data", "reanalyzed"),
+            &id,
+            &[saved_statistics(500, 1.0)],
+        );
+
+        let config = BenchmarkConfig {
+            confidence_level: 0.9,
+            _measurement_time: Duration::default(),
+            noise_threshold: 0.01,
+            nresamples: 1_000,
+            _sample_size: 0,
+            significance_level: 0.05,
+            _warm_up_time: Duration::default(),
+            test: ComparisonTest::TTest,
+        };
+        reanalyze_benchmark(
+            &source_dir,
+            &record,
+            &dest_dir,
+            &config,
+            OutlierPolicy::DropAll,
+        )
+        .unwrap();
+
+        let index = read_history_index(&dest_dir).unwrap();
+        let measurements: Vec<&Path> = (index.iter())
+            .map(|entry| entry.measurement.as_path())
+            .collect();
+        assert_eq!(
+            measurements,
+            vec![
+                Path::new("measurement_1000.cbor"),
+                Path::new("measurement_2000.cbor")
+            ]
+        );
+        assert!(!dest_dir.join("measurement_500.cbor").exists());
+        let dest_record = load_benchmark_record(&dest_dir.join("benchmark.cbor")).unwrap();
+        assert_eq!(
+            dest_record.latest_record,
+            PathBuf::from("measurement_2000.cbor")
+        );
+
+        // The estimates are recomputed from the samples that are left, with the new settings.
+        let first = load_measurement(&dest_dir.join("measurement_1000.cbor")).unwrap();
+        assert_eq!(first.avg_values.len(), 10);
+        assert_relative_eq!(first.estimates.mean.point_estimate, 10.45, epsilon = 1e-9);
+        assert_relative_eq!(first.estimates.median.point_estimate, 10.45, epsilon = 1e-9);
+        assert_eq!(
+            first.estimates.mean.confidence_interval.confidence_level,
+            0.9
+        );
+        assert!(first.estimates.slope.is_none());
+        assert!(first.changes.is_none());
+
+        // Later measurements are compared against the reanalyzed previous one.
+        let second = load_measurement(&dest_dir.join("measurement_2000.cbor")).unwrap();
+        assert_relative_eq!(second.estimates.mean.point_estimate, 15.45, epsilon = 1e-9);
+        let changes = second.changes.unwrap();
+        assert_relative_eq!(
+            changes.mean.point_estimate,
+            15.45 / 10.45 - 1.0,
+            epsilon = 1e-9
+        );
+        assert!(second.change_direction.is_some());
+        assert_eq!(
+            index[1].changes.as_ref().unwrap().mean.point_estimate,
+            changes.mean.point_estimate
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Module implementing benchmark renames. Renaming a benchmark changes the directory its data is
+//! stored in, so without this the history of the old name would be orphaned and the first run
+//! under the new name would have nothing to compare against.
+//!
+//! Renames can be applied with the `rename` subcommand, or listed in the `[renames]` and
+//! `[package_renames.<package>]` tables of criterion.toml, in which case they are applied at the
+//! start of every run.
+
+use crate::config::{FullConfig, RenameOptions};
+use crate::gc::remove_benchmark;
+use crate::lock::{DirectoryLock, LockKind};
+use crate::model::{
+    is_measurement_file, rebuild_history_index, save_benchmark_record, stored_benchmarks,
+    BenchmarkRecord, SavedBenchmarkId,
+};
+use crate::report::BenchmarkId;
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Renames of stored benchmarks, mapping old IDs to new ones, by namespace. Benchmarks which were
+/// stored with `--workspace` are namespaced by their package; the others have no namespace.
+pub type Renames = BTreeMap<Option<String>, BTreeMap<String, String>>;
+
+/// Entry point for the `rename` subcommand.
+pub fn rename(configuration: &FullConfig, options: &RenameOptions) -> Result<()> {
+    let self_config = &configuration.self_config;
+    let _lock = DirectoryLock::acquire(
+        &self_config.criterion_home,
+        LockKind::Exclusive,
+        self_config.wait_for_lock,
+    )?;
+
+    let mut renames = Renames::new();
+    renames
+        .entry(options.package.clone())
+        .or_default()
+        .insert(options.old.clone(), options.new.clone());
+    let renamed = rename_benchmarks(&self_config.criterion_home, &renames)?;
+    if renamed == 0 {
+        return Err(anyhow!(
+            "No stored benchmark{} has the ID '{}' or an ID starting with '{}/'",
+            match &options.package {
+                Some(package) => format!(" of package {}", package),
+                None => String::new(),
+            },
+            options.old,
+            options.old
+        ));
+    }
+    Ok(())
+}
+
+/// Apply the renames listed in criterion.toml. Renames whose old benchmarks no longer exist
+/// (usually because they have already been applied) are ignored.
+pub fn apply_configured_renames(criterion_home: &Path, renames: &Renames) -> Result<()> {
+    if renames.values().any(|renames| !renames.is_empty()) {
+        rename_benchmarks(criterion_home, renames)?;
+    }
+    Ok(())
+}
+
+/// Check that the given renames (of one namespace) can be resolved, ie. that following them from
+/// any of the old IDs ends at an ID which isn't renamed again.
+pub fn check_renames(renames: &BTreeMap<String, String>) -> Result<()> {
+    for old in renames.keys() {
+        resolve_renames(renames, old)?;
+    }
+    Ok(())
+}
+
+/// Returns the ID that the benchmark with the given full ID is renamed to, following chains of
+/// renames (a -> b, b -> c), or None if it isn't renamed. Fails if the renames form a cycle.
+fn resolve_renames(renames: &BTreeMap<String, String>, full_id: &str) -> Result<Option<String>> {
+    let mut applied = vec![];
+    let mut current = full_id.to_owned();
+    while let Some((old, new)) = rename_once(renames, &current) {
+        if applied.contains(&old) {
+            return Err(anyhow!(
+                "The renames of '{}' form a cycle: {} -> {}",
+                full_id,
+                applied.join(" -> "),
+                old
+            ));
+        }
+        applied.push(old);
+        current = new;
+    }
+    Ok(if applied.is_empty() {
+        None
+    } else {
+        Some(current)
+    })
+}
+
+/// Apply the rename which matches the given full ID, if any. A rename matches if its old ID is
+/// the full ID or a prefix of it (such as a group name); the longest match wins. Returns the old
+/// ID of the rename and the new full ID.
+fn rename_once<'a>(
+    renames: &'a BTreeMap<String, String>,
+    full_id: &str,
+) -> Option<(&'a str, String)> {
+    (renames.iter())
+        .filter_map(|(old, new)| {
+            if full_id == old {
+                Some((old.as_str(), new.clone()))
+            } else {
+                let rest = full_id.strip_prefix(old.as_str())?.strip_prefix('/')?;
+                Some((old.as_str(), format!("{}/{}", new, rest)))
+            }
+        })
+        .max_by_key(|(old, _)| old.len())
+}
+
+/// Move the stored data of every benchmark which is renamed by the given renames to its new ID.
+/// If an old ID is a prefix of several IDs (such as a group name), all of those benchmarks are
+/// renamed. Returns the number of benchmarks which were renamed.
+fn rename_benchmarks(criterion_home: &Path, renames: &Renames) -> Result<usize> {
+    let data_directory = path!(criterion_home, "data", "main");
+    let reports_directory = criterion_home.join("reports");
+
+    let mut renamed = 0;
+    for (dir, record) in stored_benchmarks(&data_directory)? {
+        let old_id = BenchmarkId::from(record.id.clone());
+        let new_id = match renames.get(&old_id.namespace().map(ToOwned::to_owned)) {
+            Some(renames) => resolve_renames(renames, old_id.as_full_id())?,
+            None => None,
+        };
+        let new_id = match new_id {
+            Some(new_id) => record.id.renamed(&new_id),
+            None => continue,
+        };
+
+        eprintln!(
+            "Renaming benchmark {} to {}",
+            old_id.as_title(),
+            BenchmarkId::from(new_id.clone()).as_title()
+        );
+        move_benchmark(&data_directory, &reports_directory, &dir, &record, new_id)
+            .with_context(|| format!("Failed to rename benchmark {}", old_id.as_title()))?;
+        renamed += 1;
+    }
+    Ok(renamed)
+}
+
+/// Move the measurements stored in `old_dir` to the directory of the new ID. If the new ID
+/// already has stored data, the measurements are combined and its latest measurement is kept.
+fn move_benchmark(
+    data_directory: &Path,
+    reports_directory: &Path,
+    old_dir: &Path,
+    old_record: &BenchmarkRecord,
+    new_id: SavedBenchmarkId,
+) -> Result<()> {
+    let new_dir = data_directory.join(BenchmarkId::from(new_id.clone()).as_directory_name());
+    let new_record = BenchmarkRecord {
+        id: new_id,
+        latest_record: old_record.latest_record.clone(),
+    };
+    if new_dir == old_dir {
+        // Only characters which aren't allowed in file names changed, so just update the ID.
+        return save_benchmark_record(&new_dir.join("benchmark.cbor"), &new_record);
+    }
+
+    fs::create_dir_all(&new_dir)
+        .with_context(|| format!("Failed to create directory {:?}", new_dir))?;
+    for entry in fs::read_dir(old_dir)? {
+        let entry = entry?;
+        if !is_measurement_file(&entry.file_name()) {
+            continue;
+        }
+        let destination = new_dir.join(entry.file_name());
+        if destination.exists() {
+            warn!(
+                "{:?} already exists; keeping it instead of {:?}",
+                destination,
+                entry.path()
+            );
+        } else {
+            fs::rename(entry.path(), &destination).with_context(|| {
+                format!("Failed to move {:?} to {:?}", entry.path(), destination)
+            })?;
+        }
+    }
+
+    let new_record_path = new_dir.join("benchmark.cbor");
+    if !new_record_path.is_file() {
+        save_benchmark_record(&new_record_path, &new_record)?;
+    }
+    rebuild_history_index(&new_dir)?;
+
+    remove_benchmark(
+        data_directory,
+        reports_directory,
+        old_dir.strip_prefix(data_directory)?,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::test::{saved_statistics, store_benchmark};
+    use crate::model::{load_benchmark_record, read_history_index};
+    use std::path::PathBuf;
+
+    fn renames(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        (pairs.iter())
+            .map(|(old, new)| ((*old).to_owned(), (*new).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_renames() {
+        let renames = renames(&[("a", "b"), ("b", "c"), ("g", "h"), ("g/f", "x/y")]);
+        assert_eq!(
+            resolve_renames(&renames, "a").unwrap().as_deref(),
+            Some("c")
+        );
+        assert_eq!(
+            resolve_renames(&renames, "a/1").unwrap().as_deref(),
+            Some("c/1")
+        );
+        assert_eq!(resolve_renames(&renames, "c").unwrap(), None);
+        assert_eq!(resolve_renames(&renames, "ab").unwrap(), None);
+        // The longest matching prefix wins.
+        assert_eq!(
+            resolve_renames(&renames, "g/f/1").unwrap().as_deref(),
+            Some("x/y/1")
+        );
+        assert_eq!(
+            resolve_renames(&renames, "g/e").unwrap().as_deref(),
+            Some("h/e")
+        );
+        assert!(check_renames(&renames).is_ok());
+    }
+
+    #[test]
+    fn test_check_renames_rejects_cycles() {
+        assert!(check_renames(&renames(&[("a", "b"), ("b", "a")])).is_err());
+        assert!(check_renames(&renames(&[("a", "a")])).is_err());
+        assert!(check_renames(&renames(&[("g", "h"), ("h/f", "g/f")])).is_err());
+        // Renaming a group into a subgroup of itself would never end.
+        assert!(check_renames(&renames(&[("g", "g/sub")])).is_err());
+    }
+
+    #[test]
+    fn test_saved_benchmark_id_renamed() {
+        let parts = |id: SavedBenchmarkId| {
+            let id = BenchmarkId::from(id);
+            (id.group_id, id.function_id, id.value_str)
+        };
+        let with_function = SavedBenchmarkId::from(BenchmarkId::new(
+            "group".to_owned(),
+            Some("function".to_owned()),
+            None,
+            None,
+        ));
+        assert_eq!(
+            parts(with_function.renamed("new_group/a/b")),
+            ("new_group".to_owned(), Some("a/b".to_owned()), None)
+        );
+        assert_eq!(
+            parts(with_function.renamed("new_group")),
+            ("new_group".to_owned(), None, None)
+        );
+
+        let with_value = SavedBenchmarkId::from(BenchmarkId::new(
+            "group".to_owned(),
+            None,
+            Some("10".to_owned()),
+            None,
+        ));
+        assert_eq!(
+            parts(with_value.renamed("new_group/20")),
+            ("new_group".to_owned(), None, Some("20".to_owned()))
+        );
+
+        let with_both = SavedBenchmarkId::from(BenchmarkId::new(
+            "group".to_owned(),
+            Some("function".to_owned()),
+            Some("10".to_owned()),
+            None,
+        ));
+        assert_eq!(
+            parts(with_both.renamed("g/f/v/w")),
+            ("g".to_owned(), Some("f".to_owned()), Some("v/w".to_owned()))
+        );
+    }
+
+    fn id(group: &str, function: &str, namespace: Option<&str>) -> BenchmarkId {
+        BenchmarkId::new(group.to_owned(), Some(function.to_owned()), None, None)
+            .in_namespace(namespace)
+    }
+
+    #[test]
+    fn test_rename_benchmarks() {
+        let home = tempfile::tempdir().unwrap();
+        let data_directory = path!(home.path(), "data", "main");
+        store_benchmark(
+            &data_directory,
+            &id("old", "fib", None),
+            &[saved_statistics(1_000, 1.0)],
+        );
+        store_benchmark(
+            &data_directory,
+            &id("old", "fib", Some("other")),
+            &[saved_statistics(1_000, 1.0)],
+        );
+
+        let mut all = Renames::new();
+        all.insert(None, renames(&[("old", "new")]));
+        assert_eq!(rename_benchmarks(home.path(), &all).unwrap(), 1);
+
+        let new_dir = path!(&data_directory, "new", "fib");
+        let record = load_benchmark_record(&new_dir.join("benchmark.cbor")).unwrap();
+        assert_eq!(BenchmarkId::from(record.id).as_full_id(), "new/fib");
+        assert_eq!(read_history_index(&new_dir).unwrap().len(), 1);
+        assert!(!data_directory.join("old").exists());
+        // The benchmark with the same ID in another package's namespace is left alone.
+        assert!(path!(&data_directory, "other", "old", "fib", "benchmark.cbor").is_file());
+
+        // Nothing is left to rename.
+        assert_eq!(rename_benchmarks(home.path(), &all).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rename_merges_histories() {
+        let home = tempfile::tempdir().unwrap();
+        let data_directory = path!(home.path(), "data", "main");
+        store_benchmark(
+            &data_directory,
+            &id("g", "old", None),
+            &[saved_statistics(1_000, 1.0), saved_statistics(2_000, 2.0)],
+        );
+        let new_dir = store_benchmark(
+            &data_directory,
+            &id("g", "new", None),
+            &[saved_statistics(3_000, 3.0)],
+        );
+
+        let mut all = Renames::new();
+        all.insert(None, renames(&[("g/old", "g/new")]));
+        assert_eq!(rename_benchmarks(home.path(), &all).unwrap(), 1);
+
+        let history = read_history_index(&new_dir).unwrap();
+        let estimates: Vec<f64> = (history.iter())
+            .map(|record| record.estimates.mean.point_estimate)
+            .collect();
+        assert_eq!(estimates, vec![1.0, 2.0, 3.0]);
+        // The new benchmark's latest measurement is still its baseline.
+        let record = load_benchmark_record(&new_dir.join("benchmark.cbor")).unwrap();
+        assert_eq!(record.latest_record, PathBuf::from("measurement_3000.cbor"));
+        assert!(!path!(&data_directory, "g", "old").exists());
+    }
+}
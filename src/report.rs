@@ -1,13 +1,18 @@
+use crate::bench_target::BenchTarget;
 use crate::connection::{PlotConfiguration, Throughput};
 use crate::estimate::{ChangeDistributions, ChangeEstimates, Distributions, Estimate, Estimates};
 use crate::format;
-use crate::model::{BenchmarkGroup, Model, SavedStatistics};
+use crate::model::{BenchmarkGroup, HistoryRecord, Model};
+use crate::rusage::ResourceUsage;
+use crate::stability;
 use crate::stats::bivariate::regression::Slope;
 use crate::stats::bivariate::Data;
 use crate::stats::univariate::outliers::tukey::LabeledSample;
 use crate::stats::univariate::Sample;
 use crate::stats::Distribution;
+use crate::timing;
 use crate::value_formatter::ValueFormatter;
+use crate::watchdog::Timeout;
 use std::cell::Cell;
 use std::cmp;
 use std::collections::HashSet;
@@ -31,6 +36,9 @@ pub struct ComparisonData {
     pub base_sample_times: Vec<f64>,
     pub base_avg_times: Vec<f64>,
     pub base_estimates: Estimates,
+    // Descriptions of how the machine or toolchain differ from the ones used for the base
+    // measurements, if they do.
+    pub environment_changes: Vec<String>,
 }
 
 pub struct MeasurementData<'a> {
@@ -64,6 +72,9 @@ pub struct BenchmarkId {
     pub function_id: Option<String>,
     pub value_str: Option<String>,
     pub throughput: Option<Throughput>,
+    // The package that this benchmark's data and reports are nested under, if benchmarks are
+    // namespaced by package (ie. when benchmarking a whole workspace).
+    namespace: Option<String>,
     full_id: String,
     directory_name: PathBuf,
     title: String,
@@ -138,16 +149,35 @@ impl BenchmarkId {
             function_id,
             value_str,
             throughput,
+            namespace: None,
             full_id,
             directory_name,
             title,
         }
     }
 
+    /// Returns this ID with its data and reports nested under the directory of the given package.
+    pub fn in_namespace(mut self, namespace: Option<&str>) -> BenchmarkId {
+        if let Some(namespace) = namespace {
+            self.directory_name =
+                Path::new(&make_filename_safe(namespace)).join(&self.directory_name);
+        }
+        self.namespace = namespace.map(ToOwned::to_owned);
+        self
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
     pub fn as_title(&self) -> &str {
         &self.title
     }
 
+    pub fn as_full_id(&self) -> &str {
+        &self.full_id
+    }
+
     pub fn as_directory_name(&self) -> &Path {
         &self.directory_name
     }
@@ -281,13 +311,17 @@ pub trait Report {
         _formatter: &ValueFormatter,
     ) {
     }
+    fn benchmark_timed_out(&self, _id: &BenchmarkId, _context: &ReportContext, _timeout: Timeout) {}
+    fn target_complete(&self, _target: &BenchTarget, _usage: Option<&ResourceUsage>) {}
+    fn jitter_measured(&self, _before: f64, _after: Option<f64>) {}
     fn final_summary(&self, _context: &ReportContext, _model: &Model) {}
+    fn interrupted(&self, _context: &ReportContext) {}
     fn group_separator(&self) {}
     fn history(
         &self,
         _context: &ReportContext,
         _id: &BenchmarkId,
-        _history: &[SavedStatistics],
+        _history: &[HistoryRecord],
         _formatter: &ValueFormatter,
     ) {
     }
@@ -357,12 +391,36 @@ impl Report for Reports<'_> {
         }
     }
 
+    fn benchmark_timed_out(&self, id: &BenchmarkId, context: &ReportContext, timeout: Timeout) {
+        for report in &self.reports {
+            report.benchmark_timed_out(id, context, timeout);
+        }
+    }
+
+    fn target_complete(&self, target: &BenchTarget, usage: Option<&ResourceUsage>) {
+        for report in &self.reports {
+            report.target_complete(target, usage);
+        }
+    }
+
+    fn jitter_measured(&self, before: f64, after: Option<f64>) {
+        for report in &self.reports {
+            report.jitter_measured(before, after);
+        }
+    }
+
     fn final_summary(&self, context: &ReportContext, model: &Model) {
         for report in &self.reports {
             report.final_summary(context, model);
         }
     }
 
+    fn interrupted(&self, context: &ReportContext) {
+        for report in &self.reports {
+            report.interrupted(context);
+        }
+    }
+
     fn group_separator(&self) {
         for report in &self.reports {
             report.group_separator();
@@ -373,7 +431,7 @@ impl Report for Reports<'_> {
         &self,
         context: &ReportContext,
         id: &BenchmarkId,
-        history: &[SavedStatistics],
+        history: &[HistoryRecord],
         formatter: &ValueFormatter,
     ) {
         for report in &self.reports {
@@ -685,6 +743,14 @@ impl Report for CliReport {
                 }
 
                 eprintln!("{}{}", " ".repeat(24), explanation_str);
+
+                for change in &comp.environment_changes {
+                    eprintln!(
+                        "{}{}",
+                        " ".repeat(24),
+                        self.yellow(format!("Warning: {}", change))
+                    );
+                }
             }
         }
 
@@ -727,6 +793,91 @@ impl Report for CliReport {
         }
     }
 
+    fn benchmark_timed_out(&self, id: &BenchmarkId, _: &ReportContext, timeout: Timeout) {
+        self.text_overwrite();
+        let message = format!(
+            "timed out after {}",
+            format::time(timeout.duration().as_nanos() as f64)
+        );
+        let mut id = id.as_title().to_owned();
+        if id.len() > 23 {
+            eprintln!("{}", self.red(id.clone()));
+            id.clear();
+        }
+        let id_len = id.len();
+        eprintln!(
+            "{}{}{}",
+            self.red(id),
+            " ".repeat(24 - id_len),
+            self.bold(message)
+        );
+    }
+
+    fn target_complete(&self, target: &BenchTarget, usage: Option<&ResourceUsage>) {
+        if let (true, Some(usage)) = (self.verbose, usage) {
+            eprintln!("Benchmark target {} used {}", target.name, usage.summary());
+        }
+    }
+
+    fn jitter_measured(&self, before: f64, after: Option<f64>) {
+        // The jitter tells the user how far to trust the differences, so it's only shown with them.
+        if !self.show_differences {
+            return;
+        }
+        match after {
+            None => eprintln!(
+                "Timing jitter before the run: {}",
+                stability::format_jitter(before)
+            ),
+            Some(after) => eprintln!(
+                "Timing jitter after the run: {} (before: {})",
+                stability::format_jitter(after),
+                stability::format_jitter(before)
+            ),
+        }
+    }
+
+    fn final_summary(&self, _context: &ReportContext, model: &Model) {
+        // The time spent is shown in the same output formats as the differences.
+        if !self.show_differences || model.benchmark_times().is_empty() {
+            return;
+        }
+        let seconds = |seconds: f64| format::time(seconds * 1e9);
+
+        eprintln!("Time spent on the slowest benchmarks:");
+        eprintln!("     Total    Warm-up  Measuring   Analysis  Reporting  Benchmark");
+        for benchmark in timing::slowest_benchmarks(&model.benchmark_times())
+            .into_iter()
+            .take(timing::TOP_N)
+        {
+            let phases = &benchmark.phases;
+            eprintln!(
+                "{:>10} {:>10} {:>10} {:>10} {:>10}  {}",
+                seconds(phases.total_seconds()),
+                seconds(phases.warm_up_seconds),
+                seconds(phases.measurement_seconds),
+                seconds(phases.analysis_seconds),
+                seconds(phases.reporting_seconds),
+                benchmark.id.as_title()
+            );
+        }
+        eprintln!();
+
+        eprintln!("Time spent on the slowest benchmark targets:");
+        for target in timing::slowest_targets(&model.target_times())
+            .into_iter()
+            .take(timing::TOP_N)
+        {
+            eprintln!(
+                "{:>10}  {} / {}",
+                seconds(target.seconds),
+                target.package,
+                target.target
+            );
+        }
+        eprintln!();
+    }
+
     fn group_separator(&self) {
         eprintln!();
     }
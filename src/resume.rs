@@ -0,0 +1,303 @@
+//! Module implementing `--resume`, which continues a run that was interrupted (eg. by Ctrl-C, a
+//! crash or a failing target) instead of starting it over.
+//!
+//! Every run keeps a record of the benchmarks and targets it has completed in
+//! `CRITERION_HOME/run.cbor`, which is updated as each benchmark completes. When resuming, each
+//! target is listed with `--list` and given a filter which matches only the benchmarks that the
+//! interrupted run didn't complete. Targets which completed, or which have nothing left to
+//! measure, are skipped.
+
+use crate::bench_target::BenchTarget;
+use crate::model::save_atomically;
+use crate::report::BenchmarkId;
+use crate::rusage::ResourceUsage;
+use crate::schema::{self, Migration, Versioned};
+use crate::timing::PhaseTimes;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const RUN_RECORD: &str = "run.cbor";
+
+/// Record of the progress of the latest run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started: DateTime<Utc>,
+    // Set once every target has been run, successfully or not.
+    pub finished: bool,
+    pub history_id: Option<String>,
+    pub history_description: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    // The timing jitter measured by the stability probe before and after the benchmarks.
+    #[serde(default)]
+    pub jitter_before: Option<f64>,
+    #[serde(default)]
+    pub jitter_after: Option<f64>,
+    // The resources used by and the time spent on each target (see `target_key`), including the
+    // earlier attempts if the run was resumed.
+    #[serde(default)]
+    resource_usage: BTreeMap<String, ResourceUsage>,
+    #[serde(default)]
+    target_seconds: BTreeMap<String, f64>,
+    // The time spent on each completed benchmark, by target (see `target_key`) and full ID.
+    #[serde(default)]
+    benchmark_times: BTreeMap<String, BTreeMap<String, PhaseTimes>>,
+    // The full IDs of the completed benchmarks, by target (see `target_key`).
+    completed_benchmarks: BTreeMap<String, BTreeSet<String>>,
+    completed_targets: BTreeSet<String>,
+}
+impl Versioned for RunRecord {
+    const NAME: &'static str = "run record";
+    const MIGRATIONS: &'static [Migration] = &[schema::unversioned];
+}
+
+/// What to do with a benchmark target when resuming a run.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResumePlan {
+    /// Everything in the target was completed already.
+    Skip,
+    /// Run the target with the user's arguments, as in a normal run.
+    RunAll,
+    /// Run the target with a filter (a regex) matching only the given remaining benchmarks,
+    /// instead of the user's filter.
+    RunOnly(String),
+}
+
+impl RunRecord {
+    pub fn new(
+        history_id: Option<String>,
+        history_description: Option<String>,
+        tags: BTreeMap<String, String>,
+    ) -> RunRecord {
+        RunRecord {
+            started: Utc::now(),
+            finished: false,
+            history_id,
+            history_description,
+            tags,
+            jitter_before: None,
+            jitter_after: None,
+            resource_usage: BTreeMap::new(),
+            target_seconds: BTreeMap::new(),
+            benchmark_times: BTreeMap::new(),
+            completed_benchmarks: BTreeMap::new(),
+            completed_targets: BTreeSet::new(),
+        }
+    }
+
+    /// Load the record of the latest run, which must not have finished.
+    pub fn load_unfinished(criterion_home: &Path) -> Result<RunRecord> {
+        let path = record_path(criterion_home);
+        if !path.is_file() {
+            return Err(anyhow!(
+                "There is no interrupted run to resume in {}",
+                criterion_home.display()
+            ));
+        }
+        let mut file =
+            File::open(&path).with_context(|| format!("Failed to open run record {:?}", path))?;
+        let record: RunRecord = schema::from_reader(&mut file)
+            .with_context(|| format!("Failed to read run record {:?}", path))?;
+        if record.finished {
+            return Err(anyhow!(
+                "The latest run (started {}) finished, so there is nothing to resume",
+                record.started.format("%Y-%m-%d %H:%M")
+            ));
+        }
+        Ok(record)
+    }
+
+    pub fn save(&self, criterion_home: &Path) -> Result<()> {
+        let path = record_path(criterion_home);
+        save_atomically(&path, |writer| schema::to_writer(writer, self))
+            .with_context(|| format!("Failed to save run record {:?}", path))
+    }
+
+    pub fn benchmark_complete(&mut self, target: &BenchTarget, id: &BenchmarkId) {
+        self.completed_benchmarks
+            .entry(target_key(target))
+            .or_default()
+            .insert(id.as_full_id().to_owned());
+    }
+
+    pub fn resources_used(&mut self, target: &BenchTarget, usage: &ResourceUsage) {
+        (self.resource_usage.entry(target_key(target)))
+            .and_modify(|total| total.add(usage))
+            .or_insert_with(|| usage.clone());
+    }
+
+    pub fn target_timed(&mut self, target: &BenchTarget, seconds: f64) {
+        *self.target_seconds.entry(target_key(target)).or_default() += seconds;
+    }
+
+    pub fn benchmark_timed(&mut self, target: &BenchTarget, id: &BenchmarkId, phases: PhaseTimes) {
+        self.benchmark_times
+            .entry(target_key(target))
+            .or_default()
+            .insert(id.as_full_id().to_owned(), phases);
+    }
+
+    /// The resources used by each target, as (package, target, usage).
+    pub fn resource_usage(&self) -> impl Iterator<Item = (&str, &str, &ResourceUsage)> {
+        (self.resource_usage.iter()).map(|(key, usage)| {
+            let (package, target) = split_target_key(key);
+            (package, target, usage)
+        })
+    }
+
+    /// The time spent on each target, as (package, target, seconds).
+    pub fn target_seconds(&self) -> impl Iterator<Item = (&str, &str, f64)> {
+        (self.target_seconds.iter()).map(|(key, seconds)| {
+            let (package, target) = split_target_key(key);
+            (package, target, *seconds)
+        })
+    }
+
+    /// The time spent on each completed benchmark, as (package, target, full ID, phases).
+    pub fn benchmark_times(&self) -> impl Iterator<Item = (&str, &str, &str, &PhaseTimes)> {
+        (self.benchmark_times.iter()).flat_map(|(key, benchmarks)| {
+            let (package, target) = split_target_key(key);
+            (benchmarks.iter()).map(move |(id, phases)| (package, target, id.as_str(), phases))
+        })
+    }
+
+    pub fn target_complete(&mut self, target: &BenchTarget) {
+        self.completed_targets.insert(target_key(target));
+    }
+
+    /// Decide how to run the given target so that only the benchmarks that weren't completed
+    /// (and match the user's filter, if any) are measured.
+    fn plan(
+        &self,
+        target: &BenchTarget,
+        library_paths: &[PathBuf],
+        user_filter: Option<&str>,
+        exact: bool,
+    ) -> Result<ResumePlan> {
+        let key = target_key(target);
+        if self.completed_targets.contains(&key) {
+            return Ok(ResumePlan::Skip);
+        }
+        let completed = match self.completed_benchmarks.get(&key) {
+            Some(completed) => completed,
+            None => return Ok(ResumePlan::RunAll),
+        };
+
+        let listed = target.list_benchmarks(library_paths)?;
+        if listed.is_empty() {
+            // Targets which can't list their benchmarks have to be run in full.
+            return Ok(ResumePlan::RunAll);
+        }
+        let user_regex = match user_filter {
+            Some(filter) if !exact => Some(Regex::new(filter).context("Invalid benchmark filter")?),
+            _ => None,
+        };
+        let remaining: Vec<String> = listed
+            .iter()
+            .filter(|id| !completed.contains(*id))
+            .filter(|id| match (user_filter, &user_regex) {
+                (Some(filter), _) if exact => *id == filter,
+                (_, Some(regex)) => regex.is_match(id),
+                _ => true,
+            })
+            .map(|id| regex::escape(id))
+            .collect();
+        Ok(remaining_plan(remaining))
+    }
+}
+
+/// Returns the arguments to pass to the given target: the user's benchmark filter followed by the
+/// additional arguments, or, when resuming, a filter matching only the remaining benchmarks.
+/// Returns None if there's nothing left to run in the target.
+pub fn target_args(
+    resuming: Option<&RunRecord>,
+    target: &BenchTarget,
+    library_paths: &[PathBuf],
+    bench_filter: Option<&OsStr>,
+    additional_args: &[OsString],
+) -> Result<Option<Vec<OsString>>> {
+    let plan = match resuming {
+        Some(run) => {
+            let user_filter = bench_filter
+                .map(|filter| {
+                    filter.to_str().ok_or_else(|| {
+                        anyhow!("The benchmark filter {:?} isn't valid UTF-8", filter)
+                    })
+                })
+                .transpose()?;
+            let exact = additional_args.iter().any(|arg| arg == "--exact");
+            run.plan(target, library_paths, user_filter, exact)?
+        }
+        None => ResumePlan::RunAll,
+    };
+
+    match plan {
+        ResumePlan::Skip => Ok(None),
+        ResumePlan::RunAll => Ok(Some(
+            bench_filter
+                .map(ToOwned::to_owned)
+                .into_iter()
+                .chain(additional_args.iter().cloned())
+                .collect(),
+        )),
+        // The remaining benchmarks are matched by a regex, so --exact mustn't be passed on.
+        ResumePlan::RunOnly(filter) => Ok(Some(
+            std::iter::once(OsString::from(filter))
+                .chain(
+                    additional_args
+                        .iter()
+                        .filter(|arg| *arg != "--exact")
+                        .cloned(),
+                )
+                .collect(),
+        )),
+    }
+}
+
+fn remaining_plan(remaining: Vec<String>) -> ResumePlan {
+    if remaining.is_empty() {
+        ResumePlan::Skip
+    } else {
+        ResumePlan::RunOnly(format!("^({})$", remaining.join("|")))
+    }
+}
+
+fn record_path(criterion_home: &Path) -> PathBuf {
+    criterion_home.join(RUN_RECORD)
+}
+
+fn target_key(target: &BenchTarget) -> String {
+    format!("{}/{}", target.package, target.name)
+}
+
+/// Split a key made by `target_key` back into the package and target names. Package names can't
+/// contain slashes, so the first one ends the package name.
+fn split_target_key(key: &str) -> (&str, &str) {
+    key.split_once('/').unwrap_or((key, ""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remaining_plan() {
+        assert_eq!(remaining_plan(vec![]), ResumePlan::Skip);
+        assert_eq!(
+            remaining_plan(vec![regex::escape("fib/10"), regex::escape("a+b")]),
+            ResumePlan::RunOnly("^(fib/10|a\\+b)$".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_split_target_key() {
+        assert_eq!(
+            split_target_key("my-package/my/bench"),
+            ("my-package", "my/bench")
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Module for finding the runner (wrapper command) to launch benchmark targets with, such as
+//! `taskset -c 3` or `qemu-aarch64`.
+//!
+//! A runner can be configured in `criterion.toml`, either for all targets (`runner`) or for
+//! individual targets (`[target_runners]`). Otherwise, when cross-compiling with `--target`, the
+//! runner configured in Cargo's `target.<triple>.runner` setting is used, just as `cargo bench`
+//! would.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The runners to launch benchmark targets with. Each runner is a program followed by its
+/// arguments; the path to the benchmark executable is appended to it.
+#[derive(Debug, Clone, Default)]
+pub struct Runners {
+    /// The runner for targets which don't have one of their own.
+    pub default: Option<Vec<String>>,
+    /// Runners for individual targets, by target name. An empty runner means the target is
+    /// launched directly.
+    pub targets: BTreeMap<String, Vec<String>>,
+}
+impl Runners {
+    /// Returns the runner for the target with the given name, if it has one.
+    pub fn for_target(&self, target_name: &str) -> Option<&[String]> {
+        let runner = match self.targets.get(target_name) {
+            Some(runner) => Some(runner),
+            None => self.default.as_ref(),
+        };
+        runner
+            .filter(|runner| !runner.is_empty())
+            .map(Vec::as_slice)
+    }
+}
+
+/// Check that the runner given in `criterion.toml` has a program to run.
+pub fn validate(runner: &[String], setting: &str) -> Result<()> {
+    match runner.first() {
+        Some(program) if program.is_empty() => Err(anyhow!(
+            "The {} in criterion.toml has an empty program name",
+            setting
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Find the runner that Cargo would use for benchmarks compiled for the given target triple.
+/// Like Cargo, this checks the `CARGO_TARGET_<TRIPLE>_RUNNER` environment variable and then the
+/// `.cargo/config.toml` files in the current directory and its parents, followed by the one in
+/// Cargo's home directory. `target.'cfg(..)'.runner` settings aren't supported.
+pub fn cargo_target_runner(triple: &str) -> Result<Option<Vec<String>>> {
+    let variable = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        triple.to_uppercase().replace(['-', '.'], "_")
+    );
+    if let Ok(runner) = std::env::var(&variable) {
+        return Ok(Some(split_runner(&runner)));
+    }
+
+    let cwd = std::env::current_dir().context("Unable to get the current directory")?;
+    let mut config_files: Vec<PathBuf> = cwd
+        .ancestors()
+        .flat_map(|dir| config_candidates(&dir.join(".cargo")))
+        .collect();
+    if let Some(cargo_home) = cargo_home() {
+        config_files.extend(config_candidates(&cargo_home));
+    }
+
+    for path in config_files.iter().filter(|path| path.is_file()) {
+        if let Some(runner) = read_target_runner(path, triple)? {
+            debug!("Using runner {:?} from {:?}", runner, path);
+            return Ok(Some(runner));
+        }
+    }
+    Ok(None)
+}
+
+fn config_candidates(dir: &Path) -> [PathBuf; 2] {
+    [dir.join("config.toml"), dir.join("config")]
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(home));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".cargo"))
+}
+
+/// Read `target.<triple>.runner` from the given Cargo config file.
+fn read_target_runner(path: &Path, triple: &str) -> Result<Option<Vec<String>>> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .with_context(|| format!("Failed to read Cargo config file {:?}", path))?;
+    let config: toml::Value = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse Cargo config file {:?}", path))?;
+
+    let value = match config
+        .get("target")
+        .and_then(|targets| targets.get(triple))
+        .and_then(|target| target.get("runner"))
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let mut runner = match value {
+        toml::Value::String(runner) => split_runner(runner),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(ToOwned::to_owned))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| {
+                anyhow!(
+                    "target.{}.runner in {:?} must be a string or an array of strings",
+                    triple,
+                    path
+                )
+            })?,
+        _ => {
+            return Err(anyhow!(
+                "target.{}.runner in {:?} must be a string or an array of strings",
+                triple,
+                path
+            ))
+        }
+    };
+
+    // Cargo treats program paths containing a slash as relative to the directory containing the
+    // `.cargo` directory.
+    if let Some(program) = runner.first_mut() {
+        if program.contains('/') && Path::new(program.as_str()).is_relative() {
+            let base = path.parent().and_then(Path::parent).unwrap_or(path);
+            *program = base.join(program.as_str()).to_string_lossy().into_owned();
+        }
+    }
+    Ok(Some(runner))
+}
+
+fn split_runner(runner: &str) -> Vec<String> {
+    runner.split_whitespace().map(ToOwned::to_owned).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_for_target() {
+        let runners = Runners {
+            default: Some(vec!["taskset".into(), "-c".into(), "3".into()]),
+            targets: vec![
+                ("alloc".to_owned(), vec!["nice".into()]),
+                ("direct".to_owned(), vec![]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            runners.for_target("fib"),
+            Some(&["taskset".to_owned(), "-c".to_owned(), "3".to_owned()][..])
+        );
+        assert_eq!(runners.for_target("alloc"), Some(&["nice".to_owned()][..]));
+        assert_eq!(runners.for_target("direct"), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Module which collects the resources used by each benchmark target (CPU time, memory, context
+//! switches and page faults), as reported by the operating system when the target exits.
+//!
+//! A rise in involuntary context switches often explains noisy measurements, and the maximum
+//! resident set size is a useful secondary signal of regressions.
+
+use crate::format;
+use std::io;
+use std::process::{Child, ExitStatus};
+
+/// The resources used by a benchmark target process.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub user_time_seconds: f64,
+    pub system_time_seconds: f64,
+    pub max_rss_bytes: u64,
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+    pub minor_page_faults: u64,
+    pub major_page_faults: u64,
+}
+impl ResourceUsage {
+    /// Add the resources used by another process running the same target, eg. when a run is
+    /// resumed.
+    pub fn add(&mut self, other: &ResourceUsage) {
+        self.user_time_seconds += other.user_time_seconds;
+        self.system_time_seconds += other.system_time_seconds;
+        self.max_rss_bytes = self.max_rss_bytes.max(other.max_rss_bytes);
+        self.voluntary_context_switches += other.voluntary_context_switches;
+        self.involuntary_context_switches += other.involuntary_context_switches;
+        self.minor_page_faults += other.minor_page_faults;
+        self.major_page_faults += other.major_page_faults;
+    }
+
+    /// Short human-readable summary, for the CLI output.
+    pub fn summary(&self) -> String {
+        format!(
+            "user {}, system {}, max RSS {}, context switches {} voluntary / {} involuntary, page faults {} minor / {} major",
+            format::time(self.user_time_seconds * 1e9).trim(),
+            format::time(self.system_time_seconds * 1e9).trim(),
+            format::bytes(self.max_rss_bytes).trim(),
+            self.voluntary_context_switches,
+            self.involuntary_context_switches,
+            self.minor_page_faults,
+            self.major_page_faults
+        )
+    }
+}
+
+/// Wait for the given child process to exit. Returns its exit status, and the resources it used
+/// if the platform reports them. `exited` is called after the process has exited but before it is
+/// reaped, while its process ID can't be reused yet.
+#[cfg(unix)]
+pub fn wait<F: FnOnce()>(
+    child: Child,
+    exited: F,
+) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = child.id() as libc::pid_t;
+    // Wait without reaping the process first.
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } == 0 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    exited();
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == pid {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+    // ru_maxrss is in bytes on macOS, and in kilobytes everywhere else.
+    let max_rss_bytes = if cfg!(target_os = "macos") {
+        rusage.ru_maxrss as u64
+    } else {
+        rusage.ru_maxrss as u64 * 1024
+    };
+    let usage = ResourceUsage {
+        user_time_seconds: seconds(rusage.ru_utime),
+        system_time_seconds: seconds(rusage.ru_stime),
+        max_rss_bytes,
+        voluntary_context_switches: rusage.ru_nvcsw as u64,
+        involuntary_context_switches: rusage.ru_nivcsw as u64,
+        minor_page_faults: rusage.ru_minflt as u64,
+        major_page_faults: rusage.ru_majflt as u64,
+    };
+    Ok((ExitStatus::from_raw(status), Some(usage)))
+}
+
+#[cfg(not(unix))]
+pub fn wait<F: FnOnce()>(
+    mut child: Child,
+    exited: F,
+) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    // On Windows, the process ID can't be reused while we hold the handle to the process.
+    let status = child.wait();
+    exited();
+    drop(child);
+    status.map(|status| (status, None))
+}
@@ -0,0 +1,183 @@
+//! Module that handles versioning of the records that cargo-criterion saves to disk.
+//!
+//! Every record is saved with a `schema_version` field. When a record is loaded, it is first
+//! decoded into a generic CBOR value and upgraded to the current version by applying each
+//! migration in turn, and only then converted into the in-memory struct. Records written by a
+//! newer version of cargo-criterion are rejected with a clear error instead of being misread.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// A migration upgrades a record (represented as a map of its fields) by one version.
+pub type Migration = fn(&mut BTreeMap<Value, Value>) -> Result<()>;
+
+/// Trait for the structs that are saved to disk.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Human-readable name of this kind of record, for use in error messages.
+    const NAME: &'static str;
+    /// The migrations for this kind of record. Element N upgrades a record from version N to
+    /// version N + 1, so the current version is the length of this list.
+    const MIGRATIONS: &'static [Migration];
+}
+
+/// Version 0 records were written before records were versioned. Every field added up to that
+/// point was optional, so they can be read as-is.
+pub fn unversioned(_: &mut BTreeMap<Value, Value>) -> Result<()> {
+    Ok(())
+}
+
+#[derive(Debug)]
+/// Enum representing the ways that a stored record might fail to load.
+pub enum SchemaError {
+    /// The record was written by a newer version of cargo-criterion.
+    TooNew {
+        name: &'static str,
+        version: u64,
+        supported: u64,
+    },
+    /// The record isn't structured the way any version of cargo-criterion would write it.
+    Malformed { name: &'static str },
+}
+impl SchemaError {
+    /// Returns true if the given error was caused by a record from a newer version of
+    /// cargo-criterion. Such records must not be skipped or overwritten.
+    pub fn is_too_new(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<SchemaError>(),
+            Some(SchemaError::TooNew { .. })
+        )
+    }
+}
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::TooNew {
+                name,
+                version,
+                supported,
+            } => write!(
+                f,
+                "This {} was saved by a newer version of cargo-criterion (schema version {}, but \
+                 this version only understands up to {}). Please upgrade cargo-criterion.",
+                name, version, supported
+            ),
+            SchemaError::Malformed { name } => write!(f, "Malformed {}", name),
+        }
+    }
+}
+impl std::error::Error for SchemaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+fn version_key() -> Value {
+    Value::Text("schema_version".to_owned())
+}
+
+/// Serialize the given record, tagged with the current schema version.
+pub fn to_writer<T: Versioned, W: Write>(writer: W, record: &T) -> Result<()> {
+    serde_cbor::to_writer(writer, &to_value(record)?)?;
+    Ok(())
+}
+
+/// Convert the given record into a CBOR value, tagged with the current schema version.
+pub fn to_value<T: Versioned>(record: &T) -> Result<Value> {
+    let mut value = serde_cbor::value::to_value(record)?;
+    if let Value::Map(map) = &mut value {
+        map.insert(version_key(), Value::Integer(T::MIGRATIONS.len() as i128));
+    }
+    Ok(value)
+}
+
+/// Deserialize a record from the given reader, upgrading it to the current schema version.
+pub fn from_reader<T: Versioned, R: Read>(reader: R) -> Result<T> {
+    from_value(serde_cbor::from_reader(reader)?)
+}
+
+/// Convert a decoded CBOR value into a record, upgrading it to the current schema version.
+pub fn from_value<T: Versioned>(value: Value) -> Result<T> {
+    let malformed = || SchemaError::Malformed { name: T::NAME };
+    let mut map = match value {
+        Value::Map(map) => map,
+        _ => return Err(malformed().into()),
+    };
+
+    let version = match map.remove(&version_key()) {
+        None => 0,
+        Some(Value::Integer(version)) if version >= 0 => version as u64,
+        Some(_) => return Err(malformed().into()),
+    };
+    let supported = T::MIGRATIONS.len() as u64;
+    if version > supported {
+        return Err(SchemaError::TooNew {
+            name: T::NAME,
+            version,
+            supported,
+        }
+        .into());
+    }
+
+    for (from, migration) in T::MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(&mut map).with_context(|| {
+            format!("Failed to upgrade {} from schema version {}", T::NAME, from)
+        })?;
+    }
+    serde_cbor::value::from_value(Value::Map(map)).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        name: String,
+    }
+
+    fn rename_title(map: &mut BTreeMap<Value, Value>) -> Result<()> {
+        let title = map
+            .remove(&Value::Text("title".to_owned()))
+            .ok_or_else(|| anyhow::anyhow!("Missing title"))?;
+        map.insert(Value::Text("name".to_owned()), title);
+        Ok(())
+    }
+
+    impl Versioned for Record {
+        const NAME: &'static str = "test record";
+        const MIGRATIONS: &'static [Migration] = &[unversioned, rename_title];
+    }
+
+    fn encode(fields: &[(&str, Value)]) -> Vec<u8> {
+        let map = fields
+            .iter()
+            .map(|(key, value)| (Value::Text((*key).to_owned()), value.clone()))
+            .collect();
+        serde_cbor::to_vec(&Value::Map(map)).unwrap()
+    }
+
+    #[test]
+    fn test_migrates_old_records() {
+        let old = encode(&[("title", Value::Text("fib".to_owned()))]);
+        let record: Record = from_reader(&old[..]).unwrap();
+        assert_eq!("fib", record.name);
+
+        let mut new = vec![];
+        to_writer(&mut new, &record).unwrap();
+        assert_eq!(record, from_reader(&new[..]).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_newer_records() {
+        let newer = encode(&[
+            ("name", Value::Text("fib".to_owned())),
+            ("schema_version", Value::Integer(3)),
+        ]);
+        let error = from_reader::<Record, _>(&newer[..]).unwrap_err();
+        assert!(SchemaError::is_too_new(&error));
+    }
+}
@@ -0,0 +1,76 @@
+//! Module implementing the stability probe, which estimates how much timing jitter the machine
+//! currently has by measuring a fixed CPU-bound workload many times.
+//!
+//! The probe runs before and after the benchmarks. A high jitter means that the machine is busy,
+//! throttled or otherwise unstable, so small changes in the results are likely to be noise rather
+//! than real regressions. The jitter measured before the run is saved with each measurement.
+
+use crate::affinity::CpuSet;
+use crate::stats::univariate::Sample;
+use anyhow::{anyhow, Result};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// The probe takes this many samples, after a few more to warm up.
+const SAMPLES: usize = 100;
+const WARM_UP_SAMPLES: usize = 10;
+// Each sample runs the workload for roughly this long.
+const SAMPLE_TIME: Duration = Duration::from_millis(1);
+
+/// Options for the stability probe.
+#[derive(Debug, Clone)]
+pub struct StabilityOptions {
+    /// Should the probe be run at all?
+    pub probe: bool,
+    /// Refuse to run the benchmarks if the jitter is higher than this (as a fraction).
+    pub max_jitter: Option<f64>,
+    /// Add the jitter to the noise threshold when comparing measurements.
+    pub scale_noise_threshold: bool,
+}
+
+/// Estimate the current timing jitter of the machine, as the robust standard deviation of the
+/// probe's samples relative to their median (eg. 0.01 is 1%). If `cpus` is given, the probe runs
+/// on those CPUs, like the benchmarks.
+pub fn probe(cpus: Option<&CpuSet>) -> Result<f64> {
+    let cpus = cpus.cloned();
+    std::thread::spawn(move || {
+        if let Some(cpus) = &cpus {
+            crate::affinity::pin_current_thread(cpus)?;
+        }
+        Ok(measure_jitter())
+    })
+    .join()
+    .map_err(|_| anyhow!("The stability probe panicked"))?
+}
+
+fn measure_jitter() -> f64 {
+    // Find the number of iterations that takes roughly SAMPLE_TIME.
+    let mut iterations = 1000u64;
+    while time_workload(iterations) < SAMPLE_TIME {
+        iterations *= 2;
+    }
+
+    let samples: Vec<f64> = (0..WARM_UP_SAMPLES + SAMPLES)
+        .map(|_| time_workload(iterations).as_secs_f64())
+        .skip(WARM_UP_SAMPLES)
+        .collect();
+    Sample::new(&samples).median_abs_dev_pct() / 100.0
+}
+
+fn time_workload(iterations: u64) -> Duration {
+    let start = Instant::now();
+    let mut state = black_box(0x2545_f491_4f6c_dd1du64);
+    for _ in 0..iterations {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+    }
+    black_box(state);
+    start.elapsed()
+}
+
+/// Format a jitter for display, eg. "1.25%".
+pub fn format_jitter(jitter: f64) -> String {
+    format!("{:.2}%", jitter * 100.0)
+}
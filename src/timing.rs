@@ -0,0 +1,187 @@
+//! Module which keeps track of the wall-clock time spent on each benchmark and benchmark target,
+//! so that users can see which benchmarks make the suite slow.
+//!
+//! The time spent on each benchmark is split into the phases of its lifecycle: warming up and
+//! measuring (in the benchmark target), and analyzing and reporting the measurements (in
+//! cargo-criterion, including saving the data and generating the plots).
+
+use crate::report::BenchmarkId;
+use std::time::Instant;
+
+/// How many of the slowest benchmarks and targets are listed in the CLI and HTML reports.
+pub const TOP_N: usize = 10;
+
+/// The time spent on each phase of one benchmark, in seconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PhaseTimes {
+    pub warm_up_seconds: f64,
+    pub measurement_seconds: f64,
+    pub analysis_seconds: f64,
+    pub reporting_seconds: f64,
+}
+impl PhaseTimes {
+    pub fn total_seconds(&self) -> f64 {
+        self.warm_up_seconds
+            + self.measurement_seconds
+            + self.analysis_seconds
+            + self.reporting_seconds
+    }
+}
+
+/// The time spent on a benchmark during this run.
+#[derive(Debug, Clone)]
+pub struct BenchmarkTime {
+    pub id: BenchmarkId,
+    pub package: String,
+    pub target: String,
+    pub phases: PhaseTimes,
+}
+
+/// The time spent running a benchmark target during this run, in seconds.
+#[derive(Debug, Clone)]
+pub struct TargetTime {
+    pub package: String,
+    pub target: String,
+    pub seconds: f64,
+}
+
+/// Stopwatch which measures the phases of a benchmark as its lifecycle messages arrive.
+#[derive(Debug)]
+pub struct PhaseTimer {
+    phase_start: Instant,
+    times: PhaseTimes,
+}
+impl PhaseTimer {
+    /// Start timing a benchmark. The first phase is the warm-up.
+    pub fn start() -> PhaseTimer {
+        PhaseTimer {
+            phase_start: Instant::now(),
+            times: PhaseTimes::default(),
+        }
+    }
+
+    pub fn measurement_started(&mut self) {
+        self.lap(Instant::now(), |times| &mut times.warm_up_seconds);
+    }
+
+    pub fn measurement_complete(&mut self) {
+        self.lap(Instant::now(), |times| &mut times.measurement_seconds);
+    }
+
+    pub fn analysis_complete(&mut self) {
+        self.lap(Instant::now(), |times| &mut times.analysis_seconds);
+    }
+
+    /// Stop timing after the measurements have been reported.
+    pub fn finish(mut self) -> PhaseTimes {
+        self.lap(Instant::now(), |times| &mut times.reporting_seconds);
+        self.times
+    }
+
+    /// Add the time since the end of the previous phase to the given phase, which ends `now`.
+    fn lap(&mut self, now: Instant, phase: fn(&mut PhaseTimes) -> &mut f64) {
+        *phase(&mut self.times) += now
+            .saturating_duration_since(self.phase_start)
+            .as_secs_f64();
+        self.phase_start = now;
+    }
+}
+
+/// Sort the given benchmarks from slowest to fastest.
+pub fn slowest_benchmarks(benchmarks: &[BenchmarkTime]) -> Vec<&BenchmarkTime> {
+    let mut benchmarks: Vec<_> = benchmarks.iter().collect();
+    benchmarks.sort_by(|a, b| {
+        (b.phases.total_seconds())
+            .partial_cmp(&a.phases.total_seconds())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    benchmarks
+}
+
+/// Sort the given targets from slowest to fastest.
+pub fn slowest_targets(targets: &[TargetTime]) -> Vec<&TargetTime> {
+    let mut targets: Vec<_> = targets.iter().collect();
+    targets.sort_by(|a, b| {
+        (b.seconds)
+            .partial_cmp(&a.seconds)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    targets
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn benchmark(name: &str, warm_up_seconds: f64, measurement_seconds: f64) -> BenchmarkTime {
+        BenchmarkTime {
+            id: BenchmarkId::new(name.to_owned(), None, None, None),
+            package: "package".to_owned(),
+            target: "target".to_owned(),
+            phases: PhaseTimes {
+                warm_up_seconds,
+                measurement_seconds,
+                ..PhaseTimes::default()
+            },
+        }
+    }
+
+    fn target(target: &str, seconds: f64) -> TargetTime {
+        TargetTime {
+            package: "package".to_owned(),
+            target: target.to_owned(),
+            seconds,
+        }
+    }
+
+    #[test]
+    fn test_slowest_benchmarks() {
+        // Ordered by the total of all of the phases, not by any single phase.
+        let benchmarks = vec![
+            benchmark("middle", 2.0, 1.0),
+            benchmark("fastest", 1.0, 1.0),
+            benchmark("slowest", 0.5, 3.0),
+        ];
+        let titles: Vec<&str> = slowest_benchmarks(&benchmarks)
+            .into_iter()
+            .map(|benchmark| benchmark.id.as_title())
+            .collect();
+        assert_eq!(titles, vec!["slowest", "middle", "fastest"]);
+    }
+
+    #[test]
+    fn test_slowest_targets() {
+        let targets = vec![target("b", 1.0), target("c", 3.0), target("a", 2.0)];
+        let names: Vec<&str> = slowest_targets(&targets)
+            .into_iter()
+            .map(|target| target.target.as_str())
+            .collect();
+        assert_eq!(names, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_phase_timer_laps() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut timer = PhaseTimer {
+            phase_start: start,
+            times: PhaseTimes::default(),
+        };
+        timer.lap(at(1_000), |times| &mut times.warm_up_seconds);
+        timer.lap(at(4_000), |times| &mut times.measurement_seconds);
+        // Each phase starts where the previous one ended, and repeated phases add up.
+        timer.lap(at(4_500), |times| &mut times.analysis_seconds);
+        timer.lap(at(4_750), |times| &mut times.analysis_seconds);
+        timer.lap(at(5_000), |times| &mut times.reporting_seconds);
+        // A clock which goes backwards doesn't subtract anything.
+        timer.lap(at(4_000), |times| &mut times.reporting_seconds);
+
+        let times = timer.times;
+        assert_eq!(times.warm_up_seconds, 1.0);
+        assert_eq!(times.measurement_seconds, 3.0);
+        assert_eq!(times.analysis_seconds, 0.75);
+        assert_eq!(times.reporting_seconds, 0.25);
+        assert_eq!(times.total_seconds(), 5.0);
+    }
+}
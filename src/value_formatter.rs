@@ -1,59 +1,89 @@
 use crate::connection::{Connection, IncomingMessage, OutgoingMessage, Throughput};
-use std::cell::RefCell;
+use crate::format;
+use std::cell::{Cell, RefCell};
 
+/// Formats values by asking the benchmark target, which knows the units of its measurements.
+///
+/// If the benchmark target has gone away (eg. because it crashed or the run was interrupted), the
+/// values are formatted as wall-clock times instead, which is what most benchmarks measure.
 pub struct ValueFormatter<'a> {
     connection: RefCell<&'a mut Connection>,
+    disconnected: Cell<bool>,
 }
 impl ValueFormatter<'_> {
-    pub fn new(conn: &mut Connection) -> ValueFormatter {
+    pub fn new(conn: &mut Connection) -> ValueFormatter<'_> {
         ValueFormatter {
             connection: RefCell::new(conn),
+            disconnected: Cell::new(false),
+        }
+    }
+
+    /// Send the given request to the benchmark target and return its response, or None if the
+    /// target can't be reached.
+    fn request(&self, message: &OutgoingMessage) -> Option<IncomingMessage> {
+        if self.disconnected.get() {
+            return None;
+        }
+        let mut connection = self.connection.borrow_mut();
+        let response = connection.send(message).and_then(|_| connection.recv());
+        match response {
+            Ok(Some(response)) => Some(response),
+            Ok(None) => {
+                debug!("Benchmark target closed the connection; formatting values locally");
+                self.disconnected.set(true);
+                None
+            }
+            Err(e) => {
+                debug!(
+                    "Failed to format values: {:?}; formatting values locally",
+                    e
+                );
+                self.disconnected.set(true);
+                None
+            }
         }
     }
 }
 impl ValueFormatter<'_> {
     pub fn format_value(&self, value: f64) -> String {
-        self.connection
-            .borrow_mut()
-            .send(&OutgoingMessage::FormatValue { value })
-            .unwrap();
-        match self.connection.borrow_mut().recv().unwrap().unwrap() {
-            IncomingMessage::FormattedValue { value } => value,
-            other => panic!("Unexpected message {:?}", other),
+        match self.request(&OutgoingMessage::FormatValue { value }) {
+            Some(IncomingMessage::FormattedValue { value }) => value,
+            Some(other) => panic!("Unexpected message {:?}", other),
+            None => format::time(value),
         }
     }
 
     pub fn format_throughput(&self, throughput: &Throughput, value: f64) -> String {
-        self.connection
-            .borrow_mut()
-            .send(&OutgoingMessage::FormatThroughput {
-                value,
-                throughput: throughput.clone(),
-            })
-            .unwrap();
-        match self.connection.borrow_mut().recv().unwrap().unwrap() {
-            IncomingMessage::FormattedValue { value } => value,
-            other => panic!("Unexpected message {:?}", other),
+        let message = OutgoingMessage::FormatThroughput {
+            value,
+            throughput: throughput.clone(),
+        };
+        match self.request(&message) {
+            Some(IncomingMessage::FormattedValue { value }) => value,
+            Some(other) => panic!("Unexpected message {:?}", other),
+            None => {
+                let mut rate = [value];
+                let unit = local_scale_throughputs(value, throughput, &mut rate);
+                format!("{:>6} {}", format::short(rate[0]), unit)
+            }
         }
     }
 
     pub fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> String {
-        self.connection
-            .borrow_mut()
-            .send(&OutgoingMessage::ScaleValues {
-                typical_value,
-                values,
-            })
-            .unwrap();
-        match self.connection.borrow_mut().recv().unwrap().unwrap() {
-            IncomingMessage::ScaledValues {
+        let message = OutgoingMessage::ScaleValues {
+            typical_value,
+            values,
+        };
+        match self.request(&message) {
+            Some(IncomingMessage::ScaledValues {
                 scaled_values,
                 unit,
-            } => {
+            }) => {
                 values.copy_from_slice(&scaled_values);
                 unit
             }
-            other => panic!("Unexpected message {:?}", other),
+            Some(other) => panic!("Unexpected message {:?}", other),
+            None => local_scale_values(typical_value, values),
         }
     }
 
@@ -65,48 +95,124 @@ impl ValueFormatter<'_> {
         throughput: &Throughput,
         values: &mut [f64],
     ) -> String {
-        self.connection
-            .borrow_mut()
-            .send(&OutgoingMessage::ScaleThroughputs {
-                typical_value,
-                values,
-                throughput: throughput.clone(),
-            })
-            .unwrap();
-        match self.connection.borrow_mut().recv().unwrap().unwrap() {
-            IncomingMessage::ScaledValues {
+        let message = OutgoingMessage::ScaleThroughputs {
+            typical_value,
+            values,
+            throughput: throughput.clone(),
+        };
+        match self.request(&message) {
+            Some(IncomingMessage::ScaledValues {
                 scaled_values,
                 unit,
-            } => {
+            }) => {
                 values.copy_from_slice(&scaled_values);
                 unit
             }
-            other => panic!("Unexpected message {:?}", other),
+            Some(other) => panic!("Unexpected message {:?}", other),
+            None => local_scale_throughputs(typical_value, throughput, values),
         }
     }
 
     pub fn scale_for_machines(&self, values: &mut [f64]) -> String {
-        self.connection
-            .borrow_mut()
-            .send(&OutgoingMessage::ScaleForMachines { values })
-            .unwrap();
-        match self.connection.borrow_mut().recv().unwrap().unwrap() {
-            IncomingMessage::ScaledValues {
+        match self.request(&OutgoingMessage::ScaleForMachines { values }) {
+            Some(IncomingMessage::ScaledValues {
                 scaled_values,
                 unit,
-            } => {
+            }) => {
                 values.copy_from_slice(&scaled_values);
                 unit
             }
-            other => panic!("Unexpected message {:?}", other),
+            Some(other) => panic!("Unexpected message {:?}", other),
+            // The values are already in nanoseconds.
+            None => "ns".to_owned(),
+        }
+    }
+
+    /// The unit that the measured values are recorded in, if the benchmark target doesn't need
+    /// to scale them to express them in a unit.
+    pub fn raw_unit(&self) -> Option<String> {
+        let mut values = [1.0];
+        let unit = self.scale_for_machines(&mut values);
+        if values[0] == 1.0 {
+            Some(unit)
+        } else {
+            None
         }
     }
 }
 impl Drop for ValueFormatter<'_> {
     fn drop(&mut self) {
-        let _ = self
-            .connection
-            .borrow_mut()
-            .send(&OutgoingMessage::Continue);
+        if !self.disconnected.get() {
+            let _ = self
+                .connection
+                .borrow_mut()
+                .send(&OutgoingMessage::Continue);
+        }
+    }
+}
+
+/// Scale the given times in nanoseconds to a unit which suits the typical value, as Criterion.rs
+/// does for wall-clock times.
+fn local_scale_values(typical_value: f64, values: &mut [f64]) -> String {
+    let (factor, unit) = if typical_value < 1.0 {
+        (1e3, "ps")
+    } else if typical_value < 1e3 {
+        (1.0, "ns")
+    } else if typical_value < 1e6 {
+        (1e-3, "µs")
+    } else if typical_value < 1e9 {
+        (1e-6, "ms")
+    } else {
+        (1e-9, "s")
+    };
+    for value in values {
+        *value *= factor;
+    }
+    unit.to_owned()
+}
+
+/// Convert the given times per iteration in nanoseconds to throughputs per second, scaled to a
+/// unit which suits the typical value.
+fn local_scale_throughputs(
+    typical_value: f64,
+    throughput: &Throughput,
+    values: &mut [f64],
+) -> String {
+    let (per_iteration, base, units) = match throughput {
+        Throughput::Bytes(bytes) => (*bytes, 1024.0, ["B/s", "KiB/s", "MiB/s", "GiB/s"]),
+        Throughput::BytesDecimal(bytes) => (*bytes, 1000.0, ["B/s", "KB/s", "MB/s", "GB/s"]),
+        Throughput::Elements(elements) => (
+            *elements,
+            1000.0,
+            ["elem/s", "Kelem/s", "Melem/s", "Gelem/s"],
+        ),
+    };
+    let per_second = |ns: f64| per_iteration as f64 * 1e9 / ns;
+    let typical = per_second(typical_value);
+    let mut exponent = 0;
+    while exponent < units.len() - 1 && typical >= f64::powi(base, exponent as i32 + 1) {
+        exponent += 1;
+    }
+    for value in values {
+        *value = per_second(*value) / f64::powi(base, exponent as i32);
+    }
+    units[exponent].to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_scaling() {
+        let mut values = [1500.0, 2500.0];
+        assert_eq!(local_scale_values(2000.0, &mut values), "µs");
+        assert_eq!(values, [1.5, 2.5]);
+
+        // 1024 bytes every microsecond is 1000000 KiB/s, which is about 976.6 MiB/s.
+        let mut values = [1000.0];
+        let unit = local_scale_throughputs(1000.0, &Throughput::Bytes(1024), &mut values);
+        assert_eq!(unit, "MiB/s");
+        assert!((values[0] - 976.5625).abs() < 1e-9);
     }
 }
@@ -0,0 +1,312 @@
+//! Module implementing the watchdog which kills benchmark targets that run for longer than the
+//! configured `--target-timeout` or `--benchmark-timeout`.
+//!
+//! The watchdog runs on its own thread while a benchmark target runs. When a deadline passes, it
+//! kills the target's process and shuts down its connection, which wakes up the main thread
+//! wherever it's blocked waiting for the target. The main thread then asks the watchdog whether
+//! that happened because of a timeout.
+//!
+//! The watchdog of the running target is also used to stop it when the user presses Ctrl-C; see
+//! the `interrupt` module.
+
+use crate::config::Timeouts;
+use lazy_static::lazy_static;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Which of the timeouts expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    Target(Duration),
+    Benchmark(Duration),
+}
+impl Timeout {
+    pub fn duration(&self) -> Duration {
+        match *self {
+            Timeout::Target(duration) | Timeout::Benchmark(duration) => duration,
+        }
+    }
+}
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timeout::Target(duration) => write!(
+                f,
+                "the benchmark target took longer than the target timeout ({:?})",
+                duration
+            ),
+            Timeout::Benchmark(duration) => write!(
+                f,
+                "the benchmark took longer than the benchmark timeout ({:?})",
+                duration
+            ),
+        }
+    }
+}
+
+type SharedState = Arc<(Mutex<State>, Condvar)>;
+
+lazy_static! {
+    // The state of the watchdog of the benchmark target which is currently running, if any.
+    static ref CURRENT: Mutex<Option<SharedState>> = Mutex::new(None);
+}
+
+#[derive(Debug)]
+struct State {
+    pid: u32,
+    target_deadline: Option<(Instant, Duration)>,
+    benchmark_deadline: Option<(Instant, Duration)>,
+    connection: Option<TcpStream>,
+    expired: Option<Timeout>,
+    interrupted: bool,
+    stopped: bool,
+}
+impl State {
+    /// Kill the target and shut down its connection, so that the main thread stops waiting for
+    /// it.
+    fn kill(&self) {
+        if self.stopped {
+            return;
+        }
+        if let Err(e) = kill(self.pid) {
+            error!(
+                "Failed to kill benchmark target process {}: {}",
+                self.pid, e
+            );
+        }
+        if let Some(connection) = &self.connection {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Returns the next deadline, and the timeout that expires then.
+    fn next_deadline(&self) -> Option<(Instant, Timeout)> {
+        let target = (self.target_deadline).map(|(at, d)| (at, Timeout::Target(d)));
+        let benchmark = (self.benchmark_deadline).map(|(at, d)| (at, Timeout::Benchmark(d)));
+        match (target, benchmark) {
+            (Some(target), Some(benchmark)) if benchmark.0 < target.0 => Some(benchmark),
+            (Some(target), _) => Some(target),
+            (None, benchmark) => benchmark,
+        }
+    }
+}
+
+/// Watches one benchmark target process. The watchdog stops when this is dropped, or earlier when
+/// the process exits (see `stopper`).
+#[derive(Debug)]
+pub struct Watchdog {
+    state: SharedState,
+    benchmark_timeout: Option<Duration>,
+}
+impl Watchdog {
+    /// Start watching the process with the given ID, which was just launched.
+    pub fn start(pid: u32, timeouts: &Timeouts) -> Watchdog {
+        let state = Arc::new((
+            Mutex::new(State {
+                pid,
+                target_deadline: timeouts.target.map(|d| (Instant::now() + d, d)),
+                benchmark_deadline: None,
+                connection: None,
+                expired: None,
+                interrupted: false,
+                stopped: false,
+            }),
+            Condvar::new(),
+        ));
+        if timeouts.target.is_some() || timeouts.benchmark.is_some() {
+            let thread_state = state.clone();
+            std::thread::spawn(move || watch(&thread_state));
+        }
+        *CURRENT.lock().unwrap() = Some(state.clone());
+        if crate::interrupt::interrupted() {
+            // The user pressed Ctrl-C just as the target was launched.
+            interrupt_current_target();
+        }
+        Watchdog {
+            state,
+            benchmark_timeout: timeouts.benchmark,
+        }
+    }
+
+    /// Shut down the given connection to the target as well, if a timeout expires.
+    pub fn watch_connection(&self, connection: &TcpStream) {
+        match connection.try_clone() {
+            Ok(connection) => self.update(|state| state.connection = Some(connection)),
+            Err(e) => debug!("Unable to clone connection for the watchdog: {}", e),
+        }
+    }
+
+    /// Start the benchmark timeout. This is called when the target starts a benchmark.
+    pub fn start_benchmark(&self) {
+        if let Some(timeout) = self.benchmark_timeout {
+            self.update(|state| {
+                state.benchmark_deadline = Some((Instant::now() + timeout, timeout))
+            });
+        }
+    }
+
+    /// Stop the benchmark timeout. This is called when the target has sent the measurements, so
+    /// that our own analysis doesn't count against the benchmark.
+    pub fn finish_benchmark(&self) {
+        self.update(|state| state.benchmark_deadline = None);
+    }
+
+    /// Returns a handle which stops the watchdog from another thread. The thread which waits for
+    /// the process must stop the watchdog after the process has exited but before reaping it, so
+    /// that the watchdog can't kill another process which reused the process ID.
+    pub fn stopper(&self) -> Stopper {
+        Stopper(self.state.clone())
+    }
+
+    /// Returns the timeout which expired, if any.
+    pub fn expired(&self) -> Option<Timeout> {
+        self.state.0.lock().unwrap().expired
+    }
+
+    /// Returns true if the target was stopped because the user interrupted the run.
+    pub fn interrupted(&self) -> bool {
+        self.state.0.lock().unwrap().interrupted
+    }
+
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        update(&self.state, f);
+    }
+}
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        *CURRENT.lock().unwrap() = None;
+        self.update(|state| state.stopped = true);
+    }
+}
+
+/// Handle which stops a watchdog; see `Watchdog::stopper`.
+#[derive(Debug)]
+pub struct Stopper(SharedState);
+impl Stopper {
+    pub fn stop(&self) {
+        update(&self.0, |state| state.stopped = true);
+    }
+}
+
+fn update<F: FnOnce(&mut State)>(state: &SharedState, f: F) {
+    let (lock, condvar) = &**state;
+    f(&mut lock.lock().unwrap());
+    condvar.notify_one();
+}
+
+/// Stop the benchmark target which is currently running, if any, because the user interrupted the
+/// run.
+pub fn interrupt_current_target() {
+    if let Some(state) = &*CURRENT.lock().unwrap() {
+        let mut state = state.0.lock().unwrap();
+        debug!(
+            "Killing benchmark target process {}: interrupted",
+            state.pid
+        );
+        state.interrupted = true;
+        state.kill();
+    }
+}
+
+fn watch(state: &(Mutex<State>, Condvar)) {
+    let (lock, condvar) = state;
+    let mut state = lock.lock().unwrap();
+    loop {
+        if state.stopped {
+            return;
+        }
+        match state.next_deadline() {
+            None => state = condvar.wait(state).unwrap(),
+            Some((deadline, timeout)) => {
+                let now = Instant::now();
+                if now < deadline {
+                    state = condvar.wait_timeout(state, deadline - now).unwrap().0;
+                    continue;
+                }
+
+                debug!(
+                    "Killing benchmark target process {}: {}",
+                    state.pid, timeout
+                );
+                state.expired = Some(timeout);
+                state.kill();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) -> std::io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn kill(pid: u32) -> std::io::Result<()> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+    use winapi::um::winnt::PROCESS_TERMINATE;
+
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let result = TerminateProcess(handle, 1);
+        CloseHandle(handle);
+        if result != 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn kill(_pid: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "killing processes is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(target: Option<Instant>, benchmark: Option<Instant>) -> State {
+        State {
+            pid: 0,
+            target_deadline: target.map(|at| (at, Duration::from_secs(10))),
+            benchmark_deadline: benchmark.map(|at| (at, Duration::from_secs(1))),
+            connection: None,
+            expired: None,
+            interrupted: false,
+            stopped: false,
+        }
+    }
+
+    #[test]
+    fn test_next_deadline() {
+        let now = Instant::now();
+        let (soon, later) = (now + Duration::from_secs(1), now + Duration::from_secs(10));
+        let target = |at| Some((at, Timeout::Target(Duration::from_secs(10))));
+        let benchmark = |at| Some((at, Timeout::Benchmark(Duration::from_secs(1))));
+
+        assert_eq!(None, state(None, None).next_deadline());
+        assert_eq!(target(later), state(Some(later), None).next_deadline());
+        assert_eq!(benchmark(soon), state(None, Some(soon)).next_deadline());
+        assert_eq!(
+            benchmark(soon),
+            state(Some(later), Some(soon)).next_deadline()
+        );
+        assert_eq!(target(soon), state(Some(soon), Some(later)).next_deadline());
+        // The target timeout wins a tie.
+        assert_eq!(target(soon), state(Some(soon), Some(soon)).next_deadline());
+    }
+}